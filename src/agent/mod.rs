@@ -0,0 +1,234 @@
+// Unlock agent: keeps the vault's data key in a background process, so
+// commands run one after another do not each prompt and re-derive. It never
+// holds the master password, which may be used elsewhere too.
+#[cfg(unix)]
+pub mod server;
+
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use zeroize::Zeroize;
+
+use crate::crypto::secret::{SecretBytes, SecretKey, SecretString};
+use crate::errors::EnvkeepError;
+use crate::vault;
+
+/// What the agent holds while the vault is unlocked.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Unlocked {
+    /// Data key of the vault, hex encoded
+    pub key: SecretString,
+}
+
+impl Unlocked {
+    pub fn new(key: &[u8; 32]) -> Self {
+        Unlocked {
+            key: SecretString::new(key.iter().map(|b| format!("{:02x}", b)).collect()),
+        }
+    }
+
+    fn key_bytes(&self) -> Option<SecretKey> {
+        if self.key.len() != 64 {
+            return None;
+        }
+        SecretKey::init(|key| {
+            for (i, byte) in key.iter_mut().enumerate() {
+                *byte = self
+                    .key
+                    .get(i * 2..i * 2 + 2)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or(())?;
+            }
+            Ok::<_, ()>(())
+        })
+        .ok()
+    }
+}
+
+/// One request per connection, as a line of JSON.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Request {
+    /// Hand out the data key (resets the idle timer)
+    Get,
+    Status,
+    /// Forget the data key and exit
+    Lock,
+}
+
+/// The agent's answer, as a line of JSON.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum Response {
+    Unlocked(Unlocked),
+    Status(Status),
+    Locked,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Status {
+    pub pid: u32,
+    /// Seconds until the agent locks if it is not used
+    pub idle_remaining: u64,
+    /// Seconds until the agent locks regardless
+    pub lifetime_remaining: u64,
+}
+
+/// Longest line the agent protocol uses; line buffers are this big from the start.
+const MAX_LINE: usize = 1024;
+
+/// Data key fetched from the agent by this process, asked for at most once.
+static FROM_AGENT: OnceLock<Option<SecretKey>> = OnceLock::new();
+
+/// Directory holding the socket; only the owner may enter it.
+pub fn socket_dir() -> PathBuf {
+    vault::data_dir().join("agent")
+}
+
+/// Per-user socket the agent listens on (`~/.envkeep/agent/agent.sock`).
+pub fn socket_path() -> PathBuf {
+    socket_dir().join("agent.sock")
+}
+
+/// Whether a running agent holds the unlocked vault. Fetches its data key
+/// for `key_for`.
+pub fn is_unlocked() -> bool {
+    FROM_AGENT
+        .get_or_init(|| match request(&Request::Get) {
+            Ok(Response::Unlocked(unlocked)) => unlocked.key_bytes(),
+            _ => None,
+        })
+        .is_some()
+}
+
+/// The data key held by the agent, for a command that unlocked through it.
+///
+/// The agent hands out no password, so those commands carry an empty one
+/// (see `unlock::password`); any other password gets None.
+pub fn key_for(password: &str) -> Option<SecretKey> {
+    if !password.is_empty() {
+        return None;
+    }
+    FROM_AGENT.get()?.clone()
+}
+
+/// Status of the running agent, or None if no agent is listening.
+pub fn status() -> Option<Status> {
+    match request(&Request::Status) {
+        Ok(Response::Status(status)) => Some(status),
+        _ => None,
+    }
+}
+
+/// Tell a running agent to forget the key and exit.
+///
+/// Returns false if no agent was running.
+pub fn lock() -> bool {
+    matches!(request(&Request::Lock), Ok(Response::Locked))
+}
+
+#[cfg(unix)]
+pub fn request(request: &Request) -> Result<Response, EnvkeepError> {
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    let agent_error = |e: std::io::Error| EnvkeepError::AgentError(e.to_string());
+
+    let mut stream = UnixStream::connect(socket_path()).map_err(agent_error)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5))).map_err(agent_error)?;
+
+    let line = secret_line(request)?;
+    stream.write_all(&line).map_err(agent_error)?;
+
+    let response = read_secret_line(&stream).map_err(agent_error)?;
+    serde_json::from_str(response.as_str()).map_err(|e| EnvkeepError::AgentError(e.to_string()))
+}
+
+#[cfg(not(unix))]
+pub fn request(_request: &Request) -> Result<Response, EnvkeepError> {
+    Err(EnvkeepError::AgentError("the agent needs Unix domain sockets".to_string()))
+}
+
+/// Serialize one message of the agent protocol, newline included, into a
+/// buffer that never reallocates and is wiped when dropped.
+pub fn secret_line(message: &impl Serialize) -> Result<SecretBytes, EnvkeepError> {
+    let mut line = Vec::with_capacity(MAX_LINE);
+    let result = serde_json::to_writer(&mut line, message);
+    line.push(b'\n');
+    let line = SecretBytes::new(line);
+    result.map_err(|e| EnvkeepError::AgentError(e.to_string()))?;
+    Ok(line)
+}
+
+/// Read one line of the agent protocol a byte at a time, so no reader buffer
+/// keeps a copy of the data key.
+pub fn read_secret_line(mut reader: impl Read) -> io::Result<SecretString> {
+    let mut line = Vec::with_capacity(MAX_LINE);
+    let mut byte = [0u8; 1];
+    let result = loop {
+        match reader.read(&mut byte) {
+            Ok(0) => break Ok(()),
+            Ok(_) if byte[0] == b'\n' => break Ok(()),
+            Ok(_) if line.len() == MAX_LINE => {
+                break Err(io::Error::new(io::ErrorKind::InvalidData, "agent message too long"));
+            }
+            Ok(_) => line.push(byte[0]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => break Err(e),
+        }
+    };
+    byte.zeroize();
+
+    if let Err(e) = result {
+        line.zeroize();
+        return Err(e);
+    }
+    String::from_utf8(line).map(SecretString::new).map_err(|e| {
+        e.into_bytes().zeroize();
+        io::Error::new(io::ErrorKind::InvalidData, "agent message is not UTF-8")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_round_trips_through_a_line() {
+        let key = [0xabu8; 32];
+        let line = secret_line(&Response::Unlocked(Unlocked::new(&key))).unwrap();
+        assert_eq!(line.last(), Some(&b'\n'));
+        assert_eq!(line.capacity(), MAX_LINE);
+
+        let read = read_secret_line(&line[..]).unwrap();
+        match serde_json::from_str(read.as_str()).unwrap() {
+            Response::Unlocked(unlocked) => assert_eq!(*unlocked.key_bytes().unwrap(), key),
+            _ => panic!("expected the key back"),
+        }
+    }
+
+    #[test]
+    fn reads_one_line_at_a_time() {
+        let mut input: &[u8] = b"{\"op\":\"get\"}\n{\"op\":\"lock\"}";
+        assert_eq!(read_secret_line(&mut input).unwrap().as_str(), "{\"op\":\"get\"}");
+        assert_eq!(read_secret_line(&mut input).unwrap().as_str(), "{\"op\":\"lock\"}");
+        assert_eq!(read_secret_line(&mut input).unwrap().as_str(), "");
+    }
+
+    #[test]
+    fn bad_lines_are_refused() {
+        let long = vec![b'a'; MAX_LINE + 1];
+        assert_eq!(read_secret_line(&long[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(read_secret_line(&b"\xff\xfe\n"[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn malformed_keys_are_ignored() {
+        for key in ["", "ab", &"g".repeat(64), &"ab".repeat(33)] {
+            let unlocked = Unlocked { key: SecretString::from(key) };
+            assert!(unlocked.key_bytes().is_none(), "{:?}", key);
+        }
+    }
+}
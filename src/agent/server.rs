@@ -0,0 +1,112 @@
+use std::fs;
+use std::io::{self, Write};
+use std::os::fd::AsFd;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{Request, Response, Status, Unlocked, read_secret_line, secret_line, socket_dir, socket_path};
+use crate::errors::EnvkeepError;
+
+/// How often the accept loop wakes up to check the timers.
+const TICK: Duration = Duration::from_millis(200);
+
+/// Run the agent until it is locked, idle for `idle`, or `max_lifetime` has passed.
+///
+/// The data key arrives as one line of JSON on stdin, so it never shows up in
+/// argv or the environment.
+pub fn serve(idle: Duration, max_lifetime: Duration) -> Result<(), EnvkeepError> {
+    let io_error = |e: io::Error| EnvkeepError::AgentError(e.to_string());
+
+    // Unbuffered, as Stdin's own buffer would keep a copy of the key
+    let stdin = fs::File::from(io::stdin().as_fd().try_clone_to_owned().map_err(io_error)?);
+    let line = read_secret_line(stdin).map_err(io_error)?;
+    let unlocked: Unlocked =
+        serde_json::from_str(line.as_str()).map_err(|e| EnvkeepError::AgentError(e.to_string()))?;
+    drop(line);
+
+    let listener = bind()?;
+    listener.set_nonblocking(true).map_err(io_error)?;
+
+    let started = Instant::now();
+    let mut last_used = Instant::now();
+
+    loop {
+        if last_used.elapsed() >= idle || started.elapsed() >= max_lifetime {
+            break;
+        }
+
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let request = match read_request(&stream) {
+                    Some(request) => request,
+                    None => continue,
+                };
+                let response = match request {
+                    Request::Get => {
+                        last_used = Instant::now();
+                        Response::Unlocked(unlocked.clone())
+                    }
+                    Request::Status => Response::Status(Status {
+                        pid: std::process::id(),
+                        idle_remaining: idle.saturating_sub(last_used.elapsed()).as_secs(),
+                        lifetime_remaining: max_lifetime.saturating_sub(started.elapsed()).as_secs(),
+                    }),
+                    Request::Lock => Response::Locked,
+                };
+
+                let lock = matches!(response, Response::Locked);
+                // A client that went away is not the agent's problem
+                let _ = write_response(stream, &response);
+                if lock {
+                    break;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(TICK),
+            Err(e) => return Err(io_error(e)),
+        }
+    }
+
+    let _ = fs::remove_file(socket_path());
+    Ok(())
+}
+
+/// Bind the socket inside a 0700 directory and make the socket itself 0600.
+fn bind() -> Result<UnixListener, EnvkeepError> {
+    let dir = socket_dir();
+    let path = socket_path();
+    let write_error = |e: io::Error| EnvkeepError::FileWriteError(path.display().to_string(), e);
+
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)
+        .map_err(write_error)?;
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)).map_err(write_error)?;
+
+    if path.exists() {
+        if UnixStream::connect(&path).is_ok() {
+            return Err(EnvkeepError::AgentError("an agent is already running".to_string()));
+        }
+        // Left behind by an agent that was killed
+        fs::remove_file(&path).map_err(write_error)?;
+    }
+
+    let listener = UnixListener::bind(&path).map_err(write_error)?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).map_err(write_error)?;
+    Ok(listener)
+}
+
+fn read_request(stream: &UnixStream) -> Option<Request> {
+    stream.set_nonblocking(false).ok()?;
+    stream.set_read_timeout(Some(Duration::from_secs(2))).ok()?;
+
+    let line = read_secret_line(stream).ok()?;
+    serde_json::from_str(line.as_str()).ok()
+}
+
+fn write_response(mut stream: UnixStream, response: &Response) -> Result<(), EnvkeepError> {
+    let line = secret_line(response)?;
+    stream.write_all(&line).map_err(|e| EnvkeepError::AgentError(e.to_string()))
+}
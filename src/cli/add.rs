@@ -3,17 +3,18 @@ use colored::Colorize;
 use std::env;
 use std::path::PathBuf;
 
-use crate::crypto;
+use crate::crypto::ValueBinding;
 use crate::env_parser;
+use crate::errors::EnvkeepError;
+use crate::unlock;
 use crate::vault;
-use crate::vault::project::Project;
+use crate::vault::audit::Access;
+use crate::vault::project::{DEFAULT_ENVIRONMENT, Project};
 
-pub fn handle_add(name: &str) -> Result<()> {
+pub fn handle_add(name: &str, env: Option<&str>) -> Result<()> {
     // Open the vault
-    let password = crypto::prompt_existing_password()?;
-    let conn = vault::open_vault(&password)
+    let (conn, keyring) = unlock::open_vault_with_keyring()
         .context("Failed to open vault")?;
-    let enc_key = vault::get_encryption_key(&conn, &password)?;
 
     // Find .env in current directory
     let cwd = env::current_dir().context("Could not determine current directory")?;
@@ -32,27 +33,74 @@ pub fn handle_add(name: &str) -> Result<()> {
         return Ok(());
     }
 
-    // Create the project
     let directory = cwd.display().to_string();
-    let project = Project::new(name, Some(&directory));
-    vault::project::create_project(&conn, &project)
-        .context("Failed to create project")?;
+
+    // An existing project only accepts a new, still empty environment
+    let (project, environment, is_new) = match vault::project::get_project(&conn, name) {
+        Ok(project) => {
+            let environment = env.ok_or_else(|| EnvkeepError::ProjectAlreadyExists(name.to_string()))?;
+            vault::project::validate_name(environment)?;
+
+            if !vault::variable::get_variables(&conn, &project.id, environment)?.is_empty() {
+                return Err(EnvkeepError::EnvironmentAlreadyExists(
+                    name.to_string(),
+                    environment.to_string(),
+                )
+                .into());
+            }
+
+            (project, environment.to_string(), false)
+        }
+        Err(EnvkeepError::ProjectNotFound(_)) => {
+            let environment = env.unwrap_or(DEFAULT_ENVIRONMENT);
+            vault::project::validate_name(name)?;
+            vault::project::validate_name(environment)?;
+
+            // Create the project
+            let project = Project::new(name, Some(&directory), environment);
+            vault::project::create_project(&conn, &project)
+                .context("Failed to create project")?;
+
+            (project, environment.to_string(), true)
+        }
+        Err(e) => return Err(e.into()),
+    };
 
     // Encrypt and store each variable
     let mut count = 0;
     for (key, value) in &vars {
-        let encrypted = crypto::encrypt_value(&enc_key, value)
+        let binding = ValueBinding::Variable {
+            project_id: &project.id,
+            environment: &environment,
+            key,
+        };
+        let encrypted = keyring.encrypt(&conn, value, &binding)
             .context(format!("Failed to encrypt variable: {}", key))?;
-        vault::variable::upsert_variable(&conn, &project.id, key, &encrypted)?;
+        vault::variable::upsert_variable(&conn, &project.id, &environment, key, &encrypted, "add")?;
         count += 1;
     }
 
-    println!(
-        "{} Added project {} with {} variables",
-        "Done.".green().bold(),
-        name.cyan().bold(),
-        count
-    );
+    let keys: Vec<String> = vars.keys().cloned().collect();
+    let project_ref = format!("{}:{}", name, environment);
+    vault::audit::record(&conn, "add", Access::Modify, Some(&project_ref), &keys)?;
+
+    if is_new {
+        println!(
+            "{} Added project {} ({}) with {} variables",
+            "Done.".green().bold(),
+            name.cyan().bold(),
+            environment,
+            count
+        );
+    } else {
+        println!(
+            "{} Added environment {} to {} with {} variables",
+            "Done.".green().bold(),
+            environment.cyan().bold(),
+            name.cyan().bold(),
+            count
+        );
+    }
     println!("  Directory: {}", directory);
 
     Ok(())
@@ -40,5 +40,5 @@ pub fn detect_project_name() -> Result<String> {
 pub fn handle_add_auto() -> Result<()> {
     let name = detect_project_name()?;
     println!("Detected project name: {}", name);
-    super::add::handle_add(&name)
+    super::add::handle_add(&name, None)
 }
\ No newline at end of file
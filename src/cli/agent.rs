@@ -0,0 +1,137 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use std::time::Duration;
+
+use crate::agent;
+use crate::cli::AgentAction;
+use crate::errors::EnvkeepError;
+use crate::unlock;
+use crate::vault;
+use crate::vault::audit::Access;
+
+pub fn handle_agent(action: AgentAction) -> Result<()> {
+    match action {
+        AgentAction::Start { idle, max } => handle_start(idle, max),
+        AgentAction::Stop => handle_stop(),
+        AgentAction::Status => handle_status(),
+        AgentAction::Serve { idle, max } => handle_serve(idle, max),
+    }
+}
+
+fn handle_start(idle: u64, max: u64) -> Result<()> {
+    if let Some(status) = agent::status() {
+        println!(
+            "Agent already running (pid {}). Run {} first to restart it.",
+            status.pid,
+            "envkeep lock".cyan()
+        );
+        return Ok(());
+    }
+
+    // Never from the agent itself
+    let password = unlock::password_without_agent()?;
+    let conn = vault::open_vault(&password)
+        .context("Failed to open vault")?;
+    vault::get_encryption_key(&conn, &password)?;
+    vault::audit::record(&conn, "agent start", Access::Read, None, &[])?;
+    drop(conn);
+
+    let data_key = vault::data_key(&password)?;
+    spawn(&agent::Unlocked::new(&data_key), idle, max)?;
+
+    println!(
+        "{} Vault unlocked. Locks after {} min idle, or {} min at most.",
+        "Done.".green().bold(),
+        idle,
+        max
+    );
+    println!("  Run {} to lock it sooner.", "envkeep lock".cyan());
+
+    Ok(())
+}
+
+/// Start `envkeep agent serve` in the background and wait until it listens.
+#[cfg(unix)]
+fn spawn(unlocked: &agent::Unlocked, idle: u64, max: u64) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::process::CommandExt;
+    use std::process::{Command, Stdio};
+
+    let exe = std::env::current_exe().context("Could not find the envkeep executable")?;
+    let mut child = Command::new(exe)
+        .args(["agent", "serve", "--idle", &idle.to_string(), "--max", &max.to_string()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        // Own process group, so Ctrl-C in this terminal does not reach it
+        .process_group(0)
+        .spawn()
+        .context("Failed to start the agent")?;
+
+    // The credentials go over a pipe, never argv or the environment
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(&agent::secret_line(unlocked)?)?;
+    }
+
+    for _ in 0..50 {
+        if agent::status().is_some() {
+            return Ok(());
+        }
+        if let Some(exit) = child.try_wait()? {
+            return Err(EnvkeepError::AgentError(format!("agent exited early ({})", exit)).into());
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    Err(EnvkeepError::AgentError("agent did not start listening".to_string()).into())
+}
+
+#[cfg(not(unix))]
+fn spawn(_unlocked: &agent::Unlocked, _idle: u64, _max: u64) -> Result<()> {
+    Err(EnvkeepError::AgentError("the agent needs Unix domain sockets".to_string()).into())
+}
+
+#[cfg(unix)]
+fn handle_serve(idle: u64, max: u64) -> Result<()> {
+    agent::server::serve(Duration::from_secs(idle * 60), Duration::from_secs(max * 60))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn handle_serve(_idle: u64, _max: u64) -> Result<()> {
+    Err(EnvkeepError::AgentError("the agent needs Unix domain sockets".to_string()).into())
+}
+
+fn handle_stop() -> Result<()> {
+    if agent::lock() {
+        println!("{} Agent stopped; the vault is locked.", "Done.".green().bold());
+    } else {
+        println!("No agent running.");
+    }
+
+    Ok(())
+}
+
+fn handle_status() -> Result<()> {
+    match agent::status() {
+        Some(status) => {
+            println!("Agent running (pid {}), vault {}.", status.pid, "unlocked".yellow());
+            println!("  Locks in {} if unused", format_secs(status.idle_remaining));
+            println!("  Locks in {} at the latest", format_secs(status.lifetime_remaining));
+            println!("  Socket: {}", agent::socket_path().display().to_string().dimmed());
+        }
+        None => println!("No agent running; commands will ask for the master password."),
+    }
+
+    Ok(())
+}
+
+fn format_secs(secs: u64) -> String {
+    if secs >= 3600 {
+        format!("{}h {}m", secs / 3600, secs % 3600 / 60)
+    } else if secs >= 60 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
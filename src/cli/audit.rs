@@ -0,0 +1,84 @@
+use anyhow::{Context, Result};
+use chrono::DateTime;
+use colored::Colorize;
+use comfy_table::{Table, ContentArrangement, presets::UTF8_FULL_CONDENSED};
+
+use crate::cli::AuditAction;
+use crate::errors::EnvkeepError;
+use crate::unlock;
+use crate::vault;
+use crate::vault::audit::ChainStatus;
+
+pub fn handle_audit(
+    action: Option<AuditAction>,
+    project: Option<&str>,
+    since: Option<&str>,
+) -> Result<()> {
+    match action {
+        Some(AuditAction::Verify) => handle_verify(),
+        None => handle_list(project, since),
+    }
+}
+
+fn handle_list(project: Option<&str>, since: Option<&str>) -> Result<()> {
+    let since = since
+        .map(|s| {
+            vault::history::parse_timestamp(s)
+                .ok_or_else(|| EnvkeepError::InvalidRollbackPoint(s.to_string()))
+        })
+        .transpose()?;
+
+    let conn = unlock::open_vault()
+        .context("Failed to open vault")?;
+
+    let entries = vault::audit::get_entries(&conn, project, since)?;
+
+    if entries.is_empty() {
+        println!("No audit entries.");
+        return Ok(());
+    }
+
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL_CONDENSED)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["#", "Time (UTC)", "Command", "Action", "Project", "Keys"]);
+
+    for entry in &entries {
+        let time = DateTime::parse_from_rfc3339(&entry.timestamp)
+            .map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|_| entry.timestamp.clone());
+
+        table.add_row(vec![
+            entry.seq.to_string(),
+            time,
+            entry.command.clone(),
+            entry.action.clone(),
+            entry.project.clone().unwrap_or_else(|| "-".to_string()),
+            entry.keys.replace(',', ", "),
+        ]);
+    }
+
+    println!("{table}");
+
+    Ok(())
+}
+
+fn handle_verify() -> Result<()> {
+    let conn = unlock::open_vault()
+        .context("Failed to open vault")?;
+
+    match vault::audit::verify_chain(&conn)? {
+        ChainStatus::Intact(count) => {
+            println!(
+                "{} Audit log intact ({} entries).",
+                "Done.".green().bold(),
+                count
+            );
+            Ok(())
+        }
+        ChainStatus::Broken(seq, reason) => {
+            Err(EnvkeepError::AuditChainBroken(seq, reason).into())
+        }
+    }
+}
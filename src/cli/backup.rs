@@ -2,6 +2,7 @@ use anyhow::{Context, Result};
 use colored::Colorize;
 use chrono::Utc;
 use std::fs;
+use std::path::Path;
 
 use crate::vault;
 
@@ -23,6 +24,14 @@ pub fn handle_backup() -> Result<()> {
     fs::copy(&vault_path, &backup_name)
         .context("Failed to create backup")?;
 
+    // The key file holds the wrapped data key; the database cannot be opened without it
+    let keyfile = vault::keyfile_path();
+    let keys_name = vault::keyfile::path_for(Path::new(&backup_name));
+    if keyfile.exists() {
+        fs::copy(&keyfile, &keys_name)
+            .context("Failed to back up the key file")?;
+    }
+
     let file_size = fs::metadata(&backup_name)
         .map(|m| m.len())
         .unwrap_or(0);
@@ -33,7 +42,12 @@ pub fn handle_backup() -> Result<()> {
         backup_name.bold()
     );
     println!("  Size: {} KB", file_size / 1024);
-    println!("  {}", "Keep this file safe. It is encrypted with your master password.".dimmed());
+    if keyfile.exists() {
+        println!("  Key file: {}", keys_name.display().to_string().bold());
+        println!("  {}", "Keep both files together. They are encrypted with your master password.".dimmed());
+    } else {
+        println!("  {}", "Keep this file safe. It is encrypted with your master password.".dimmed());
+    }
 
     Ok(())
 }
\ No newline at end of file
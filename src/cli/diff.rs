@@ -2,20 +2,30 @@ use anyhow::{Context, Result};
 use colored::Colorize;
 use std::collections::BTreeSet;
 
-use crate::crypto;
+use crate::unlock;
 use crate::vault;
+use crate::vault::audit::Access;
 
 pub fn handle_diff(project1: &str, project2: &str) -> Result<()> {
-    let password = crypto::prompt_existing_password()?;
-    let conn = vault::open_vault(&password)
+    let (conn, keyring) = unlock::open_vault_with_keyring()
         .context("Failed to open vault")?;
-    let enc_key = vault::get_encryption_key(&conn, &password)?;
 
-    let proj1 = vault::project::get_project(&conn, project1)?;
-    let proj2 = vault::project::get_project(&conn, project2)?;
+    // Each side is `project[:environment]`
+    let (name1, env1) = vault::project::split_ref(project1);
+    let (name2, env2) = vault::project::split_ref(project2);
 
-    let vars1 = vault::variable::get_variables(&conn, &proj1.id)?;
-    let vars2 = vault::variable::get_variables(&conn, &proj2.id)?;
+    let proj1 = vault::project::get_project(&conn, name1)?;
+    let proj2 = vault::project::get_project(&conn, name2)?;
+
+    let env1 = proj1.environment_or_default(env1);
+    let env2 = proj2.environment_or_default(env2);
+
+    let vars1 = vault::variable::get_variables(&conn, &proj1.id, &env1)?;
+    let vars2 = vault::variable::get_variables(&conn, &proj2.id, &env2)?;
+
+    // Label both sides with their resolved environment
+    let project1 = &format!("{}:{}", name1, env1);
+    let project2 = &format!("{}:{}", name2, env2);
 
     // Collect keys from both projects
     let keys1: BTreeSet<String> = vars1.iter().map(|v| v.key.clone()).collect();
@@ -46,8 +56,8 @@ pub fn handle_diff(project1: &str, project2: &str) -> Result<()> {
                 let v1 = vars1.iter().find(|v| &v.key == *key).unwrap();
                 let v2 = vars2.iter().find(|v| &v.key == *key).unwrap();
 
-                let dec1 = crypto::decrypt_value(&enc_key, &v1.encrypted_value)?;
-                let dec2 = crypto::decrypt_value(&enc_key, &v2.encrypted_value)?;
+                let dec1 = keyring.decrypt(&conn, &v1.encrypted_value, &v1.binding())?;
+                let dec2 = keyring.decrypt(&conn, &v2.encrypted_value, &v2.binding())?;
 
                 if dec1 == dec2 {
                     same.push(key.to_string());
@@ -59,6 +69,11 @@ pub fn handle_diff(project1: &str, project2: &str) -> Result<()> {
         }
     }
 
+    // Only keys present on both sides were decrypted
+    let compared: Vec<String> = same.iter().chain(different.iter()).cloned().collect();
+    vault::audit::record(&conn, "diff", Access::Decrypt, Some(project1), &compared)?;
+    vault::audit::record(&conn, "diff", Access::Decrypt, Some(project2), &compared)?;
+
     // Print results
     if !only_in_1.is_empty() {
         println!("  Only in {}:", project1.cyan());
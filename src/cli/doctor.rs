@@ -0,0 +1,104 @@
+use anyhow::Result;
+use colored::Colorize;
+
+use crate::errors::EnvkeepError;
+use crate::unlock;
+use crate::vault;
+use crate::vault::keyfile::KeyFile;
+use crate::vault::{keyring, migrations};
+
+/// Run the unlock steps one at a time and stop at the first that fails, so the
+/// error (and exit code) says which part of the vault is the problem.
+pub fn handle_doctor() -> Result<()> {
+    let path = vault::vault_path();
+    let keys = vault::keyfile_path();
+
+    println!("Checking {}", path.display().to_string().cyan());
+    println!();
+
+    check("vault file", || {
+        if !path.exists() {
+            return Err(EnvkeepError::VaultNotFound);
+        }
+        vault::check_file(&path)?;
+        Ok(((), format!("{} bytes", std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0))))
+    })?;
+
+    let legacy = !keys.exists();
+    check("key file", || {
+        if legacy {
+            return Ok(((), "none (vault from before key files, keyed with the password)".to_string()));
+        }
+        let keyfile = KeyFile::load(&keys)?;
+        let recovery = if keyfile.has_recovery() { "set" } else { "not set" };
+        Ok(((), format!("{} slot(s), recovery key {}", keyfile.slots.len(), recovery)))
+    })?;
+
+    // The agent holds only the data key; the password itself is checked
+    let password = unlock::password_without_agent()?;
+    if !legacy {
+        check("master password", || {
+            vault::data_key(&password)?;
+            Ok(((), "unwraps the data key".to_string()))
+        })?;
+    }
+
+    let conn = check("decryption", || {
+        let conn = vault::open_database(&password)?;
+        Ok((conn, "ok".to_string()))
+    })?;
+
+    check("metadata", || {
+        vault::check_metadata(&conn, legacy)?;
+        Ok(((), "ok".to_string()))
+    })?;
+
+    let up_to_date = check("schema version", || {
+        let pending = migrations::pending(&conn)?;
+        let version = migrations::schema_version(&conn);
+        let detail = if pending.is_empty() {
+            format!("{} (up to date)", version)
+        } else {
+            format!("{} ({} migration(s) pending, run 'envkeep migrate')", version, pending.len())
+        };
+        Ok((pending.is_empty(), detail))
+    })?;
+
+    let key = check("verification key", || {
+        let key = vault::get_encryption_key(&conn, &password)?;
+        Ok((key, "matches".to_string()))
+    })?;
+
+    check("page integrity", || {
+        vault::check_integrity(&conn)?;
+        Ok(((), "ok".to_string()))
+    })?;
+
+    // Vaults that still need migrating may not have a keyring yet
+    if up_to_date {
+        check("keyring", || {
+            let count = keyring::verify_all(&conn, &key)?;
+            Ok(((), format!("{} key(s) unwrap", count)))
+        })?;
+    }
+
+    println!();
+    println!("{} No problems found.", "Done.".green().bold());
+
+    Ok(())
+}
+
+/// Print the outcome of one step, which returns a value to keep and a detail line.
+fn check<T>(name: &str, step: impl FnOnce() -> Result<(T, String), EnvkeepError>) -> Result<T, EnvkeepError> {
+    match step() {
+        Ok((value, detail)) => {
+            println!("  [{}] {}: {}", " OK ".green().bold(), name, detail.dimmed());
+            Ok(value)
+        }
+        Err(e) => {
+            println!("  [{}] {}: {}", "FAIL".red().bold(), name, e);
+            println!();
+            Err(e)
+        }
+    }
+}
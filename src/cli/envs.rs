@@ -0,0 +1,63 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use comfy_table::{Table, presets::UTF8_FULL_CONDENSED};
+
+use crate::errors::EnvkeepError;
+use crate::unlock;
+use crate::vault;
+use crate::vault::audit::Access;
+
+pub fn handle_envs(project_name: &str, set_default: Option<&str>) -> Result<()> {
+    let conn = unlock::open_vault()
+        .context("Failed to open vault")?;
+
+    let project = vault::project::get_project(&conn, project_name)?;
+    let environments = vault::project::list_environments(&conn, &project)?;
+
+    if let Some(environment) = set_default {
+        if !environments.iter().any(|(env, _)| env == environment) {
+            return Err(EnvkeepError::EnvironmentNotFound(
+                project_name.to_string(),
+                environment.to_string(),
+            )
+            .into());
+        }
+
+        vault::project::set_default_environment(&conn, project_name, environment)?;
+        vault::audit::record(&conn, "envs", Access::Modify, Some(project_name), &[])?;
+
+        println!(
+            "{} Default environment of {} is now {}",
+            "Done.".green().bold(),
+            project_name.cyan(),
+            environment.cyan().bold()
+        );
+        return Ok(());
+    }
+
+    vault::audit::record(&conn, "envs", Access::Read, Some(project_name), &[])?;
+
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL_CONDENSED)
+        .set_header(vec!["Environment", "Vars", "Default"]);
+
+    for (environment, count) in &environments {
+        let is_default = if environment == &project.default_environment {
+            "*"
+        } else {
+            ""
+        };
+        table.add_row(vec![environment.clone(), count.to_string(), is_default.to_string()]);
+    }
+
+    println!(
+        "Environments of {} ({}):",
+        project_name.cyan().bold(),
+        environments.len()
+    );
+    println!();
+    println!("{table}");
+
+    Ok(())
+}
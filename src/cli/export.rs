@@ -5,26 +5,43 @@ use std::collections::BTreeMap;
 use std::fs;
 
 use crate::crypto;
+use crate::crypto::secret::SecretString;
+use crate::crypto::{recipients, signing};
+use crate::unlock;
 use crate::vault;
+use crate::vault::audit::Access;
 
 pub const MAGIC: &[u8] = b"DKVAULT\0";
+/// Files encrypted to public keys (`export --to`) instead of a passphrase.
+pub const RECIPIENTS_MAGIC: &[u8] = b"DKVAULT\x01";
+/// Signed files: this, the signer's public key and signature, then the file
+/// it signs (which starts with one of the magics above).
+pub const SIGNED_MAGIC: &[u8] = b"DKVAULT\x02";
 
 #[derive(Serialize, Deserialize)]
 pub struct ExportData {
     pub version: u32,
     pub project_name: String,
+    /// Environment the variables came from (older exports have none: `dev`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<String>,
     pub created_at: String,
-    pub variables: BTreeMap<String, String>,
+    pub variables: BTreeMap<String, SecretString>,
 }
 
 
-pub fn handle_export(project_name: &str) -> Result<()> {
-    let password = crypto::prompt_existing_password()?;
-    let conn = vault::open_vault(&password)?;
-    let enc_key = vault::get_encryption_key(&conn, &password)?;
+pub fn handle_export(project_name: &str, to: &[String]) -> Result<()> {
+    let recipients = to
+        .iter()
+        .map(|key| recipients::parse_public_key(key))
+        .collect::<Result<Vec<_>, _>>()?;
 
+    let (conn, keyring) = unlock::open_vault_with_keyring()?;
+
+    let (project_name, env) = vault::project::split_ref(project_name);
     let project = vault::project::get_project(&conn, project_name)?;
-    let variables = vault::variable::get_variables(&conn, &project.id)?;
+    let environment = project.environment_or_default(env);
+    let variables = vault::variable::get_variables(&conn, &project.id, &environment)?;
 
     if variables.is_empty() {
         println!("Project {} has no variables.", project_name.cyan());
@@ -34,7 +51,7 @@ pub fn handle_export(project_name: &str) -> Result<()> {
     // Decrypt all variables to plaintext
     let mut vars = BTreeMap::new();
     for var in &variables {
-        let decrypted = crypto::decrypt_value(&enc_key, &var.encrypted_value)?;
+        let decrypted = keyring.decrypt(&conn, &var.encrypted_value, &var.binding())?;
         vars.insert(var.key.clone(), decrypted);
     }
 
@@ -42,34 +59,52 @@ pub fn handle_export(project_name: &str) -> Result<()> {
     let export_data = ExportData {
         version: 1,
         project_name: project_name.to_string(),
+        environment: Some(environment.clone()),
         created_at: chrono::Utc::now().to_rfc3339(),
         variables: vars,
     };
 
-    let json = serde_json::to_string_pretty(&export_data)?;
-
-    // Ask for export passphrase
-    println!("Set a passphrase for the export file.");
-    println!("{}", "Share this passphrase with the recipient separately.".dimmed());
-    let export_password = crypto::prompt_new_password()
-        .context("Failed to read export passphrase")?;
+    let json = SecretString::new(serde_json::to_string_pretty(&export_data)?);
 
-    // Derive key from export passphrase
-    let salt = crypto::key::generate_salt()?;
-    let export_key = crypto::key::derive_key(&export_password, &salt);
+    let mut output = Vec::new();
+    if recipients.is_empty() {
+        // Ask for export passphrase
+        println!("Set a passphrase for the export file.");
+        println!("{}", "Share this passphrase with the recipient separately.".dimmed());
+        let export_password = crypto::prompt_new_password()
+            .context("Failed to read export passphrase")?;
+
+        // Derive key from export passphrase
+        let salt = crypto::key::generate_salt()?;
+        let export_key = crypto::key::derive_key(&export_password, &salt);
+
+        // Encrypt the JSON
+        let encrypted = crypto::encrypt(&export_key, json.as_bytes())?;
+
+        // Build the output file: magic + salt + encrypted
+        output.extend_from_slice(MAGIC);
+        output.extend_from_slice(&salt);
+        output.extend_from_slice(&encrypted);
+    } else {
+        // Only the holders of the recipients' vaults can open it, no passphrase needed
+        output.extend_from_slice(RECIPIENTS_MAGIC);
+        output.extend_from_slice(&recipients::seal(json.as_bytes(), &recipients)?);
+    }
 
-    // Encrypt the JSON
-    let encrypted = crypto::encrypt(&export_key, json.as_bytes())?;
+    // Sign the whole file so the importer can tell who wrote it
+    let signing_key = keyring.signing_key(&conn)?;
+    let mut signed = SIGNED_MAGIC.to_vec();
+    signed.extend_from_slice(&signing::public_key(&signing_key));
+    signed.extend_from_slice(&signing::sign(&signing_key, &output));
+    signed.extend_from_slice(&output);
 
-    // Build the output file: magic + salt + encrypted
-    let mut output = Vec::new();
-    output.extend_from_slice(MAGIC);
-    output.extend_from_slice(&salt);
-    output.extend_from_slice(&encrypted);
+    let keys: Vec<String> = export_data.variables.keys().cloned().collect();
+    let project_ref = format!("{}:{}", project_name, environment);
+    vault::audit::record(&conn, "export", Access::Export, Some(&project_ref), &keys)?;
 
     // Write to file
     let filename = format!("{}.envvault", project_name);
-    fs::write(&filename, &output)
+    fs::write(&filename, &signed)
         .map_err(|e| crate::errors::EnvkeepError::FileWriteError(filename.clone(), e))?;
 
     println!(
@@ -79,6 +114,9 @@ pub fn handle_export(project_name: &str) -> Result<()> {
         export_data.variables.len(),
         filename.bold()
     );
+    if !recipients.is_empty() {
+        println!("  Encrypted to {} recipient(s); they import it with their own vault.", recipients.len());
+    }
 
     Ok(())
 }
\ No newline at end of file
@@ -5,6 +5,7 @@ use std::fs;
 use std::path::Path;
 
 use crate::crypto;
+use crate::crypto::secret::SecretString;
 use crate::env_parser;
 use crate::vault;
 
@@ -98,10 +99,10 @@ pub fn handle_generate(template_path: &str) -> Result<()> {
             filled_vars.insert(key.clone(), vault_value.clone());
             from_vault += 1;
         } else if !default_value.is_empty() {
-            filled_vars.insert(key.clone(), default_value.clone());
+            filled_vars.insert(key.clone(), SecretString::from(default_value.as_str()));
             from_default += 1;
         } else {
-            filled_vars.insert(key.clone(), String::new());
+            filled_vars.insert(key.clone(), SecretString::from(""));
             empty += 1;
         }
     }
@@ -564,7 +565,7 @@ fn get_builtin_template(name: &str) -> Option<String> {
 }
 
 /// Try to load variables from the most recently used project in the vault.
-fn try_load_vault_vars() -> Option<BTreeMap<String, String>> {
+fn try_load_vault_vars() -> Option<BTreeMap<String, SecretString>> {
     // This is a best-effort operation -- if vault is locked or empty, return None.
     // For now, return None. A more complete implementation would prompt for
     // a password and load the most recent project.
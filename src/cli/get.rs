@@ -0,0 +1,31 @@
+use anyhow::{Context, Result};
+
+use crate::unlock;
+use crate::vault;
+use crate::vault::audit::Access;
+
+/// Print only the raw value, so the output can be captured by scripts.
+pub fn handle_get(project_name: &str, key: &str, env: Option<&str>) -> Result<()> {
+    let (conn, keyring) = unlock::open_vault_with_keyring()
+        .context("Failed to open vault")?;
+
+    let project = vault::project::get_project(&conn, project_name)?;
+    let environment = project.environment_or_default(env);
+
+    let variable = vault::variable::get_variable(&conn, &project.id, &environment, key)
+        .map_err(|_| {
+            crate::errors::EnvkeepError::VariableNotFound(
+                format!("{}:{}", project_name, environment),
+                key.to_string(),
+            )
+        })?;
+    let value = keyring.decrypt(&conn, &variable.encrypted_value, &variable.binding())
+        .context(format!("Failed to decrypt variable: {}", key))?;
+
+    let project_ref = format!("{}:{}", project_name, environment);
+    vault::audit::record(&conn, "get", Access::Decrypt, Some(&project_ref), &[key.to_string()])?;
+
+    println!("{}", value.as_str());
+
+    Ok(())
+}
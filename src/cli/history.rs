@@ -0,0 +1,67 @@
+use anyhow::{Context, Result};
+use chrono::DateTime;
+use colored::Colorize;
+use comfy_table::{Table, presets::UTF8_FULL_CONDENSED};
+
+use crate::unlock;
+use crate::vault;
+use crate::vault::audit::Access;
+
+pub fn handle_history(project_name: &str, key: Option<&str>, env: Option<&str>) -> Result<()> {
+    let conn = unlock::open_vault()
+        .context("Failed to open vault")?;
+
+    let project = vault::project::get_project(&conn, project_name)?;
+    let environment = project.environment_or_default(env);
+    let entries = vault::history::get_history(&conn, &project.id, &environment, key)?;
+
+    let project_ref = format!("{}:{}", project_name, environment);
+    let keys: Vec<String> = key.map(|k| k.to_string()).into_iter().collect();
+    vault::audit::record(&conn, "history", Access::Read, Some(&project_ref), &keys)?;
+
+    if entries.is_empty() {
+        println!(
+            "No history for {}:{}{}.",
+            project_name.cyan(),
+            environment,
+            key.map(|k| format!(" {}", k)).unwrap_or_default()
+        );
+        return Ok(());
+    }
+
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL_CONDENSED)
+        .set_header(vec!["Rev", "Time (UTC)", "Key", "Change", "Command"]);
+
+    for entry in &entries {
+        let time = DateTime::parse_from_rfc3339(&entry.changed_at)
+            .map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|_| entry.changed_at.clone());
+
+        table.add_row(vec![
+            entry.revision.to_string(),
+            time,
+            entry.key.clone(),
+            entry.change().to_string(),
+            entry.command.clone(),
+        ]);
+    }
+
+    println!(
+        "History of {}:{} ({} changes):",
+        project_name.cyan().bold(),
+        environment,
+        entries.len()
+    );
+    println!();
+    println!("{table}");
+    println!();
+    let env_flag = env.map(|e| format!(" --env {}", e)).unwrap_or_default();
+    println!(
+        "  Restore with {}",
+        format!("envkeep rollback {}{} --to <rev>", project_name, env_flag).cyan()
+    );
+
+    Ok(())
+}
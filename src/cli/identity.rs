@@ -0,0 +1,34 @@
+use anyhow::{Context, Result};
+use x25519_dalek::PublicKey;
+
+use crate::cli::IdentityAction;
+use crate::crypto::{recipients, signing};
+use crate::unlock;
+use crate::vault;
+use crate::vault::audit::Access;
+
+pub fn handle_identity(action: IdentityAction) -> Result<()> {
+    match action {
+        IdentityAction::Show { signing } => handle_show(signing),
+    }
+}
+
+fn handle_show(signing: bool) -> Result<()> {
+    let (conn, keyring) = unlock::open_vault_with_keyring()
+        .context("Failed to open vault")?;
+
+    // Only the key on stdout, so it can be piped or copied as is
+    if signing {
+        let key = signing::public_key(&keyring.signing_key(&conn)?);
+        println!("{}", signing::format_key(&key));
+        eprintln!("Fingerprint: {}", signing::fingerprint(&key));
+        eprintln!("Teammates trust your exports with: envkeep trust add <this key> --name <you>");
+    } else {
+        let identity = keyring.identity(&conn)?;
+        println!("{}", recipients::format_public_key(&PublicKey::from(&identity)));
+        eprintln!("Teammates export to you with: envkeep export <project> --to <this key>");
+    }
+    vault::audit::record(&conn, "identity show", Access::Read, None, &[])?;
+
+    Ok(())
+}
@@ -1,18 +1,23 @@
 use anyhow::{Context, Result};
 use colored::Colorize;
+use rusqlite::Connection;
 use std::fs;
 use std::path::Path;
 
-use crate::crypto;
+use crate::crypto::secret::SecretBytes;
+use crate::crypto::{self, recipients, signing, ValueBinding};
 use crate::errors::EnvkeepError;
+use crate::unlock;
 use crate::vault;
-use crate::vault::project::Project;
+use crate::vault::audit::Access;
+use crate::vault::keyring::Keyring;
+use crate::vault::project::{DEFAULT_ENVIRONMENT, Project};
 
-use super::export::MAGIC; // re-export MAGIC from export module
+use super::export::{MAGIC, RECIPIENTS_MAGIC, SIGNED_MAGIC}; // re-export MAGIC from export module
 
 // Note: you may need to make MAGIC pub in export.rs
 
-pub fn handle_import(file_path: &str) -> Result<()> {
+pub fn handle_import(file_path: &str, allow_unsigned: bool) -> Result<()> {
     let path = Path::new(file_path);
     if !path.exists() {
         return Err(EnvkeepError::BackupNotFound(file_path.to_string()).into());
@@ -22,37 +27,51 @@ pub fn handle_import(file_path: &str) -> Result<()> {
     let data = fs::read(path)
         .map_err(|e| EnvkeepError::FileReadError(file_path.to_string(), e))?;
 
-    // Verify magic bytes
-    if data.len() < MAGIC.len() + 16 || &data[..MAGIC.len()] != MAGIC {
-        return Err(EnvkeepError::InvalidVaultFile.into());
-    }
+    // Open the vault first: the trusted signers and the identity live there
+    let (mut conn, keyring) = unlock::open_vault_with_keyring()?;
+    let data = check_signature(&conn, &keyring, &data, allow_unsigned)?;
+
+    let json_bytes = SecretBytes::new(if let Some(sealed) = data.strip_prefix(RECIPIENTS_MAGIC) {
+        // Encrypted to public keys: the vault's identity opens it
+        let identity = keyring.identity(&conn)?;
+        recipients::open(sealed, &identity)
+            .context("Failed to decrypt the export file")?
+    } else {
+        // Verify magic bytes
+        if data.len() < MAGIC.len() + 16 || &data[..MAGIC.len()] != MAGIC {
+            return Err(EnvkeepError::InvalidVaultFile.into());
+        }
+
+        // Extract salt and encrypted data
+        let salt = &data[MAGIC.len()..MAGIC.len() + 16];
+        let encrypted = &data[MAGIC.len() + 16..];
 
-    // Extract salt and encrypted data
-    let salt = &data[MAGIC.len()..MAGIC.len() + 16];
-    let encrypted = &data[MAGIC.len() + 16..];
+        // Ask for export passphrase
+        let export_password = crypto::prompt_password("Export passphrase: ")?;
+        let export_key = crypto::key::derive_key(&export_password, salt);
 
-    // Ask for export passphrase
-    let export_password = crypto::prompt_password("Export passphrase: ")?;
-    let export_key = crypto::key::derive_key(&export_password, salt);
+        // Decrypt
+        crypto::decrypt(&export_key, encrypted)
+            .context("Wrong passphrase or corrupted file")?
+    });
 
-    // Decrypt
-    let json_bytes = crypto::decrypt(&export_key, encrypted)
-        .context("Wrong passphrase or corrupted file")?;
-    let json_str = String::from_utf8(json_bytes)
+    let json_str = std::str::from_utf8(&json_bytes)
         .context("Invalid UTF-8 in decrypted data")?;
 
     // Parse
-    let export_data: super::export::ExportData = serde_json::from_str(&json_str)
+    let export_data: super::export::ExportData = serde_json::from_str(json_str)
         .context("Invalid export data format")?;
 
-    // Open the vault
-    let vault_password = crypto::prompt_existing_password()?;
-    let conn = vault::open_vault(&vault_password)?;
-    let enc_key = vault::get_encryption_key(&conn, &vault_password)?;
+    // Older exports do not say which environment they came from
+    let environment = export_data.environment.as_deref().unwrap_or(DEFAULT_ENVIRONMENT);
+    vault::project::validate_name(environment)?;
+
+    // All or nothing: a failure leaves no half-imported project behind
+    let tx = conn.transaction()?;
 
     // Create the project
-    let project = Project::new(&export_data.project_name, None);
-    vault::project::create_project(&conn, &project)
+    let project = Project::new(&export_data.project_name, None, environment);
+    vault::project::create_project(&tx, &project)
         .context(format!(
             "Failed to create project '{}' (already exists?)",
             export_data.project_name
@@ -61,18 +80,78 @@ pub fn handle_import(file_path: &str) -> Result<()> {
     // Encrypt and store each variable
     let mut count = 0;
     for (key, value) in &export_data.variables {
-        let encrypted_value = crypto::encrypt_value(&enc_key, value)?;
-        vault::variable::upsert_variable(&conn, &project.id, key, &encrypted_value)?;
+        let binding = ValueBinding::Variable {
+            project_id: &project.id,
+            environment,
+            key,
+        };
+        let encrypted_value = keyring.encrypt(&tx, value, &binding)?;
+        vault::variable::upsert_variable(
+            &tx,
+            &project.id,
+            environment,
+            key,
+            &encrypted_value,
+            "import",
+        )?;
         count += 1;
     }
 
+    let keys: Vec<String> = export_data.variables.keys().cloned().collect();
+    let project_ref = format!("{}:{}", project.name, environment);
+    vault::audit::record(&tx, "import", Access::Import, Some(&project_ref), &keys)?;
+    tx.commit()?;
+
     println!(
         "{} Imported {} ({} variables) from {}",
         "Done.".green().bold(),
-        export_data.project_name.cyan(),
+        project_ref.cyan(),
         count,
         file_path.bold()
     );
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Verify the signature of a signed export and check its signer against the
+/// trusted signers. Returns the file inside the signature.
+fn check_signature<'a>(
+    conn: &Connection,
+    keyring: &Keyring,
+    data: &'a [u8],
+    allow_unsigned: bool,
+) -> Result<&'a [u8]> {
+    let Some(signed) = data.strip_prefix(SIGNED_MAGIC) else {
+        if !allow_unsigned {
+            return Err(EnvkeepError::UnsignedExport.into());
+        }
+        println!("{} The export is not signed; importing it anyway.", "Warning:".yellow());
+        return Ok(data);
+    };
+
+    if signed.len() < signing::PUBLIC_KEY_LEN + signing::SIGNATURE_LEN {
+        return Err(EnvkeepError::InvalidVaultFile.into());
+    }
+    let (public_key, rest) = signed.split_at(signing::PUBLIC_KEY_LEN);
+    let (signature, inner) = rest.split_at(signing::SIGNATURE_LEN);
+    let public_key: [u8; signing::PUBLIC_KEY_LEN] = public_key.try_into()?;
+
+    signing::verify(&public_key, inner, signature)?;
+
+    let key = signing::format_key(&public_key);
+    let fingerprint = signing::fingerprint(&public_key);
+    let own_key = signing::public_key(&keyring.signing_key(conn)?);
+
+    match vault::trust::find_signer(conn, &key)? {
+        Some(signer) => println!("Signed by {} ({})", signer.name.cyan(), fingerprint),
+        None if public_key == own_key => println!("Signed by this vault ({})", fingerprint),
+        None if allow_unsigned => println!(
+            "{} Signed by {}, which is not a trusted signer; importing it anyway.",
+            "Warning:".yellow(),
+            fingerprint
+        ),
+        None => return Err(EnvkeepError::UntrustedSigner(fingerprint, key).into()),
+    }
+
+    Ok(inner)
+}
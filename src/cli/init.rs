@@ -1,10 +1,13 @@
 use anyhow::{Context, Result};
 use colored::Colorize;
+use std::path::Path;
 
-use crate::crypto;
+use crate::cli::recovery;
+use crate::unlock;
 use crate::vault;
+use crate::vault::audit::Access;
 
-pub fn handle_init() -> Result<()> {
+pub fn handle_init(recovery_file: Option<&Path>) -> Result<()> {
     // Check if vault already exists
     if vault::vault_exists() {
         let path = vault::vault_path();
@@ -24,16 +27,21 @@ pub fn handle_init() -> Result<()> {
     println!();
 
     // Prompt for master password
-    let password = crypto::prompt_new_password()
+    let password = unlock::new_password()
         .context("Failed to read password")?;
 
     // Create the vault
-    vault::create_vault(&password)
+    let conn = vault::create_vault(&password)
         .context("Failed to create vault")?;
+    vault::audit::record(&conn, "init", Access::Modify, None, &[])?;
 
     let path = vault::vault_path();
     println!();
     println!("{} Vault created at {}", "Done.".green().bold(), path.display());
+
+    let data_key = vault::data_key(&password)?;
+    let recovery_key = vault::new_recovery_key(&data_key)?;
+    recovery::show_recovery_key(&recovery_key, recovery_file)?;
     println!();
     println!("Next steps:");
     println!("  1. cd into a project directory");
@@ -2,28 +2,29 @@ use anyhow::{Context, Result};
 use colored::Colorize;
 use comfy_table::{Table, ContentArrangement, presets::UTF8_FULL_CONDENSED};
 
-use crate::crypto;
+use crate::unlock;
 use crate::vault;
+use crate::vault::audit::Access;
 
-pub fn handle_inspect(name: &str) -> Result<()> {
-    let password = crypto::prompt_existing_password()?;
-    let conn = vault::open_vault(&password)
+pub fn handle_inspect(name: &str, env: Option<&str>) -> Result<()> {
+    let (conn, keyring) = unlock::open_vault_with_keyring()
         .context("Failed to open vault")?;
-    let enc_key = vault::get_encryption_key(&conn, &password)?;
 
     let project = vault::project::get_project(&conn, name)?;
-    let variables = vault::variable::get_variables(&conn, &project.id)?;
+    let environment = project.environment_or_default(env);
+    let resolved = vault::resolve::resolve(&conn, &project, &environment)?;
 
-    if variables.is_empty() {
-        println!("Project {} has no variables.", name.cyan());
+    if resolved.values.is_empty() {
+        println!("Project {} has no variables in {}.", name.cyan(), environment);
         return Ok(());
     }
 
     println!(
         "Project: {} ({} variables)",
         name.cyan().bold(),
-        variables.len()
+        resolved.values.len()
     );
+    println!("Environment: {}", environment);
     if let Some(dir) = &project.directory {
         println!("Directory: {}", dir);
     }
@@ -33,18 +34,30 @@ pub fn handle_inspect(name: &str) -> Result<()> {
     table
         .load_preset(UTF8_FULL_CONDENSED)
         .set_content_arrangement(ContentArrangement::Dynamic)
-        .set_header(vec!["Key", "Value"]);
+        .set_header(vec!["Key", "Value", "Source"]);
 
-    for var in &variables {
-        let decrypted = crypto::decrypt_value(&enc_key, &var.encrypted_value)
-            .context(format!("Failed to decrypt: {}", var.key))?;
+    for (key, value) in &resolved.values {
+        let decrypted = keyring.decrypt(&conn, &value.encrypted_value, &value.binding(key, &environment))
+            .context(format!("Failed to decrypt: {}", key))?;
 
-        let masked = mask_value(&var.key, &decrypted);
-        table.add_row(vec![var.key.clone(), masked]);
+        let masked = mask_value(key, &decrypted);
+        table.add_row(vec![key.clone(), masked, value.source.as_str().to_string()]);
     }
 
     println!("{table}");
 
+    for key in &resolved.conflicts {
+        println!(
+            "{} {} is also a linked shared secret; the project value takes precedence.",
+            "Note:".yellow(),
+            key.cyan()
+        );
+    }
+
+    let keys: Vec<String> = resolved.values.keys().cloned().collect();
+    let project_ref = format!("{}:{}", name, environment);
+    vault::audit::record(&conn, "inspect", Access::Decrypt, Some(&project_ref), &keys)?;
+
     Ok(())
 }
 
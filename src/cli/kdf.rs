@@ -0,0 +1,69 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use std::time::Duration;
+
+use crate::cli::KdfAction;
+use crate::crypto;
+use crate::unlock;
+use crate::vault;
+use crate::vault::audit::Access;
+
+pub fn handle_kdf(action: KdfAction) -> Result<()> {
+    match action {
+        KdfAction::Show => handle_show(),
+        KdfAction::Benchmark { target_ms, apply } => handle_benchmark(target_ms, apply),
+    }
+}
+
+fn handle_show() -> Result<()> {
+    // Unlocking also moves an old PBKDF2 vault to Argon2id
+    let (conn, _) = unlock::open_vault_with_keyring()
+        .context("Failed to open vault")?;
+    let params = vault::kdf_params()?;
+    vault::audit::record(&conn, "kdf show", Access::Read, None, &[])?;
+
+    let elapsed = crypto::key::time_derivation(&params)?;
+    println!("Key derivation: {}", params.describe().cyan());
+    println!("Unlock time on this machine: ~{} ms", elapsed.as_millis());
+
+    Ok(())
+}
+
+fn handle_benchmark(target_ms: u64, apply: bool) -> Result<()> {
+    println!("Benchmarking Argon2id for a {} ms unlock...", target_ms);
+    let (params, measured) = crypto::key::calibrate(Duration::from_millis(target_ms))?;
+
+    println!("  Parameters: {}", params.describe().cyan());
+    println!("  Measured:   {} ms", measured.as_millis());
+
+    if !apply {
+        println!();
+        println!("  Run with {} to use these parameters for the vault.", "--apply".cyan());
+        return Ok(());
+    }
+
+    // The data key is wrapped again under a key derived from the password,
+    // which the agent does not have
+    let password = unlock::password_without_agent()?;
+    let conn = vault::open_vault(&password)
+        .context("Failed to open vault")?;
+    vault::get_encryption_key(&conn, &password)?;
+
+    if vault::kdf_params()? == params {
+        println!("The vault already uses these parameters.");
+        return Ok(());
+    }
+
+    let backup = vault::backup_vault("pre-kdf")?;
+    vault::set_kdf(&password, &params)?;
+    vault::audit::record(&conn, "kdf benchmark", Access::Modify, None, &[])?;
+
+    println!(
+        "{} Vault key now derived with {}",
+        "Done.".green().bold(),
+        params.describe()
+    );
+    println!("  Backup: {}", backup.display());
+
+    Ok(())
+}
@@ -3,15 +3,16 @@ use colored::Colorize;
 use comfy_table::{Table, ContentArrangement, presets::UTF8_FULL_CONDENSED};
 use chrono::{DateTime, Utc};
 
-use crate::crypto;
+use crate::unlock;
 use crate::vault;
+use crate::vault::audit::Access;
 
 pub fn handle_list() -> Result<()> {
-    let password = crypto::prompt_existing_password()?;
-    let conn = vault::open_vault(&password)
+    let conn = unlock::open_vault()
         .context("Failed to open vault")?;
 
     let projects = vault::project::list_projects(&conn)?;
+    vault::audit::record(&conn, "list", Access::Read, None, &[])?;
 
     if projects.is_empty() {
         println!("No projects in vault.");
@@ -0,0 +1,14 @@
+use anyhow::Result;
+use colored::Colorize;
+
+use crate::agent;
+
+pub fn handle_lock() -> Result<()> {
+    if agent::lock() {
+        println!("{} Vault locked.", "Done.".green().bold());
+    } else {
+        println!("No agent running; the vault is already locked.");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use comfy_table::{Table, presets::UTF8_FULL_CONDENSED};
+
+use crate::cli::LockoutAction;
+use crate::unlock;
+use crate::vault;
+use crate::vault::attempts::{self, Attempts};
+use crate::vault::audit::Access;
+
+pub fn handle_lockout(action: LockoutAction) -> Result<()> {
+    match action {
+        LockoutAction::Status => handle_status(),
+        LockoutAction::Set { after } => handle_set(Some(after)),
+        LockoutAction::Off => handle_set(None),
+    }
+}
+
+/// Needs no password: it only shows what `unlock.json` already holds.
+fn handle_status() -> Result<()> {
+    let attempts = Attempts::load()?;
+
+    match attempts.lockout_after {
+        Some(after) => println!("Lockout: after {} wrong passwords in a row", after),
+        None => println!("Lockout: {} (wrong passwords only slow down the next attempt)", "off".dimmed()),
+    }
+
+    if attempts.locked_out {
+        println!(
+            "{} The vault is locked out; run {} or {}.",
+            "Warning:".yellow(),
+            "envkeep recover".cyan(),
+            "envkeep recovery combine".cyan()
+        );
+    } else if attempts.failures > 0 {
+        println!("Wrong passwords in a row: {}", attempts.failures.to_string().yellow());
+        match attempts.wait_secs() {
+            0 => println!("  The next attempt is allowed now."),
+            secs => println!("  The next attempt is allowed in {}s.", secs),
+        }
+    }
+
+    if let Some(last) = &attempts.last_success {
+        println!("Last successful unlock: {}", attempts::local_time(last));
+    }
+
+    if attempts.history.is_empty() {
+        return Ok(());
+    }
+
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL_CONDENSED).set_header(vec!["Time", "Result"]);
+    for attempt in attempts.history.iter().rev() {
+        let result = if attempt.ok { "unlocked" } else { "wrong password" };
+        table.add_row(vec![attempts::local_time(&attempt.at), result.to_string()]);
+    }
+
+    println!();
+    println!("Recent attempts:");
+    println!("{table}");
+
+    Ok(())
+}
+
+fn handle_set(after: Option<u32>) -> Result<()> {
+    // Like passwd: prove the password is known, do not take it from the agent
+    let password = unlock::password_without_agent()?;
+    let conn = vault::open_vault(&password)
+        .context("Failed to open vault")?;
+
+    let mut attempts = Attempts::load()?;
+    attempts.lockout_after = after;
+    attempts.save()?;
+    vault::audit::record(&conn, "lockout", Access::Modify, None, &[])?;
+
+    match after {
+        Some(after) => {
+            println!(
+                "{} The vault locks after {} wrong passwords in a row.",
+                "Done.".green().bold(),
+                after
+            );
+            println!(
+                "  Only {} (or {}) unlocks it then.",
+                "envkeep recover".cyan(),
+                "envkeep recovery combine".cyan()
+            );
+            if !vault::has_recovery_key()? {
+                println!(
+                    "{} This vault has no recovery key, so a lockout could not be undone. Create one with {}.",
+                    "Warning:".yellow(),
+                    "envkeep recovery new".cyan()
+                );
+            }
+        }
+        None => println!("{} Lockout turned off.", "Done.".green().bold()),
+    }
+
+    Ok(())
+}
@@ -1,39 +1,48 @@
-use anyhow::{Context, Result};
+use anyhow::Result;
 use colored::Colorize;
 
-use crate::crypto;
+use crate::unlock;
 use crate::vault;
+use crate::vault::audit::Access;
+use crate::vault::migrations;
 
-pub fn handle_migrate() -> Result<()> {
-    let password = crypto::prompt_existing_password()?;
-    let conn = vault::open_vault(&password)?;
+pub fn handle_migrate(dry_run: bool) -> Result<()> {
+    let password = unlock::password()?;
+    let mut conn = vault::open_vault_unmigrated(&password)?;
 
-    // Check current schema version
-    let version: String = conn
-        .query_row(
-            "SELECT value FROM metadata WHERE key = 'schema_version'",
-            [],
-            |row| row.get(0),
-        )
-        .unwrap_or_else(|_| "0".to_string());
-
-    let current: u32 = version.parse().unwrap_or(0);
+    let current = migrations::schema_version(&conn);
+    let pending = migrations::pending(&conn)?;
 
     println!("Current schema version: {}", current);
 
-    // Apply migrations based on version
-    // For now, version 1 is the only version. Future versions will be added here.
-    if current >= 1 {
+    if pending.is_empty() {
         println!("{} Schema is up to date (version {}).", "Done.".green().bold(), current);
         return Ok(());
     }
 
-    // Example of a future migration:
-    // if current < 2 {
-    //     conn.execute_batch("ALTER TABLE projects ADD COLUMN tags TEXT;")?;
-    //     conn.execute("UPDATE metadata SET value = '2' WHERE key = 'schema_version'", [])?;
-    //     println!("  Applied migration to version 2");
-    // }
+    println!("Pending migrations:");
+    for step in &pending {
+        println!("  {} v{}: {}", "->".cyan(), step.version, step.description);
+    }
+
+    if dry_run {
+        println!();
+        println!("{}", "Dry run: nothing was changed.".dimmed());
+        return Ok(());
+    }
+
+    let backup = vault::backup_vault(&format!("pre-v{}", migrations::latest_version()))?;
+    println!("  Backup saved to {}", backup.display().to_string().dimmed());
+
+    migrations::apply(&mut conn, &pending, &migrations::Context { password: &password })?;
+    vault::audit::record(&conn, "migrate", Access::Modify, None, &[])?;
+
+    println!(
+        "{} Migrated schema from version {} to {}.",
+        "Done.".green().bold(),
+        current,
+        migrations::latest_version()
+    );
 
     Ok(())
-}
\ No newline at end of file
+}
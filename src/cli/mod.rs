@@ -20,7 +20,29 @@ pub mod restore;
 pub mod status;
 pub mod recent;
 pub mod migrate;
-use clap::{Parser, Subcommand};
+pub mod doctor;
+pub mod envs;
+pub mod history;
+pub mod rollback;
+pub mod audit;
+pub mod update;
+pub mod set;
+pub mod get;
+pub mod unset;
+pub mod kdf;
+pub mod passwd;
+pub mod agent;
+pub mod lock;
+pub mod recover;
+pub mod recovery;
+pub mod rekey;
+pub mod identity;
+pub mod trust;
+pub mod lockout;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+
+use crate::crypto::generator::Format;
 
 #[derive(Parser)]
 #[command(
@@ -32,6 +54,18 @@ use clap::{Parser, Subcommand};
 )]
 
 pub struct Cli {
+    ///Read the master password from the first line of this file (must not be readable by everyone)
+    #[arg(long, global = true, value_name = "PATH", conflicts_with_all = ["password_fd", "password_command"])]
+    pub password_file: Option<PathBuf>,
+
+    ///Read the master password from this open file descriptor (eg 3 with `3<secret.txt`)
+    #[arg(long, global = true, value_name = "FD", conflicts_with = "password_command")]
+    pub password_fd: Option<u32>,
+
+    ///Run this shell command (sh -c, or cmd /C on Windows) and use the first line it prints as the master password
+    #[arg(long, global = true, value_name = "COMMAND")]
+    pub password_command: Option<String>,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -39,10 +73,71 @@ pub struct Cli {
 #[derive(Subcommand)]
 pub enum Commands {
     ///create a new vault and set master password
-    Init,
+    Init {
+        ///Also write the recovery key to this file (it is always printed once)
+        #[arg(long, value_name = "PATH")]
+        recovery_file: Option<PathBuf>,
+    },
+
+    ///Set a new master password using the recovery key
+    Recover {
+        ///Read the recovery key from this file instead of asking for it
+        #[arg(long, value_name = "PATH")]
+        recovery_file: Option<PathBuf>,
+    },
+
+    ///Manage the recovery key
+    Recovery {
+        #[command(subcommand)]
+        action: RecoveryAction,
+    },
 
     ///upgrade vault schema to latest version
-    Migrate,
+    Migrate {
+        /// List the pending migrations without applying them
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    ///Check the vault for problems and explain why it does not unlock
+    Doctor,
+
+    ///Change the master password and move the vault to a new data key
+    Passwd {
+        ///Also write the new recovery key to this file (it is always printed once)
+        #[arg(long, value_name = "PATH")]
+        recovery_file: Option<PathBuf>,
+    },
+
+    ///Inspect or tune how the master password is turned into the vault key
+    Kdf {
+        #[command(subcommand)]
+        action: KdfAction,
+    },
+
+    ///Give a project (or shared secret) a new data key and re-encrypt its values
+    Rekey {
+        ///Project name (or secret name with --secret)
+        name: String,
+        ///Re-key the shared secret NAME instead of a project
+        #[arg(long)]
+        secret: bool,
+    },
+
+    ///Keep the vault unlocked in a background agent so commands do not prompt
+    Agent {
+        #[command(subcommand)]
+        action: AgentAction,
+    },
+
+    ///Make the agent forget the vault key now
+    Lock,
+
+    ///Show failed unlock attempts and set the lockout after repeated wrong passwords
+    Lockout {
+        #[command(subcommand)]
+        action: LockoutAction,
+    },
 
     ///Export encrypted vault backup
     Backup,
@@ -57,6 +152,9 @@ pub enum Commands {
     Add {
         /// Project String
         name: String,
+        /// Environment to store the variables in (eg dev, staging, prod)
+        #[arg(long)]
+        env: Option<String>,
     },
 
     ///Auto detect project name from git remote or directory
@@ -76,19 +174,121 @@ pub enum Commands {
     Inspect {
         ///Project name
         name: String,
+        /// Environment to show (defaults to the project's default environment)
+        #[arg(long)]
+        env: Option<String>,
     },
 
-    ///Compare variable between two projects
+    ///Compare variable between two projects or environments
     Diff{
-        ///First project name
+        ///First project, optionally with an environment (eg api:dev)
         project1: String,
-        ///Second project name
+        ///Second project, optionally with an environment (eg api:prod)
         project2: String,
     },
 
     ///Write .env file from vault to current directory
     Use{
         project: String,
+        /// Environment to write (defaults to the project's default environment)
+        #[arg(long)]
+        env: Option<String>,
+    },
+
+    ///Show the change history of a project's variables
+    History {
+        ///Project name
+        project: String,
+        ///Only show changes to this key
+        key: Option<String>,
+        /// Environment to show (defaults to the project's default environment)
+        #[arg(long)]
+        env: Option<String>,
+    },
+
+    ///Restore a project (or a single key) to an earlier revision or time
+    Rollback {
+        ///Project name
+        project: String,
+        ///Only roll back this key
+        key: Option<String>,
+        ///Revision number (see 'envkeep history') or timestamp (eg 2024-05-01T12:00:00Z)
+        #[arg(long)]
+        to: String,
+        /// Environment to roll back (defaults to the project's default environment)
+        #[arg(long)]
+        env: Option<String>,
+    },
+
+    ///Show the audit log of vault access, or verify it has not been tampered with
+    Audit {
+        #[command(subcommand)]
+        action: Option<AuditAction>,
+        ///Only show entries for this project
+        #[arg(long)]
+        project: Option<String>,
+        ///Only show entries since this time (eg 2024-05-01, 12h, 7d)
+        #[arg(long)]
+        since: Option<String>,
+    },
+
+    ///Push changes from .env in the current directory back into an existing project
+    Update {
+        ///Project name
+        project: String,
+        /// Environment to update (defaults to the project's default environment)
+        #[arg(long)]
+        env: Option<String>,
+        ///How to combine .env with the vault
+        #[arg(long, value_enum, default_value_t = UpdatePolicy::MergeKeepFile)]
+        policy: UpdatePolicy,
+        ///Apply without asking for confirmation
+        #[arg(long, short)]
+        yes: bool,
+    },
+
+    ///Set one or more variables of a project (KEY=VALUE)
+    Set {
+        ///Project name
+        project: String,
+        ///KEY=VALUE pairs
+        #[arg(required = true)]
+        pairs: Vec<String>,
+        /// Environment to change (defaults to the project's default environment)
+        #[arg(long)]
+        env: Option<String>,
+    },
+
+    ///Print the raw value of a variable (for scripts: $(envkeep get api KEY))
+    Get {
+        ///Project name
+        project: String,
+        ///Variable name
+        key: String,
+        /// Environment to read (defaults to the project's default environment)
+        #[arg(long)]
+        env: Option<String>,
+    },
+
+    ///Remove one or more variables from a project
+    Unset {
+        ///Project name
+        project: String,
+        ///Variable names
+        #[arg(required = true)]
+        keys: Vec<String>,
+        /// Environment to change (defaults to the project's default environment)
+        #[arg(long)]
+        env: Option<String>,
+    },
+
+    ///List the environments of a project
+    Envs {
+        ///Project name
+        project: String,
+        /// Make this environment the project's default
+        #[arg(long)]
+        set_default: Option<String>,
     },
 
     ///show the currently active project 
@@ -105,18 +305,19 @@ pub enum Commands {
 
     ///find variables no longer referenced in the project code
     Unused{
-        ///prohect name
+        ///prohect name, optionally with an environment (eg api:prod)
         project: String,
     },
 
     ///validate variable values for common mistakes
     Validate{
+        ///Project name, optionally with an environment (eg api:prod)
         project: String,
     },
 
     ///Infer types of variables (string, number, boolean, url etc)
     Types {
-        ///Project name
+        ///Project name, optionally with an environment (eg api:prod)
         project: String,
     },
 
@@ -128,9 +329,9 @@ pub enum Commands {
 
     ///Copy common variables from one porject to another
     Sync {
-        ///source project 
+        ///source project, optionally with an environment (eg api:dev)
         from: String,
-        ///destination 
+        ///destination, optionally with an environment (eg api:prod)
         to: String,
     },
 
@@ -142,14 +343,32 @@ pub enum Commands {
 
     ///Export the project as encrypted .envvault file
     Export {
-        ///Project name
+        ///Project name, optionally with an environment (eg api:prod)
         project: String,
+        ///Encrypt to this public key (from 'envkeep identity show') instead of a passphrase; repeatable
+        #[arg(long = "to", value_name = "PUBKEY")]
+        to: Vec<String>,
+    },
+
+    ///Show this vault's public key, for teammates to export to
+    Identity {
+        #[command(subcommand)]
+        action: IdentityAction,
     },
 
     ///Import project from .envvault file
     Import {
         /// path to .envvault file
         file: String,
+        /// Import even if the file is unsigned or its signer is not trusted
+        #[arg(long)]
+        allow_unsigned: bool,
+    },
+
+    ///Manage the signing keys whose exports import accepts
+    Trust {
+        #[command(subcommand)]
+        action: TrustAction,
     },
 
     ///Launch the full-screen TUI
@@ -167,6 +386,47 @@ pub enum SecretsAction{
     ///List all secrets (values are masked)
     List,
 
+    ///Print the raw value of a secret
+    Get {
+        ///Secret key name
+        secret: String,
+    },
+
+    ///Show a secret's metadata, linked projects and change history
+    Show {
+        ///Secret key name
+        secret: String,
+    },
+
+    ///Turn copies of a project variable into one shared secret linked to those projects
+    Promote {
+        ///Variable key to promote
+        key: String,
+        ///Take the value from this project (project[:env]); required when copies differ
+        #[arg(long)]
+        from: Option<String>,
+        ///Apply without asking for confirmation
+        #[arg(long, short)]
+        yes: bool,
+    },
+
+    ///Rename a secret (links and history are kept)
+    Rename {
+        ///Current key name
+        secret: String,
+        ///New key name
+        new_name: String,
+    },
+
+    ///Delete a secret
+    Delete {
+        ///Secret key name
+        secret: String,
+        ///Delete even if projects still link to it
+        #[arg(long)]
+        force: bool,
+    },
+
     ///Link a secret to a project
     Link{
         ///Secret key name
@@ -184,9 +444,191 @@ pub enum SecretsAction{
     },
 
     ///Rotate a secret (generate new value)
+    ///
+    ///Generator options are remembered and reused by later rotations.
     Rotate {
         ///secret key name
         secret: String,
+        ///Format of the new value
+        #[arg(long, value_enum)]
+        format: Option<Format>,
+        ///Bytes for hex/base64/base64url, characters for alnum, words for words
+        #[arg(long)]
+        length: Option<usize>,
+        ///Characters to pick from (alnum only)
+        #[arg(long)]
+        charset: Option<String>,
+        ///Fixed prefix for the value (eg sk_live_)
+        #[arg(long)]
+        prefix: Option<String>,
+        ///Do not run the secret's rotation hooks
+        #[arg(long)]
+        no_hooks: bool,
+    },
+
+    ///Manage commands that run after a secret is rotated
+    Hook {
+        #[command(subcommand)]
+        action: HookAction,
+    },
+
+}
+
+#[derive(Subcommand)]
+pub enum IdentityAction {
+    ///Print the public key
+    Show {
+        ///Print the key exports are signed with instead (for 'envkeep trust add')
+        #[arg(long)]
+        signing: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum TrustAction {
+    ///Trust exports signed by this key (from 'envkeep identity show --signing')
+    Add {
+        key: String,
+        ///Who the key belongs to
+        #[arg(long)]
+        name: String,
+    },
+    ///List the trusted signers
+    List,
+    ///Stop trusting a signer
+    Remove {
+        ///Name or key of the signer
+        signer: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum RecoveryAction {
+    ///Create a new recovery key (the previous one stops working)
+    New {
+        ///Also write the recovery key to this file (it is always printed once)
+        #[arg(long, value_name = "PATH")]
+        file: Option<PathBuf>,
+    },
+
+    ///Split the vault key into shares for several people; any --threshold of them unlock it
+    Split {
+        ///Number of shares to write
+        #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u8).range(2..))]
+        shares: u8,
+        ///Number of shares needed to unlock the vault
+        #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u8).range(2..))]
+        threshold: u8,
+        ///Directory to write the share files to
+        #[arg(long, value_name = "DIR", default_value = ".")]
+        dir: PathBuf,
+    },
+
+    ///Rebuild access from enough shares and set a new master password
+    Combine {
+        ///Share files written by 'recovery split' (asked for one by one if none are given)
+        files: Vec<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum AgentAction {
+    ///Unlock the vault and start the agent
+    Start {
+        ///Lock after this many minutes without use
+        #[arg(long, default_value_t = 15, value_parser = clap::value_parser!(u64).range(1..))]
+        idle: u64,
+        ///Lock after this many minutes no matter what
+        #[arg(long, default_value_t = 480, value_parser = clap::value_parser!(u64).range(1..))]
+        max: u64,
     },
 
-}
\ No newline at end of file
+    ///Lock the vault and stop the agent
+    Stop,
+
+    ///Show whether the agent is running and when it locks
+    Status,
+
+    ///Run the agent in the foreground (started by 'agent start')
+    #[command(hide = true)]
+    Serve {
+        #[arg(long)]
+        idle: u64,
+        #[arg(long)]
+        max: u64,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum LockoutAction {
+    ///Show recent unlock attempts and the current wait or lockout
+    Status,
+
+    ///Lock the vault after this many wrong master passwords in a row, until 'envkeep recover'
+    Set {
+        ///Wrong passwords in a row before the lockout
+        #[arg(value_parser = clap::value_parser!(u32).range(3..))]
+        after: u32,
+    },
+
+    ///Never lock out; wrong passwords only slow down the next attempt
+    Off,
+}
+
+#[derive(Subcommand)]
+pub enum KdfAction {
+    ///Show the key derivation parameters of the vault
+    Show,
+
+    ///Pick Argon2id parameters that take about --target-ms to unlock on this machine
+    Benchmark {
+        ///Target unlock time in milliseconds
+        #[arg(long, default_value_t = 500)]
+        target_ms: u64,
+        ///Rewrap the vault key with the chosen parameters
+        #[arg(long)]
+        apply: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum HookAction {
+    ///Run a shell command after each rotation; it gets the new value on stdin
+    Add {
+        ///Secret key name
+        secret: String,
+        ///Shell command (run with sh -c, or cmd /C on Windows)
+        command: String,
+    },
+
+    ///List the hooks of a secret
+    List {
+        ///Secret key name
+        secret: String,
+    },
+
+    ///Remove a hook
+    Remove {
+        ///Secret key name
+        secret: String,
+        ///Hook id (see 'envkeep secrets hook list')
+        id: i64,
+    },
+}
+
+/// How `envkeep update` resolves differences between .env and the vault.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum UpdatePolicy {
+    /// Make the vault match .env exactly (keys missing from .env are removed)
+    Replace,
+    /// Add new keys from .env, keep the vault's value when both have the key
+    MergeKeepVault,
+    /// Add new keys from .env, take the file's value when both have the key
+    MergeKeepFile,
+}
+
+#[derive(Subcommand)]
+pub enum AuditAction {
+    ///Check the hash chain of the audit log
+    Verify,
+}
@@ -0,0 +1,75 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::agent;
+use crate::cli::recovery;
+use crate::crypto;
+use crate::unlock;
+use crate::vault;
+use crate::vault::audit::Access;
+
+pub fn handle_passwd(recovery_file: Option<&Path>) -> Result<()> {
+    // Never from the agent: changing the password needs the password
+    let password = unlock::password_without_agent()?;
+    let conn = vault::open_vault(&password)
+        .context("Failed to open vault")?;
+    vault::get_encryption_key(&conn, &password)?;
+
+    println!("Choose a new master password.");
+    let new_password = crypto::prompt_new_password()
+        .context("Failed to read password")?;
+
+    if new_password.as_str() == password.as_str() {
+        println!("The new password is the same as the current one. Nothing changed.");
+        return Ok(());
+    }
+
+    // Shares split from the old data key stop working
+    let had_shares = vault::has_recovery_shares()?;
+
+    // A new data key, so a leaked password plus an old copy of the key file
+    // does not open the vault any more
+    let data_key = vault::data_key(&password)?;
+    let backup = vault::backup_vault("pre-passwd")?;
+    let (conn, recovery_key) = vault::rotate_data_key(conn, &data_key, &new_password, &backup)
+        .context("Failed to change the master password; the vault was left unchanged")?;
+    vault::audit::record(&conn, "passwd", Access::Modify, None, &[])?;
+
+    // The agent was unlocked with the old password
+    if agent::lock() {
+        println!("Agent locked; run {} to unlock with the new password.", "envkeep agent start".cyan());
+    }
+
+    println!("{} Master password changed and the vault moved to a new data key.", "Done.".green().bold());
+
+    if let Some(recovery_key) = &recovery_key {
+        println!("  The old recovery key no longer works; this is the new one.");
+        recovery::show_recovery_key(recovery_key, recovery_file)?;
+        println!();
+    }
+    if had_shares {
+        println!(
+            "{} Shares from {} no longer work. Run it again and hand out the new shares.",
+            "Warning:".yellow(),
+            "envkeep recovery split".cyan()
+        );
+    }
+
+    // The backup is exactly what someone who knows the old password wants
+    print!("Delete the backup taken before the change? It still opens with the old password. [y/N]: ");
+    io::stdout().flush()?;
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+
+    if input.trim().to_lowercase() == "y" {
+        vault::remove_backup(&backup)?;
+        println!("  Backup deleted.");
+    } else {
+        println!("  Backup (still opens with the old password): {}", backup.display());
+        println!("  {}", "Delete the backup once you no longer need it.".yellow());
+    }
+
+    Ok(())
+}
@@ -2,14 +2,15 @@ use anyhow::{Context, Result};
 use colored::Colorize;
 use std::io::{self, Write};
 
-use crate::crypto;
+use crate::unlock;
 use crate::vault;
+use crate::vault::audit::Access;
 
 pub fn handle_recent() -> Result<()> {
-    let password = crypto::prompt_existing_password()?;
-    let conn = vault::open_vault(&password)?;
+    let conn = unlock::open_vault()?;
 
     let projects = vault::project::list_projects(&conn)?;
+    vault::audit::record(&conn, "recent", Access::Read, None, &[])?;
 
     if projects.is_empty() {
         println!("No projects in vault.");
@@ -65,7 +66,7 @@ pub fn handle_recent() -> Result<()> {
 
     // Delegate to the use command
     drop(conn); // Close connection before re-opening in handle_use
-    crate::cli::use_project::handle_use(&selected.name)?;
+    crate::cli::use_project::handle_use(&selected.name, None)?;
 
     Ok(())
 }
\ No newline at end of file
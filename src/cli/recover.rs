@@ -0,0 +1,63 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::agent;
+use crate::crypto;
+use crate::crypto::secret::SecretString;
+use crate::errors::EnvkeepError;
+use crate::vault;
+use crate::vault::attempts::Attempts;
+use crate::vault::audit::Access;
+
+pub fn handle_recover(recovery_file: Option<&Path>) -> Result<()> {
+    if !vault::vault_exists() {
+        return Err(EnvkeepError::VaultNotFound.into());
+    }
+
+    let recovery_key = match recovery_file {
+        Some(path) => SecretString::new(
+            fs::read_to_string(path).map_err(|e| EnvkeepError::FileReadError(path.display().to_string(), e))?,
+        ),
+        None => crypto::prompt_password("Recovery key: ")?,
+    };
+
+    let data_key = vault::data_key_from_recovery(&recovery_key)?;
+    println!("Recovery key accepted. Choose a new master password.");
+    let backup = reset_password(&data_key, "recover")?;
+
+    println!("{} Master password reset.", "Done.".green().bold());
+    println!("  The recovery key keeps working; run {} to replace it.", "envkeep recovery new".cyan());
+    println!("  Backup: {}", backup.display());
+
+    Ok(())
+}
+
+/// Set a new master password once the data key was recovered some other way
+/// (recovery key, shares). Returns the backup taken first.
+pub fn reset_password(data_key: &[u8; 32], command: &str) -> Result<PathBuf> {
+    let conn = vault::open_vault_with_data_key(data_key)
+        .context("Failed to open vault")?;
+
+    let new_password = crypto::prompt_new_password()
+        .context("Failed to read password")?;
+
+    let backup = vault::backup_vault(&format!("pre-{}", command.replace(' ', "-")))?;
+    vault::set_password(data_key, &new_password)
+        .context("Failed to set the new master password; the vault was left unchanged")?;
+    vault::audit::record(&conn, command, Access::Modify, None, &[])?;
+
+    // The vault was unlocked without the password, so earlier failures stop counting
+    let mut attempts = Attempts::load()?;
+    if attempts.locked_out {
+        println!("  Lockout after {} wrong passwords cleared.", attempts.failures);
+    }
+    attempts.clear();
+    attempts.save()?;
+
+    // Whatever the agent holds was unlocked with the forgotten password
+    agent::lock();
+
+    Ok(backup)
+}
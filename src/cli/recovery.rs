@@ -0,0 +1,193 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::cli::RecoveryAction;
+use crate::cli::recover;
+use crate::crypto;
+use crate::crypto::shamir::{self, Share};
+use crate::errors::EnvkeepError;
+use crate::unlock;
+use crate::vault;
+use crate::vault::audit::Access;
+
+pub fn handle_recovery(action: RecoveryAction) -> Result<()> {
+    match action {
+        RecoveryAction::New { file } => handle_new(file.as_deref()),
+        RecoveryAction::Split { shares, threshold, dir } => handle_split(shares, threshold, &dir),
+        RecoveryAction::Combine { files } => handle_combine(&files),
+    }
+}
+
+fn handle_new(file: Option<&Path>) -> Result<()> {
+    // Never from the agent: a recovery key is as good as the password
+    let password = unlock::password_without_agent()?;
+    let conn = vault::open_vault(&password)
+        .context("Failed to open vault")?;
+    vault::get_encryption_key(&conn, &password)?;
+
+    if vault::has_recovery_key()? {
+        print!("This vault already has a recovery key. Replace it (the old one stops working)? [y/N]: ");
+        io::stdout().flush()?;
+
+        let mut input = String::new();
+        io::stdin().read_line(&mut input)?;
+
+        if input.trim().to_lowercase() != "y" {
+            println!("Cancelled.");
+            return Ok(());
+        }
+    }
+
+    let data_key = vault::data_key(&password)?;
+    let backup = vault::backup_vault("pre-recovery")?;
+    let recovery_key = vault::new_recovery_key(&data_key)?;
+    vault::audit::record(&conn, "recovery new", Access::Modify, None, &[])?;
+
+    println!("{} New recovery key created.", "Done.".green().bold());
+    println!("  Backup (still opens with the old recovery key): {}", backup.display());
+    show_recovery_key(&recovery_key, file)?;
+
+    Ok(())
+}
+
+fn handle_split(count: u8, threshold: u8, dir: &Path) -> Result<()> {
+    // Never from the agent, as for `recovery new`
+    let password = unlock::password_without_agent()?;
+    let conn = vault::open_vault(&password)
+        .context("Failed to open vault")?;
+    vault::get_encryption_key(&conn, &password)?;
+
+    let data_key = vault::data_key(&password)?;
+    let shares = shamir::split(&data_key, threshold, count)?;
+
+    let paths: Vec<PathBuf> = shares
+        .iter()
+        .map(|share| dir.join(format!("envkeep-share-{}-of-{}.txt", share.x, count)))
+        .collect();
+    if let Some(existing) = paths.iter().find(|path| path.exists()) {
+        return Err(EnvkeepError::FileWriteError(
+            existing.display().to_string(),
+            io::Error::from(io::ErrorKind::AlreadyExists),
+        )
+        .into());
+    }
+
+    let created = chrono::Utc::now().format("%Y-%m-%d %H:%M UTC");
+    for (share, path) in shares.iter().zip(&paths) {
+        let contents = format!(
+            "# envkeep recovery share {} of {}\n\
+             # Any {} shares unlock the vault: envkeep recovery combine <share files>\n\
+             # Created {}\n\
+             {}",
+            share.x,
+            count,
+            threshold,
+            created,
+            share.encode()
+        );
+        write_new_file(path, &contents)
+            .context(format!("Failed to write {}", path.display()))?;
+    }
+    vault::mark_shares_split()?;
+    vault::audit::record(&conn, "recovery split", Access::Export, None, &[])?;
+
+    println!(
+        "{} Wrote {} shares; any {} of them unlock the vault:",
+        "Done.".green().bold(),
+        count,
+        threshold
+    );
+    for path in &paths {
+        println!("  {}", path.display());
+    }
+    println!(
+        "{}",
+        "Give each share to a different person, then delete the files from this machine.".yellow().bold()
+    );
+    println!(
+        "  {} moves the vault to a new data key and these shares stop working; split again after it.",
+        "envkeep passwd".cyan()
+    );
+
+    Ok(())
+}
+
+fn handle_combine(files: &[PathBuf]) -> Result<()> {
+    if !vault::vault_exists() {
+        return Err(EnvkeepError::VaultNotFound.into());
+    }
+
+    let mut shares = Vec::new();
+    for path in files {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| EnvkeepError::FileReadError(path.display().to_string(), e))?;
+        shares.push(parse_share_text(&contents).context(format!("In {}", path.display()))?);
+    }
+
+    // No files: type them in, until the first share says how many are needed
+    while files.is_empty() && shares.first().is_none_or(|first| shares.len() < first.threshold as usize) {
+        let input = crypto::prompt_password(&format!("Share {}: ", shares.len() + 1))?;
+        match Share::decode(&input) {
+            Ok(share) => shares.push(share),
+            Err(e) => eprintln!("{} {}", "Error:".red(), e),
+        }
+    }
+
+    let data_key = shamir::combine(&shares)?;
+    println!("Shares accepted. Choose a new master password.");
+    let backup = recover::reset_password(&data_key, "recovery combine")?;
+
+    println!("{} Master password reset.", "Done.".green().bold());
+    println!("  Backup: {}", backup.display());
+
+    Ok(())
+}
+
+/// The share in a file written by `split`: the line that is not a comment.
+fn parse_share_text(contents: &str) -> Result<Share, EnvkeepError> {
+    let text: String = contents
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .collect();
+    Share::decode(&text)
+}
+
+/// Print a new recovery key once, and write it to `file` if given.
+pub fn show_recovery_key(recovery_key: &str, file: Option<&Path>) -> Result<()> {
+    if let Some(path) = file {
+        write_new_file(path, recovery_key)
+            .context(format!("Failed to write the recovery key to {}", path.display()))?;
+    }
+
+    println!();
+    println!("{}", "Recovery key".bold());
+    println!();
+    println!("    {}", recovery_key.cyan().bold());
+    println!();
+    println!("It unlocks the vault without the master password ({}).", "envkeep recover".cyan());
+    println!(
+        "{}",
+        "It is shown only this once. Store it offline, away from this machine.".yellow().bold()
+    );
+    if let Some(path) = file {
+        println!("  Also written to {}", path.display());
+    }
+
+    Ok(())
+}
+
+/// Create `path` readable by the owner only; never overwrite an existing file.
+fn write_new_file(path: &Path, contents: &str) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    writeln!(file, "{}", contents)
+}
@@ -0,0 +1,47 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+
+use crate::unlock;
+use crate::vault;
+use crate::vault::audit::Access;
+use crate::vault::keyring::OwnerKind;
+use crate::vault::reencrypt;
+
+pub fn handle_rekey(name: &str, secret: bool) -> Result<()> {
+    let (mut conn, keyring) = unlock::open_vault_with_keyring()
+        .context("Failed to open vault")?;
+
+    let (kind, owner_id) = if secret {
+        (OwnerKind::Secret, vault::secret::get_secret(&conn, name)?.id)
+    } else {
+        (OwnerKind::Project, vault::project::get_project(&conn, name)?.id)
+    };
+
+    let backup = vault::backup_vault("pre-rekey")?;
+
+    // The new key and the re-encrypted values land together or not at all
+    let tx = conn.transaction()?;
+    let (old_key, new_key) = keyring.replace(&tx, kind, &owner_id)?;
+    let count = match old_key {
+        Some(old_key) => reencrypt::reencrypt_owner(&tx, &owner_id, &old_key, &new_key)?,
+        None => 0,
+    };
+    tx.commit()?;
+
+    if secret {
+        vault::audit::record(&conn, "rekey", Access::Modify, None, &[name.to_string()])?;
+    } else {
+        vault::audit::record(&conn, "rekey", Access::Modify, Some(name), &[])?;
+    }
+
+    println!(
+        "{} New data key for {} {}, {} values re-encrypted (history included)",
+        "Done.".green().bold(),
+        kind.as_str(),
+        name.cyan(),
+        count
+    );
+    println!("  Backup: {}", backup.display());
+
+    Ok(())
+}
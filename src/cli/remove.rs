@@ -2,12 +2,12 @@ use anyhow::{Context, Result};
 use colored::Colorize;
 use std::io::{self, Write};
 
-use crate::crypto;
+use crate::unlock;
 use crate::vault;
+use crate::vault::audit::Access;
 
 pub fn handle_remove(name: &str) -> Result<()> {
-    let password = crypto::prompt_existing_password()?;
-    let conn = vault::open_vault(&password)
+    let mut conn = unlock::open_vault()
         .context("Failed to open vault")?;
 
     // Check project exists
@@ -30,7 +30,11 @@ pub fn handle_remove(name: &str) -> Result<()> {
         return Ok(());
     }
 
-    vault::project::delete_project(&conn, name)?;
+    // All or nothing: the project, its variables, history, links and key
+    let tx = conn.transaction()?;
+    vault::project::delete_project(&tx, name)?;
+    vault::audit::record(&tx, "remove", Access::Delete, Some(name), &[])?;
+    tx.commit()?;
 
     println!(
         "{} Removed project {} and {} variables",
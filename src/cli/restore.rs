@@ -4,6 +4,7 @@ use std::fs;
 use std::io::{self, Write};
 use std::path::Path;
 
+use crate::agent;
 use crate::crypto;
 use crate::vault;
 
@@ -19,14 +20,10 @@ pub fn handle_restore(file_path: &str) -> Result<()> {
     println!("Verifying backup file...");
     let password = crypto::prompt_password("Master password for backup: ")?;
 
-    // Try opening the backup with the given password
-    let backup_conn = rusqlite::Connection::open(backup_path)?;
-    backup_conn.pragma_update(None, "key", &password)?;
-    backup_conn
-        .execute_batch("SELECT count(*) FROM sqlite_master;")
-        .map_err(|_| {
-            anyhow::anyhow!("Wrong password or invalid backup file")
-        })?;
+    // Try opening the backup with the given password (and its key file, if it has one)
+    let backup_keys = vault::keyfile::path_for(backup_path);
+    let backup_conn = vault::open_backup(backup_path, &password)
+        .context("Wrong password or invalid backup file")?;
 
     // Count projects in backup
     let project_count: u32 = backup_conn
@@ -59,6 +56,16 @@ pub fn handle_restore(file_path: &str) -> Result<()> {
         let safety = vault_path.with_extension("db.bak");
         fs::copy(&vault_path, &safety)
             .context("Failed to create safety backup")?;
+        let keyfile = vault::keyfile_path();
+        // Where `restore` looks for the key file of the safety backup
+        let safety_keys = vault::keyfile::path_for(&safety);
+        if keyfile.exists() {
+            fs::copy(&keyfile, &safety_keys)
+                .context("Failed to create safety backup of the key file")?;
+        } else if safety_keys.exists() {
+            fs::remove_file(&safety_keys)
+                .context("Failed to remove the old safety backup of the key file")?;
+        }
         println!("  Current vault saved to {}", safety.display().to_string().dimmed());
     }
 
@@ -70,6 +77,18 @@ pub fn handle_restore(file_path: &str) -> Result<()> {
     fs::copy(backup_path, &vault_path)
         .context("Failed to restore backup")?;
 
+    // A backup from before key files opens with its password alone
+    if backup_keys.exists() {
+        fs::copy(&backup_keys, vault::keyfile_path())
+            .context("Failed to restore the key file")?;
+    } else if vault::keyfile_path().exists() {
+        fs::remove_file(vault::keyfile_path())
+            .context("Failed to remove the old key file")?;
+    }
+
+    // The agent holds the key of the vault that was replaced
+    agent::lock();
+
     println!(
         "{} Vault restored from {} ({} projects)",
         "Done.".green().bold(),
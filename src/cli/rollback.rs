@@ -0,0 +1,96 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use std::io::{self, Write};
+
+use crate::unlock;
+use crate::vault;
+use crate::vault::audit::Access;
+use crate::vault::history::RollbackPoint;
+
+pub fn handle_rollback(
+    project_name: &str,
+    key: Option<&str>,
+    to: &str,
+    env: Option<&str>,
+) -> Result<()> {
+    let point = RollbackPoint::parse(to)?;
+
+    let mut conn = unlock::open_vault()
+        .context("Failed to open vault")?;
+
+    let project = vault::project::get_project(&conn, project_name)?;
+    let environment = project.environment_or_default(env);
+    let mut targets = vault::history::values_at(&conn, &project.id, &environment, key, &point)?;
+
+    // Keys added and removed again after the point need no change
+    targets.retain(|key, value| {
+        value.is_some()
+            || vault::variable::get_variable(&conn, &project.id, &environment, key).is_ok()
+    });
+
+    if targets.is_empty() {
+        println!(
+            "Nothing to roll back: {}:{} has not changed since {}.",
+            project_name.cyan(),
+            environment,
+            to
+        );
+        return Ok(());
+    }
+
+    // Preview what will happen to each key
+    println!(
+        "Rolling back {}:{} to {}:",
+        project_name.cyan().bold(),
+        environment,
+        to.bold()
+    );
+    for (key, value) in &targets {
+        let exists = vault::variable::get_variable(&conn, &project.id, &environment, key).is_ok();
+        match (exists, value) {
+            (true, Some(_)) => println!("    {} {}", "~".yellow(), key),
+            (false, Some(_)) => println!("    {} {}", "+".green(), key),
+            (_, None) => println!("    {} {}", "-".red(), key),
+        }
+    }
+    print!("Apply? [y/N]: ");
+    io::stdout().flush()?;
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+
+    if input.trim().to_lowercase() != "y" {
+        println!("Cancelled.");
+        return Ok(());
+    }
+
+    // All or nothing; the rollback itself is recorded so it can be undone too
+    let tx = conn.transaction()?;
+    for (key, value) in &targets {
+        match value {
+            Some(encrypted) => vault::variable::upsert_variable(
+                &tx,
+                &project.id,
+                &environment,
+                key,
+                encrypted,
+                "rollback",
+            )?,
+            None => vault::variable::delete_variable(&tx, &project.id, &environment, key, "rollback")?,
+        }
+    }
+    let keys: Vec<String> = targets.keys().cloned().collect();
+    let project_ref = format!("{}:{}", project_name, environment);
+    vault::audit::record(&tx, "rollback", Access::Modify, Some(&project_ref), &keys)?;
+    tx.commit()?;
+
+    println!(
+        "{} Rolled back {} keys in {}:{}",
+        "Done.".green().bold(),
+        targets.len(),
+        project_name.cyan(),
+        environment
+    );
+
+    Ok(())
+}
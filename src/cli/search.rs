@@ -1,14 +1,13 @@
 use anyhow::{Context, Result};
 use colored::Colorize;
 
-use crate::crypto;
+use crate::unlock;
 use crate::vault;
+use crate::vault::audit::Access;
 
 pub fn handle_search(key: &str) -> Result<()> {
-    let password = crypto::prompt_existing_password()?;
-    let conn = vault::open_vault(&password)
+    let (conn, keyring) = unlock::open_vault_with_keyring()
         .context("Failed to open vault")?;
-    let enc_key = vault::get_encryption_key(&conn, &password)?;
 
     let results = vault::variable::search_key(&conn, key)?;
 
@@ -18,22 +17,32 @@ pub fn handle_search(key: &str) -> Result<()> {
     }
 
     println!(
-        "Found {} in {} projects:",
+        "Found {} in {} project environments:",
         key.cyan().bold(),
         results.len()
     );
 
-    for (project_name, encrypted_value) in &results {
-        let decrypted = crypto::decrypt_value(&enc_key, encrypted_value)?;
+    for (project_name, var) in &results {
+        let decrypted = keyring.decrypt(&conn, &var.encrypted_value, &var.binding())?;
+        let environment = &var.environment;
 
         // Show a short preview (mask if sensitive)
         let preview = if decrypted.len() > 40 {
             format!("{}...", &decrypted[..40])
         } else {
-            decrypted
+            decrypted.to_string()
         };
 
-        println!("  {} {}: {}", "|--".dimmed(), project_name.cyan(), preview);
+        let project_ref = format!("{}:{}", project_name, environment);
+        vault::audit::record(&conn, "search", Access::Decrypt, Some(&project_ref), &[key.to_string()])?;
+
+        println!(
+            "  {} {}:{}: {}",
+            "|--".dimmed(),
+            project_name.cyan(),
+            environment.dimmed(),
+            preview
+        );
     }
 
     Ok(())
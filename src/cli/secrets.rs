@@ -1,21 +1,36 @@
 use anyhow::{Context, Result};
 use colored::Colorize;
+use chrono::DateTime;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+use std::process::Stdio;
 use comfy_table::{Table, presets::UTF8_FULL_CONDENSED};
-use ring::rand::{SecureRandom, SystemRandom};
 
-use crate::cli::SecretsAction;
-use crate::crypto;
+use crate::cli::{HookAction, SecretsAction};
+use crate::crypto::ValueBinding;
+use crate::crypto::generator::{Format, Generator};
+use crate::crypto::secret::SecretString;
 use crate::errors::EnvkeepError;
+use crate::unlock;
 use crate::vault;
+use crate::vault::audit::Access;
 use crate::vault::secret;
 
 pub fn handle_secrets(action: SecretsAction) -> Result<()> {
     match action {
         SecretsAction::Set { pair } => handle_set(&pair),
         SecretsAction::List => handle_list(),
+        SecretsAction::Get { secret } => handle_get(&secret),
+        SecretsAction::Show { secret } => handle_show(&secret),
+        SecretsAction::Rename { secret, new_name } => handle_rename(&secret, &new_name),
+        SecretsAction::Delete { secret, force } => handle_delete(&secret, force),
+        SecretsAction::Promote { key, from, yes } => handle_promote(&key, from.as_deref(), yes),
         SecretsAction::Link { secret, project } => handle_link(&secret, &project),
         SecretsAction::Unlink { secret, project } => handle_unlink(&secret, &project),
-        SecretsAction::Rotate { secret } => handle_rotate(&secret),
+        SecretsAction::Rotate { secret, format, length, charset, prefix, no_hooks } => {
+            handle_rotate(&secret, format, length, charset, prefix, no_hooks)
+        }
+        SecretsAction::Hook { action } => handle_hook(action),
     }
 }
 
@@ -24,12 +39,12 @@ fn handle_set(pair: &str) -> Result<()> {
         .split_once('=')
         .ok_or_else(|| EnvkeepError::InvalidKeyValue(pair.to_string()))?;
 
-    let password = crypto::prompt_existing_password()?;
-    let conn = vault::open_vault(&password)?;
-    let enc_key = vault::get_encryption_key(&conn, &password)?;
+    let (conn, keyring) = unlock::open_vault_with_keyring()?;
 
-    let encrypted = crypto::encrypt_value(&enc_key, value)?;
-    secret::create_secret(&conn, key, &encrypted)?;
+    let id = secret::id_for_key(&conn, key)?;
+    let encrypted = keyring.encrypt(&conn, value, &ValueBinding::Secret { secret_id: &id, key })?;
+    secret::create_secret(&conn, &id, key, &encrypted, "secrets set")?;
+    vault::audit::record(&conn, "secrets set", Access::Modify, None, &[key.to_string()])?;
 
     println!("{} Secret {} stored.", "Done.".green().bold(), (*key).cyan());
 
@@ -37,10 +52,10 @@ fn handle_set(pair: &str) -> Result<()> {
 }
 
 fn handle_list() -> Result<()> {
-    let password = crypto::prompt_existing_password()?;
-    let conn = vault::open_vault(&password)?;
+    let conn = unlock::open_vault()?;
 
     let secrets = secret::list_secrets(&conn)?;
+    vault::audit::record(&conn, "secrets list", Access::Read, None, &[])?;
 
     if secrets.is_empty() {
         println!("No secrets stored.");
@@ -68,11 +83,275 @@ fn handle_list() -> Result<()> {
     Ok(())
 }
 
+/// Print only the raw value, so the output can be captured by scripts.
+fn handle_get(secret_key: &str) -> Result<()> {
+    let (conn, keyring) = unlock::open_vault_with_keyring()?;
+
+    let s = secret::get_secret(&conn, secret_key)?;
+    let value = keyring.decrypt(&conn, &s.encrypted_value, &s.binding())
+        .context(format!("Failed to decrypt secret: {}", secret_key))?;
+    vault::audit::record(&conn, "secrets get", Access::Decrypt, None, &[secret_key.to_string()])?;
+
+    println!("{}", value.as_str());
+
+    Ok(())
+}
+
+fn handle_show(secret_key: &str) -> Result<()> {
+    let conn = unlock::open_vault()?;
+
+    let s = secret::get_secret(&conn, secret_key)?;
+    let projects = secret::get_linked_projects(&conn, secret_key)?;
+    let history = secret::get_secret_history(&conn, &s.id)?;
+    vault::audit::record(&conn, "secrets show", Access::Read, None, &[secret_key.to_string()])?;
+
+    println!("Secret: {}", s.key.cyan().bold());
+    println!("Created: {}", format_time(&s.created_at));
+    println!("Updated: {}", format_time(&s.updated_at));
+    println!("Generator: {}", s.generator().describe());
+    let hooks = vault::hook::list_hooks(&conn, &s.id)?;
+    if !hooks.is_empty() {
+        println!("Rotation hooks: {}", hooks.len());
+    }
+    if projects.is_empty() {
+        println!("Linked projects: (none)");
+    } else {
+        println!("Linked projects: {}", projects.join(", "));
+    }
+
+    if history.is_empty() {
+        return Ok(());
+    }
+
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL_CONDENSED)
+        .set_header(vec!["Rev", "Time (UTC)", "Key", "Change", "Command"]);
+
+    for entry in &history {
+        table.add_row(vec![
+            entry.revision.to_string(),
+            format_time(&entry.changed_at),
+            entry.key.clone(),
+            entry.change().to_string(),
+            entry.command.clone(),
+        ]);
+    }
+
+    println!();
+    println!("{table}");
+
+    Ok(())
+}
+
+fn handle_rename(secret_key: &str, new_name: &str) -> Result<()> {
+    let (conn, keyring) = unlock::open_vault_with_keyring()?;
+
+    // The value is bound to the secret's name, so it is encrypted again under the new one
+    let s = secret::get_secret(&conn, secret_key)?;
+    let value = keyring.decrypt(&conn, &s.encrypted_value, &s.binding())
+        .context(format!("Failed to decrypt secret: {}", secret_key))?;
+    let binding = ValueBinding::Secret { secret_id: &s.id, key: new_name };
+    let encrypted = keyring.encrypt(&conn, &value, &binding)?;
+    secret::rename_secret(&conn, secret_key, new_name, &encrypted)?;
+    vault::audit::record(
+        &conn,
+        "secrets rename",
+        Access::Modify,
+        None,
+        &[secret_key.to_string(), new_name.to_string()],
+    )?;
+
+    println!(
+        "{} Renamed {} to {}",
+        "Done.".green().bold(),
+        secret_key.cyan(),
+        new_name.cyan()
+    );
+
+    Ok(())
+}
+
+fn handle_delete(secret_key: &str, force: bool) -> Result<()> {
+    let mut conn = unlock::open_vault()?;
+
+    let projects = secret::get_linked_projects(&conn, secret_key)?;
+    if !projects.is_empty() && !force {
+        return Err(EnvkeepError::SecretStillLinked(secret_key.to_string(), projects.len()).into());
+    }
+
+    // All or nothing: the secret, its links, history, hooks and key
+    let tx = conn.transaction()?;
+    secret::delete_secret(&tx, secret_key)?;
+    vault::audit::record(&tx, "secrets delete", Access::Delete, None, &[secret_key.to_string()])?;
+    tx.commit()?;
+
+    println!("{} Deleted secret {}", "Done.".green().bold(), secret_key.cyan());
+    if !projects.is_empty() {
+        println!("  Unlinked from: {}", projects.join(", "));
+    }
+
+    Ok(())
+}
+
+fn handle_promote(key: &str, from: Option<&str>, yes: bool) -> Result<()> {
+    let (mut conn, keyring) = unlock::open_vault_with_keyring()?;
+
+    if secret::get_secret(&conn, key).is_ok() {
+        return Err(EnvkeepError::SecretAlreadyExists(key.to_string()).into());
+    }
+
+    // Decrypt every variable once; values are compared in plaintext
+    let mut all = Vec::new();
+    for (project_name, var) in vault::variable::list_all_variables(&conn)? {
+        let value = keyring.decrypt(&conn, &var.encrypted_value, &var.binding())
+            .context(format!("Failed to decrypt {}:{} {}", project_name, var.environment, var.key))?;
+        all.push((project_name, var, value));
+    }
+
+    let value = match from {
+        Some(from) => {
+            let (name, env) = vault::project::split_ref(from);
+            let project = vault::project::get_project(&conn, name)?;
+            let environment = project.environment_or_default(env);
+            all.iter()
+                .find(|(p, v, _)| p == name && v.environment == environment && v.key == key)
+                .map(|(_, _, value)| value.clone())
+                .ok_or_else(|| {
+                    EnvkeepError::VariableNotFound(format!("{}:{}", name, environment), key.to_string())
+                })?
+        }
+        None => {
+            let values: BTreeSet<&str> = all
+                .iter()
+                .filter(|(_, v, _)| v.key == key)
+                .map(|(_, _, value)| value.as_str())
+                .collect();
+            match values.len() {
+                0 => return Err(EnvkeepError::VariableNotFound("any project".to_string(), key.to_string()).into()),
+                1 => values.into_iter().next().map(SecretString::from).unwrap_or_default(),
+                n => return Err(EnvkeepError::AmbiguousPromotion(key.to_string(), n).into()),
+            }
+        }
+    };
+
+    // Copies under the same key are replaced by the secret. Other keys are only
+    // reported: a link exposes the secret under its own name, so it would add
+    // `key` next to them rather than replace them.
+    let duplicates: Vec<&(String, vault::Variable, SecretString)> = all
+        .iter()
+        .filter(|(_, v, val)| v.key == key && *val == value)
+        .collect();
+    let other_keys: Vec<&(String, vault::Variable, SecretString)> = all
+        .iter()
+        .filter(|(_, v, val)| v.key != key && *val == value)
+        .collect();
+
+    let mut projects: BTreeMap<String, String> = BTreeMap::new();
+    for (project_name, var, _) in &duplicates {
+        projects.insert(project_name.clone(), var.project_id.clone());
+    }
+
+    // Environments of those projects that did not have the key will now see the secret
+    let mut gaining = Vec::new();
+    for project_name in projects.keys() {
+        let project = vault::project::get_project(&conn, project_name)?;
+        for (environment, _) in vault::project::list_environments(&conn, &project)? {
+            let has_key = all
+                .iter()
+                .any(|(p, v, _)| p == project_name && v.environment == environment && v.key == key);
+            if !has_key {
+                gaining.push(format!("{}:{}", project_name, environment));
+            }
+        }
+    }
+
+    println!("Promoting {} to a shared secret:", key.cyan().bold());
+    for (project_name, var, _) in &duplicates {
+        println!("    {} {}:{} (variable removed, secret linked)", "~".yellow(), project_name, var.environment);
+    }
+    for (project_name, var, _) in all.iter().filter(|(_, v, val)| v.key == key && *val != value) {
+        println!(
+            "    {} {}:{} keeps its own, different value {}",
+            "!".yellow(),
+            project_name,
+            var.environment,
+            "(overrides the secret)".dimmed()
+        );
+    }
+    for project_ref in &gaining {
+        println!("    {} {} (did not have {}, will now get the secret)", "+".green(), project_ref, key);
+    }
+    for (project_name, var, _) in &other_keys {
+        println!(
+            "    {} {}:{} {} has the same value {}",
+            "=".dimmed(),
+            project_name,
+            var.environment,
+            var.key,
+            format!("(not changed: a link would add {}, not replace {})", key, var.key).dimmed()
+        );
+    }
+
+    if !yes {
+        print!("Apply? [y/N]: ");
+        io::stdout().flush()?;
+
+        let mut input = String::new();
+        io::stdin().read_line(&mut input)?;
+
+        if input.trim().to_lowercase() != "y" {
+            println!("Cancelled.");
+            return Ok(());
+        }
+    }
+
+    // All or nothing
+    let tx = conn.transaction()?;
+    let id = secret::id_for_key(&tx, key)?;
+    let encrypted = keyring.encrypt(&tx, &value, &ValueBinding::Secret { secret_id: &id, key })?;
+    secret::create_secret(&tx, &id, key, &encrypted, "secrets promote")?;
+    for project_name in projects.keys() {
+        secret::link_secret(&tx, key, project_name)?;
+    }
+    for (_, var, _) in &duplicates {
+        vault::variable::delete_variable(&tx, &var.project_id, &var.environment, key, "secrets promote")?;
+    }
+    for (project_name, var, _) in &duplicates {
+        let project_ref = format!("{}:{}", project_name, var.environment);
+        vault::audit::record(&tx, "secrets promote", Access::Modify, Some(&project_ref), &[key.to_string()])?;
+    }
+    tx.commit()?;
+
+    println!(
+        "{} Promoted {}: linked to {} projects, removed {} copies",
+        "Done.".green().bold(),
+        key.cyan(),
+        projects.len(),
+        duplicates.len()
+    );
+
+    Ok(())
+}
+
+/// Show an RFC 3339 timestamp as `YYYY-MM-DD HH:MM:SS`.
+fn format_time(timestamp: &str) -> String {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|_| timestamp.to_string())
+}
+
 fn handle_link(secret_key: &str, project_name: &str) -> Result<()> {
-    let password = crypto::prompt_existing_password()?;
-    let conn = vault::open_vault(&password)?;
+    let conn = unlock::open_vault()?;
 
     secret::link_secret(&conn, secret_key, project_name)?;
+    vault::audit::record(
+        &conn,
+        "secrets link",
+        Access::Modify,
+        Some(project_name),
+        &[secret_key.to_string()],
+    )?;
 
     println!(
         "{} Linked {} to {}",
@@ -85,10 +364,16 @@ fn handle_link(secret_key: &str, project_name: &str) -> Result<()> {
 }
 
 fn handle_unlink(secret_key: &str, project_name: &str) -> Result<()> {
-    let password = crypto::prompt_existing_password()?;
-    let conn = vault::open_vault(&password)?;
+    let conn = unlock::open_vault()?;
 
     secret::unlink_secret(&conn, secret_key, project_name)?;
+    vault::audit::record(
+        &conn,
+        "secrets unlink",
+        Access::Modify,
+        Some(project_name),
+        &[secret_key.to_string()],
+    )?;
 
     println!(
         "{} Unlinked {} from {}",
@@ -100,31 +385,210 @@ fn handle_unlink(secret_key: &str, project_name: &str) -> Result<()> {
     Ok(())
 }
 
-fn handle_rotate(secret_key: &str) -> Result<()> {
-    let password = crypto::prompt_existing_password()?;
-    let conn = vault::open_vault(&password)?;
-    let enc_key = vault::get_encryption_key(&conn, &password)?;
+fn handle_rotate(
+    secret_key: &str,
+    format: Option<Format>,
+    length: Option<usize>,
+    charset: Option<String>,
+    prefix: Option<String>,
+    no_hooks: bool,
+) -> Result<()> {
+    let (mut conn, keyring) = unlock::open_vault_with_keyring()?;
+
+    let existing = secret::get_secret(&conn, secret_key)?;
+
+    // Options given now replace the stored generator; otherwise reuse it
+    let changed = format.is_some() || length.is_some() || charset.is_some() || prefix.is_some();
+    let generator = if changed {
+        let stored = existing.generator();
+        let format = format.unwrap_or(stored.format);
+        // A new format starts from that format's defaults
+        let keep = format == stored.format;
+        Generator::new(
+            format,
+            length.or(if keep { stored.length } else { None }),
+            charset.or(if keep { stored.charset } else { None }),
+            prefix.or(stored.prefix),
+        )?
+    } else {
+        existing.generator()
+    };
+
+    let new_value = generator.generate()?;
+    let encrypted = keyring.encrypt(&conn, &new_value, &existing.binding())?;
 
-    // Verify the secret exists
-    secret::get_secret(&conn, secret_key)?;
+    // Store first, so a crash while hooks run never leaves consumers ahead of the vault
+    let tx = conn.transaction()?;
+    secret::create_secret(&tx, &existing.id, secret_key, &encrypted, "secrets rotate")?;
+    vault::audit::record(&tx, "secrets rotate", Access::Rotate, None, &[secret_key.to_string()])?;
+    tx.commit()?;
 
-    // Generate a new random value (32 bytes, hex-encoded)
-    let rng = SystemRandom::new();
-    let mut bytes = [0u8; 32];
-    rng.fill(&mut bytes)
-        .map_err(|_| EnvkeepError::EncryptionError("RNG failed".to_string()))?;
-    let new_value: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    let hooks = if no_hooks {
+        Vec::new()
+    } else {
+        vault::hook::list_hooks(&conn, &existing.id)?
+    };
 
-    let encrypted = crypto::encrypt_value(&enc_key, &new_value)?;
-    secret::create_secret(&conn, secret_key, &encrypted)?;
+    let mut done = Vec::new();
+    for hook in &hooks {
+        match run_hook(secret_key, hook, &new_value) {
+            Ok(()) => {
+                println!("  {} hook #{}: {}", "ok".green(), hook.id, hook.command);
+                done.push(hook);
+            }
+            Err(reason) => {
+                println!("  {} hook #{}: {} ({})", "failed".red(), hook.id, hook.command, reason);
+
+                // Put the previous value back, in the vault and in every consumer already updated
+                let tx = conn.transaction()?;
+                secret::create_secret(
+                    &tx,
+                    &existing.id,
+                    secret_key,
+                    &existing.encrypted_value,
+                    "secrets rotate (rolled back)",
+                )?;
+                vault::audit::record(
+                    &tx,
+                    "secrets rotate (rolled back)",
+                    Access::Rotate,
+                    None,
+                    &[secret_key.to_string()],
+                )?;
+                tx.commit()?;
+
+                let old_value = keyring.decrypt(&conn, &existing.encrypted_value, &existing.binding())
+                    .context(format!("Failed to decrypt secret: {}", secret_key))?;
+                for hook in done.iter().rev() {
+                    match run_hook(secret_key, hook, &old_value) {
+                        Ok(()) => println!("  {} hook #{} with the previous value", "restored".yellow(), hook.id),
+                        Err(reason) => println!(
+                            "  {} hook #{} could not be restored ({}); fix it by hand",
+                            "Warning:".yellow(),
+                            hook.id,
+                            reason
+                        ),
+                    }
+                }
+
+                println!("{} {} keeps its previous value.", "Rolled back.".yellow().bold(), secret_key.cyan());
+                return Err(EnvkeepError::HookFailed(hook.id, reason).into());
+            }
+        }
+    }
+
+    if changed {
+        secret::set_generator(&conn, secret_key, &generator)?;
+    }
 
     println!(
-        "{} Rotated {}. New value: {}",
+        "{} Rotated {} ({}). New value: {}",
         "Done.".green().bold(),
         secret_key.cyan(),
+        generator.describe(),
         new_value.dimmed()
     );
     println!("{}", "Copy this value now -- it will not be shown again.".yellow());
 
+    // Which projects see the new value, and which override it with their own variable
+    let projects = secret::get_linked_projects(&conn, secret_key)?;
+    if projects.is_empty() {
+        println!("  Not linked to any project.");
+        return Ok(());
+    }
+
+    let overrides: Vec<String> = vault::variable::search_key(&conn, secret_key)?
+        .into_iter()
+        .filter(|(project_name, _)| projects.contains(project_name))
+        .map(|(project_name, var)| format!("{}:{}", project_name, var.environment))
+        .collect();
+
+    println!("  Picked up by: {}", projects.join(", "));
+    if !overrides.is_empty() {
+        println!(
+            "  {} these override it with their own {} variable: {}",
+            "Note:".yellow(),
+            secret_key,
+            overrides.join(", ")
+        );
+    }
+    println!("  Run {} in those projects to refresh .env.", "envkeep use <project>".cyan());
+
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Run one hook with `sh -c` (`cmd /C` on Windows). The value goes to stdin
+/// only, never into argv or the environment; `ENVKEEP_SECRET` holds the
+/// secret's name.
+fn run_hook(secret_key: &str, hook: &vault::hook::Hook, value: &str) -> Result<(), String> {
+    let mut child = unlock::shell(&hook.command)
+        .env("ENVKEEP_SECRET", secret_key)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // A hook that does not read its input is fine
+        if let Err(e) = stdin.write_all(value.as_bytes())
+            && e.kind() != io::ErrorKind::BrokenPipe
+        {
+            return Err(e.to_string());
+        }
+    }
+
+    let status = child.wait().map_err(|e| e.to_string())?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(status.to_string())
+    }
+}
+
+fn handle_hook(action: HookAction) -> Result<()> {
+    let conn = unlock::open_vault()?;
+
+    match action {
+        HookAction::Add { secret, command } => {
+            let s = secret::get_secret(&conn, &secret)?;
+            let id = vault::hook::add_hook(&conn, &s.id, &command)?;
+            vault::audit::record(&conn, "secrets hook add", Access::Modify, None, std::slice::from_ref(&secret))?;
+
+            println!(
+                "{} Added hook #{} to {}. It runs after each rotation with the new value on stdin.",
+                "Done.".green().bold(),
+                id,
+                secret.cyan()
+            );
+        }
+        HookAction::List { secret } => {
+            let s = secret::get_secret(&conn, &secret)?;
+            let hooks = vault::hook::list_hooks(&conn, &s.id)?;
+            vault::audit::record(&conn, "secrets hook list", Access::Read, None, std::slice::from_ref(&secret))?;
+
+            if hooks.is_empty() {
+                println!("No hooks for {}.", secret.cyan());
+                return Ok(());
+            }
+
+            let mut table = Table::new();
+            table
+                .load_preset(UTF8_FULL_CONDENSED)
+                .set_header(vec!["Id", "Command", "Added"]);
+            for hook in &hooks {
+                table.add_row(vec![hook.id.to_string(), hook.command.clone(), format_time(&hook.created_at)]);
+            }
+            println!("{table}");
+        }
+        HookAction::Remove { secret, id } => {
+            let s = secret::get_secret(&conn, &secret)?;
+            if !vault::hook::remove_hook(&conn, &s.id, id)? {
+                return Err(EnvkeepError::HookNotFound(secret, id).into());
+            }
+            vault::audit::record(&conn, "secrets hook remove", Access::Modify, None, std::slice::from_ref(&secret))?;
+
+            println!("{} Removed hook #{} from {}", "Done.".green().bold(), id, secret.cyan());
+        }
+    }
+
+    Ok(())
+}
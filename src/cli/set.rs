@@ -0,0 +1,55 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+
+use crate::crypto::ValueBinding;
+use crate::errors::EnvkeepError;
+use crate::unlock;
+use crate::vault;
+use crate::vault::audit::Access;
+
+pub fn handle_set(project_name: &str, pairs: &[String], env: Option<&str>) -> Result<()> {
+    // Parse everything before touching the vault
+    let mut parsed = Vec::new();
+    for pair in pairs {
+        let (key, value) = pair
+            .split_once('=')
+            .ok_or_else(|| EnvkeepError::InvalidKeyValue(pair.to_string()))?;
+        if key.trim().is_empty() {
+            return Err(EnvkeepError::InvalidKeyValue(pair.to_string()).into());
+        }
+        parsed.push((key.trim(), value));
+    }
+
+    let (mut conn, keyring) = unlock::open_vault_with_keyring()
+        .context("Failed to open vault")?;
+
+    let project = vault::project::get_project(&conn, project_name)?;
+    let environment = project.environment_or_default(env);
+    vault::project::validate_name(&environment)?;
+
+    let tx = conn.transaction()?;
+    for (key, value) in &parsed {
+        let binding = ValueBinding::Variable {
+            project_id: &project.id,
+            environment: &environment,
+            key,
+        };
+        let encrypted = keyring.encrypt(&tx, value, &binding)
+            .context(format!("Failed to encrypt variable: {}", key))?;
+        vault::variable::upsert_variable(&tx, &project.id, &environment, key, &encrypted, "set")?;
+    }
+
+    let keys: Vec<String> = parsed.iter().map(|(k, _)| k.to_string()).collect();
+    let project_ref = format!("{}:{}", project_name, environment);
+    vault::audit::record(&tx, "set", Access::Modify, Some(&project_ref), &keys)?;
+    tx.commit()?;
+
+    println!(
+        "{} Set {} in {}",
+        "Done.".green().bold(),
+        keys.join(", ").cyan(),
+        project_ref.cyan().bold()
+    );
+
+    Ok(())
+}
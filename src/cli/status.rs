@@ -1,15 +1,16 @@
 use anyhow::{Context, Result};
 use colored::Colorize;
 
-use crate::crypto;
+use crate::unlock;
 use crate::vault;
+use crate::vault::audit::Access;
 
 pub fn handle_status() -> Result<()> {
-    let password = crypto::prompt_existing_password()?;
-    let conn = vault::open_vault(&password)?;
+    let conn = unlock::open_vault()?;
 
     // Find the most recently used project
     let projects = vault::project::list_projects(&conn)?;
+    vault::audit::record(&conn, "status", Access::Read, None, &[])?;
 
     if projects.is_empty() {
         println!("No projects in vault.");
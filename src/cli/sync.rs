@@ -1,19 +1,26 @@
 use anyhow::{Context, Result};
 use colored::Colorize;
 
-use crate::crypto;
+use crate::crypto::ValueBinding;
+use crate::unlock;
 use crate::vault;
+use crate::vault::audit::Access;
 
 pub fn handle_sync(from_name: &str, to_name: &str) -> Result<()> {
-    let password = crypto::prompt_existing_password()?;
-    let conn = vault::open_vault(&password)?;
-    let enc_key = vault::get_encryption_key(&conn, &password)?;
+    let (conn, keyring) = unlock::open_vault_with_keyring()?;
+
+    // Each side is `project[:environment]`
+    let (from_name, from_env) = vault::project::split_ref(from_name);
+    let (to_name, to_env) = vault::project::split_ref(to_name);
 
     let from_project = vault::project::get_project(&conn, from_name)?;
     let to_project = vault::project::get_project(&conn, to_name)?;
 
-    let from_vars = vault::variable::get_variables(&conn, &from_project.id)?;
-    let to_vars = vault::variable::get_variables(&conn, &to_project.id)?;
+    let from_env = from_project.environment_or_default(from_env);
+    let to_env = to_project.environment_or_default(to_env);
+
+    let from_vars = vault::variable::get_variables(&conn, &from_project.id, &from_env)?;
+    let to_vars = vault::variable::get_variables(&conn, &to_project.id, &to_env)?;
 
     // Find common keys that exist in both projects
     let to_keys: std::collections::HashSet<String> =
@@ -27,6 +34,7 @@ pub fn handle_sync(from_name: &str, to_name: &str) -> Result<()> {
 
     let mut synced = 0;
     let mut skipped = 0;
+    let mut copied = Vec::new();
 
     for var in &from_vars {
         // Only sync vars with common prefixes OR that already exist in target
@@ -38,14 +46,26 @@ pub fn handle_sync(from_name: &str, to_name: &str) -> Result<()> {
             continue;
         }
 
-        // Copy the variable (preserving encryption)
+        // Values are bound to their row, so re-encrypt for the target
+        let value = keyring.decrypt(&conn, &var.encrypted_value, &var.binding())
+            .context(format!("Failed to decrypt variable: {}", var.key))?;
+        let binding = ValueBinding::Variable {
+            project_id: &to_project.id,
+            environment: &to_env,
+            key: &var.key,
+        };
+        let encrypted = keyring.encrypt(&conn, &value, &binding)?;
         vault::variable::upsert_variable(
             &conn,
             &to_project.id,
+            &to_env,
             &var.key,
-            &var.encrypted_value,
+            &encrypted,
+            "sync",
         )?;
 
+        copied.push(var.key.clone());
+
         if to_keys.contains(&var.key) {
             skipped += 1; // Updated existing
         } else {
@@ -53,11 +73,14 @@ pub fn handle_sync(from_name: &str, to_name: &str) -> Result<()> {
         }
     }
 
+    let to_ref = format!("{}:{}", to_name, to_env);
+    vault::audit::record(&conn, "sync", Access::Modify, Some(&to_ref), &copied)?;
+
     println!(
         "{} Synced {} -> {}: {} new, {} updated",
         "Done.".green().bold(),
-        from_name.cyan(),
-        to_name.cyan(),
+        format!("{}:{}", from_name, from_env).cyan(),
+        format!("{}:{}", to_name, to_env).cyan(),
         synced,
         skipped
     );
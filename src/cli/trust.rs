@@ -0,0 +1,89 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use comfy_table::{Table, presets::UTF8_FULL_CONDENSED};
+
+use crate::cli::TrustAction;
+use crate::crypto::signing;
+use crate::unlock;
+use crate::vault;
+use crate::vault::audit::Access;
+
+pub fn handle_trust(action: TrustAction) -> Result<()> {
+    match action {
+        TrustAction::Add { key, name } => handle_add(&key, &name),
+        TrustAction::List => handle_list(),
+        TrustAction::Remove { signer } => handle_remove(&signer),
+    }
+}
+
+fn handle_add(key: &str, name: &str) -> Result<()> {
+    // Store the key as printed, so lookups compare like for like
+    let public_key = signing::parse_key(key)?;
+    let key = signing::format_key(&public_key);
+
+    let conn = unlock::open_vault()
+        .context("Failed to open vault")?;
+
+    vault::trust::add_signer(&conn, &key, name)
+        .context(format!("Failed to trust {} (is the name already used?)", name))?;
+    vault::audit::record(&conn, "trust add", Access::Modify, None, &[name.to_string()])?;
+
+    println!(
+        "{} Trusting exports signed by {} ({})",
+        "Done.".green().bold(),
+        name.cyan(),
+        signing::fingerprint(&public_key)
+    );
+
+    Ok(())
+}
+
+fn handle_list() -> Result<()> {
+    let conn = unlock::open_vault()
+        .context("Failed to open vault")?;
+
+    let signers = vault::trust::list_signers(&conn)?;
+    vault::audit::record(&conn, "trust list", Access::Read, None, &[])?;
+
+    if signers.is_empty() {
+        println!("No trusted signers.");
+        println!("  Add one with {}", "envkeep trust add <key> --name <name>".cyan());
+        return Ok(());
+    }
+
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL_CONDENSED)
+        .set_header(vec!["Name", "Fingerprint", "Key", "Added"]);
+
+    for signer in &signers {
+        let fingerprint = signing::parse_key(&signer.public_key)
+            .map(|key| signing::fingerprint(&key))
+            .unwrap_or_else(|_| "(invalid)".to_string());
+        table.add_row(vec![
+            signer.name.clone(),
+            fingerprint,
+            signer.public_key.clone(),
+            signer.added_at.clone(),
+        ]);
+    }
+
+    println!("{table}");
+
+    Ok(())
+}
+
+fn handle_remove(signer: &str) -> Result<()> {
+    let conn = unlock::open_vault()
+        .context("Failed to open vault")?;
+
+    if !vault::trust::remove_signer(&conn, signer)? {
+        println!("No trusted signer named {}.", signer.cyan());
+        return Ok(());
+    }
+    vault::audit::record(&conn, "trust remove", Access::Delete, None, &[signer.to_string()])?;
+
+    println!("{} No longer trusting {}", "Done.".green().bold(), signer.cyan());
+
+    Ok(())
+}
@@ -2,16 +2,17 @@ use anyhow::{Context, Result};
 use colored::Colorize;
 use comfy_table::{Table, presets::UTF8_FULL_CONDENSED};
 
-use crate::crypto;
+use crate::unlock;
 use crate::vault;
+use crate::vault::audit::Access;
 
 pub fn handle_types(project_name: &str) -> Result<()> {
-    let password = crypto::prompt_existing_password()?;
-    let conn = vault::open_vault(&password)?;
-    let enc_key = vault::get_encryption_key(&conn, &password)?;
+    let (conn, keyring) = unlock::open_vault_with_keyring()?;
 
+    let (project_name, env) = vault::project::split_ref(project_name);
     let project = vault::project::get_project(&conn, project_name)?;
-    let variables = vault::variable::get_variables(&conn, &project.id)?;
+    let environment = project.environment_or_default(env);
+    let variables = vault::variable::get_variables(&conn, &project.id, &environment)?;
 
     if variables.is_empty() {
         println!("Project {} has no variables.", project_name.cyan());
@@ -24,12 +25,16 @@ pub fn handle_types(project_name: &str) -> Result<()> {
         .set_header(vec!["Key", "Inferred Type", "Example"]);
 
     for var in &variables {
-        let value = crypto::decrypt_value(&enc_key, &var.encrypted_value)?;
+        let value = keyring.decrypt(&conn, &var.encrypted_value, &var.binding())?;
         let (var_type, example) = infer_type(&value);
 
         table.add_row(vec![var.key.clone(), var_type.to_string(), example]);
     }
 
+    let keys: Vec<String> = variables.iter().map(|v| v.key.clone()).collect();
+    let project_ref = format!("{}:{}", project_name, environment);
+    vault::audit::record(&conn, "types", Access::Decrypt, Some(&project_ref), &keys)?;
+
     println!(
         "Type inference for {} ({} variables):",
         project_name.cyan().bold(),
@@ -0,0 +1,39 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+
+use crate::errors::EnvkeepError;
+use crate::unlock;
+use crate::vault;
+use crate::vault::audit::Access;
+
+pub fn handle_unset(project_name: &str, keys: &[String], env: Option<&str>) -> Result<()> {
+    let mut conn = unlock::open_vault()
+        .context("Failed to open vault")?;
+
+    let project = vault::project::get_project(&conn, project_name)?;
+    let environment = project.environment_or_default(env);
+    let project_ref = format!("{}:{}", project_name, environment);
+
+    // Refuse the whole batch if any key is missing
+    for key in keys {
+        if vault::variable::get_variable(&conn, &project.id, &environment, key).is_err() {
+            return Err(EnvkeepError::VariableNotFound(project_ref, key.clone()).into());
+        }
+    }
+
+    let tx = conn.transaction()?;
+    for key in keys {
+        vault::variable::delete_variable(&tx, &project.id, &environment, key, "unset")?;
+    }
+    vault::audit::record(&tx, "unset", Access::Delete, Some(&project_ref), keys)?;
+    tx.commit()?;
+
+    println!(
+        "{} Removed {} from {}",
+        "Done.".green().bold(),
+        keys.join(", ").cyan(),
+        project_ref.cyan().bold()
+    );
+
+    Ok(())
+}
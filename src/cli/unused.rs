@@ -3,16 +3,18 @@ use colored::Colorize;
 use std::fs;
 use std::path::Path;
 
-use crate::crypto;
+use crate::unlock;
 use crate::vault;
+use crate::vault::audit::Access;
 
 pub fn handle_unused(project_name: &str) -> Result<()> {
-    let password = crypto::prompt_existing_password()?;
-    let conn = vault::open_vault(&password)
+    let conn = unlock::open_vault()
         .context("Failed to open vault")?;
 
+    let (project_name, env) = vault::project::split_ref(project_name);
     let project = vault::project::get_project(&conn, project_name)?;
-    let variables = vault::variable::get_variables(&conn, &project.id)?;
+    let environment = project.environment_or_default(env);
+    let variables = vault::variable::get_variables(&conn, &project.id, &environment)?;
 
     if variables.is_empty() {
         println!("Project {} has no variables.", project_name.cyan());
@@ -47,6 +49,9 @@ pub fn handle_unused(project_name: &str) -> Result<()> {
         search_dir.dimmed()
     );
 
+    let project_ref = format!("{}:{}", project_name, environment);
+    vault::audit::record(&conn, "unused", Access::Read, Some(&project_ref), &[])?;
+
     // Collect all source file contents
     let source_content = collect_source_files(search_path)?;
 
@@ -0,0 +1,165 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use std::collections::BTreeMap;
+use std::env;
+use std::io::{self, Write};
+
+use crate::cli::UpdatePolicy;
+use crate::crypto::ValueBinding;
+use crate::crypto::secret::SecretString;
+use crate::env_parser;
+use crate::errors::EnvkeepError;
+use crate::unlock;
+use crate::vault;
+use crate::vault::audit::Access;
+
+/// One difference between .env and the vault.
+enum Change {
+    Added(SecretString),
+    Changed(SecretString),
+    Removed,
+}
+
+impl UpdatePolicy {
+    /// Whether this policy writes the given change to the vault.
+    fn applies(&self, change: &Change) -> bool {
+        match (self, change) {
+            (_, Change::Added(_)) => true,
+            (UpdatePolicy::MergeKeepVault, Change::Changed(_)) => false,
+            (_, Change::Changed(_)) => true,
+            (UpdatePolicy::Replace, Change::Removed) => true,
+            (_, Change::Removed) => false,
+        }
+    }
+}
+
+pub fn handle_update(
+    project_name: &str,
+    env: Option<&str>,
+    policy: UpdatePolicy,
+    yes: bool,
+) -> Result<()> {
+    let cwd = env::current_dir().context("Could not determine current directory")?;
+    let env_path = cwd.join(".env");
+
+    if !env_path.exists() {
+        return Err(EnvkeepError::NoEnvFile.into());
+    }
+
+    let file_vars = env_parser::parse_env_file(&env_path)
+        .context("Failed to parse .env file")?;
+
+    let (mut conn, keyring) = unlock::open_vault_with_keyring()
+        .context("Failed to open vault")?;
+
+    let project = vault::project::get_project(&conn, project_name)?;
+    let environment = project.environment_or_default(env);
+    let project_ref = format!("{}:{}", project_name, environment);
+
+    let mut vault_vars = BTreeMap::new();
+    for var in vault::variable::get_variables(&conn, &project.id, &environment)? {
+        let value = keyring.decrypt(&conn, &var.encrypted_value, &var.binding())
+            .context(format!("Failed to decrypt variable: {}", var.key))?;
+        vault_vars.insert(var.key, value);
+    }
+
+    // Compare both sides key by key
+    let mut changes = BTreeMap::new();
+    for (key, value) in &file_vars {
+        match vault_vars.get(key) {
+            None => {
+                changes.insert(key.clone(), Change::Added(value.clone()));
+            }
+            Some(current) if current != value => {
+                changes.insert(key.clone(), Change::Changed(value.clone()));
+            }
+            Some(_) => {}
+        }
+    }
+    for key in vault_vars.keys() {
+        if !file_vars.contains_key(key) {
+            changes.insert(key.clone(), Change::Removed);
+        }
+    }
+
+    if changes.is_empty() {
+        println!("{} is already up to date with .env.", project_ref.cyan());
+        return Ok(());
+    }
+
+    // Report every difference, and whether the policy applies it
+    println!("Changes between .env and {}:", project_ref.cyan().bold());
+    for (key, change) in &changes {
+        let marker = match change {
+            Change::Added(_) => "+".green(),
+            Change::Changed(_) => "~".yellow(),
+            Change::Removed => "-".red(),
+        };
+        if policy.applies(change) {
+            println!("    {} {}", marker, key);
+        } else {
+            println!("    {} {} {}", marker, key, "(kept from vault)".dimmed());
+        }
+    }
+
+    changes.retain(|_, change| policy.applies(change));
+
+    if changes.is_empty() {
+        println!("Nothing to apply with this policy.");
+        return Ok(());
+    }
+
+    if !yes {
+        print!("Apply {} changes? [y/N]: ", changes.len());
+        io::stdout().flush()?;
+
+        let mut input = String::new();
+        io::stdin().read_line(&mut input)?;
+
+        if input.trim().to_lowercase() != "y" {
+            println!("Cancelled.");
+            return Ok(());
+        }
+    }
+
+    // All or nothing
+    let tx = conn.transaction()?;
+    let (mut added, mut changed, mut removed) = (0, 0, 0);
+    for (key, change) in &changes {
+        match change {
+            Change::Added(value) | Change::Changed(value) => {
+                let binding = ValueBinding::Variable {
+                    project_id: &project.id,
+                    environment: &environment,
+                    key,
+                };
+                let encrypted = keyring.encrypt(&tx, value, &binding)
+                    .context(format!("Failed to encrypt variable: {}", key))?;
+                vault::variable::upsert_variable(&tx, &project.id, &environment, key, &encrypted, "update")?;
+                if matches!(change, Change::Added(_)) {
+                    added += 1;
+                } else {
+                    changed += 1;
+                }
+            }
+            Change::Removed => {
+                vault::variable::delete_variable(&tx, &project.id, &environment, key, "update")?;
+                removed += 1;
+            }
+        }
+    }
+    let keys: Vec<String> = changes.keys().cloned().collect();
+    vault::audit::record(&tx, "update", Access::Modify, Some(&project_ref), &keys)?;
+    tx.commit()?;
+
+    println!(
+        "{} Updated {}: {} added, {} changed, {} removed",
+        "Done.".green().bold(),
+        project_ref.cyan().bold(),
+        added,
+        changed,
+        removed
+    );
+
+    Ok(())
+}
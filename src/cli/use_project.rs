@@ -3,38 +3,54 @@ use colored::Colorize;
 use std::collections::BTreeMap;
 use std::env;
 
-use crate::crypto;
 use crate::env_parser;
+use crate::unlock;
 use crate::vault;
+use crate::vault::audit::Access;
+use crate::vault::resolve::Source;
 
-pub fn handle_use(project_name: &str) -> Result<()> {
-    let password = crypto::prompt_existing_password()?;
-    let conn = vault::open_vault(&password)
+pub fn handle_use(project_name: &str, env: Option<&str>) -> Result<()> {
+    let (conn, keyring) = unlock::open_vault_with_keyring()
         .context("Failed to open vault")?;
-    let enc_key = vault::get_encryption_key(&conn, &password)?;
 
     // Get the project
     let project = vault::project::get_project(&conn, project_name)?;
 
-    // Get all variables
-    let variables = vault::variable::get_variables(&conn, &project.id)?;
+    let environment = project.environment_or_default(env);
 
-    if variables.is_empty() {
+    // Project variables plus linked shared secrets
+    let resolved = vault::resolve::resolve(&conn, &project, &environment)?;
+
+    if resolved.values.is_empty() {
         println!(
-            "{} Project {} has no variables.",
+            "{} Project {} has no variables in {}.",
             "Warning:".yellow(),
-            project_name.cyan()
+            project_name.cyan(),
+            environment
         );
         return Ok(());
     }
 
+    for key in &resolved.conflicts {
+        println!(
+            "{} {} is set in the project and by a linked shared secret; using the project value.",
+            "Warning:".yellow(),
+            key.cyan()
+        );
+    }
+
     // Decrypt all values
     let mut vars = BTreeMap::new();
-    for var in &variables {
-        let decrypted = crypto::decrypt_value(&enc_key, &var.encrypted_value)
-            .context(format!("Failed to decrypt variable: {}", var.key))?;
-        vars.insert(var.key.clone(), decrypted);
+    for (key, value) in &resolved.values {
+        let decrypted = keyring.decrypt(&conn, &value.encrypted_value, &value.binding(key, &environment))
+            .context(format!("Failed to decrypt variable: {}", key))?;
+        vars.insert(key.clone(), decrypted);
     }
+    let shared = resolved
+        .values
+        .values()
+        .filter(|v| v.source == Source::Secret)
+        .count();
 
     // Write .env to current directory
     let cwd = env::current_dir().context("Could not determine current directory")?;
@@ -53,10 +69,16 @@ pub fn handle_use(project_name: &str) -> Result<()> {
     // Update last_used_at
     vault::project::touch_project(&conn, project_name)?;
 
+    let keys: Vec<String> = vars.keys().cloned().collect();
+    let project_ref = format!("{}:{}", project_name, environment);
+    vault::audit::record(&conn, "use", Access::WriteFile, Some(&project_ref), &keys)?;
+
     println!(
-        "{} Wrote {} variables to .env",
+        "{} Wrote {} variables to .env ({}, {} from shared secrets)",
         "Done.".green().bold(),
-        variables.len()
+        vars.len(),
+        environment,
+        shared
     );
 
     Ok(())
@@ -1,8 +1,9 @@
 use anyhow::{Context, Result};
 use colored::Colorize;
 
-use crate::crypto;
+use crate::unlock;
 use crate::vault;
+use crate::vault::audit::Access;
 
 #[derive(Debug)]
 struct ValidationIssue {
@@ -18,12 +19,12 @@ enum Severity {
 }
 
 pub fn handle_validate(project_name: &str) -> Result<()> {
-    let password = crypto::prompt_existing_password()?;
-    let conn = vault::open_vault(&password)?;
-    let enc_key = vault::get_encryption_key(&conn, &password)?;
+    let (conn, keyring) = unlock::open_vault_with_keyring()?;
 
+    let (project_name, env) = vault::project::split_ref(project_name);
     let project = vault::project::get_project(&conn, project_name)?;
-    let variables = vault::variable::get_variables(&conn, &project.id)?;
+    let environment = project.environment_or_default(env);
+    let variables = vault::variable::get_variables(&conn, &project.id, &environment)?;
 
     if variables.is_empty() {
         println!("Project {} has no variables.", project_name.cyan());
@@ -33,10 +34,14 @@ pub fn handle_validate(project_name: &str) -> Result<()> {
     let mut issues = Vec::new();
 
     for var in &variables {
-        let value = crypto::decrypt_value(&enc_key, &var.encrypted_value)?;
+        let value = keyring.decrypt(&conn, &var.encrypted_value, &var.binding())?;
         validate_variable(&var.key, &value, &mut issues);
     }
 
+    let keys: Vec<String> = variables.iter().map(|v| v.key.clone()).collect();
+    let project_ref = format!("{}:{}", project_name, environment);
+    vault::audit::record(&conn, "validate", Access::Decrypt, Some(&project_ref), &keys)?;
+
     if issues.is_empty() {
         println!(
             "{} All {} variables passed validation.",
@@ -0,0 +1,327 @@
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use clap::ValueEnum;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::EnvkeepError;
+
+/// Output format of a generated secret value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Random bytes, hex-encoded (length = bytes)
+    Hex,
+    /// Random bytes, standard base64 (length = bytes)
+    Base64,
+    /// Random bytes, URL-safe base64 without padding (length = bytes)
+    Base64url,
+    /// Random UUID v4 (length is ignored)
+    Uuid,
+    /// Letters and digits, or the given --charset (length = characters)
+    Alnum,
+    /// Dash-separated words (length = number of words)
+    Words,
+}
+
+const ALNUM: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+/// 256 short words, so each word is exactly one random byte.
+const WORDS: [&str; 256] = [
+    "able", "acid", "aged", "also", "area", "army", "away", "baby", "back", "ball",
+    "band", "bank", "base", "bath", "bear", "beat", "bell", "belt", "best", "bird",
+    "blow", "blue", "boat", "body", "bond", "bone", "book", "boot", "born", "boss",
+    "both", "bowl", "bulk", "burn", "bush", "busy", "cake", "call", "calm", "camp",
+    "card", "care", "cart", "case", "cash", "cast", "cell", "chat", "chip", "city",
+    "clay", "club", "coal", "coat", "code", "cold", "come", "cook", "cool", "cope",
+    "copy", "core", "corn", "cost", "crew", "crop", "dark", "data", "date", "dawn",
+    "deal", "dear", "deck", "deep", "deer", "desk", "dial", "diet", "dirt", "dish",
+    "dock", "door", "dose", "down", "draw", "drop", "drum", "dual", "duck", "dust",
+    "duty", "each", "earn", "ease", "east", "easy", "edge", "else", "epic", "even",
+    "ever", "exit", "face", "fact", "fair", "fall", "farm", "fast", "fate", "fear",
+    "feed", "feel", "file", "fill", "film", "find", "fine", "fire", "firm", "fish",
+    "five", "flag", "flat", "flow", "folk", "food", "foot", "fork", "form", "fort",
+    "four", "free", "frog", "fuel", "full", "fund", "gain", "game", "gate", "gear",
+    "gift", "girl", "glad", "glow", "goal", "gold", "golf", "good", "grab", "gray",
+    "grew", "grid", "grow", "gulf", "hair", "half", "hall", "hand", "hang", "hard",
+    "harm", "hawk", "head", "heat", "helm", "herb", "hero", "high", "hill", "hint",
+    "hold", "hole", "home", "hook", "hope", "horn", "host", "hour", "huge", "hunt",
+    "idea", "inch", "iron", "item", "jade", "jazz", "join", "joke", "jump", "jury",
+    "keen", "keep", "kind", "king", "kite", "knee", "knot", "lake", "lamp", "land",
+    "lane", "last", "lawn", "lead", "leaf", "lean", "left", "lens", "life", "lift",
+    "lime", "line", "link", "lion", "list", "load", "loan", "lock", "loft", "long",
+    "loop", "lord", "loud", "love", "luck", "lung", "mail", "main", "make", "malt",
+    "many", "mark", "mask", "mast", "meal", "melt", "mild", "mile", "milk", "mill",
+    "mind", "mine", "mint", "mist", "mode", "mood", "moon", "moss", "most", "move",
+    "much", "nail", "name", "navy", "neat", "neck",
+];
+
+/// How to produce a new value for a secret. Stored with the secret so later
+/// rotations produce the same kind of value.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Generator {
+    pub format: Format,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub charset: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+}
+
+impl Default for Generator {
+    /// 32 random bytes, hex-encoded (what `secrets rotate` always did).
+    fn default() -> Self {
+        Self {
+            format: Format::Hex,
+            length: None,
+            charset: None,
+            prefix: None,
+        }
+    }
+}
+
+impl Generator {
+    pub fn new(
+        format: Format,
+        length: Option<usize>,
+        charset: Option<String>,
+        prefix: Option<String>,
+    ) -> Result<Self, EnvkeepError> {
+        let generator = Self { format, length, charset, prefix };
+        generator.validate()?;
+        Ok(generator)
+    }
+
+    fn validate(&self) -> Result<(), EnvkeepError> {
+        if self.length == Some(0) {
+            return Err(EnvkeepError::InvalidGenerator("length must be at least 1".to_string()));
+        }
+
+        if let Some(charset) = &self.charset {
+            if self.format != Format::Alnum {
+                return Err(EnvkeepError::InvalidGenerator(
+                    "--charset only applies to --format alnum".to_string(),
+                ));
+            }
+            let distinct: std::collections::BTreeSet<char> = charset.chars().collect();
+            if distinct.len() < 2 || distinct.len() > 256 {
+                return Err(EnvkeepError::InvalidGenerator(
+                    "--charset needs between 2 and 256 distinct characters".to_string(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Length actually used, with the per-format default.
+    fn effective_length(&self) -> usize {
+        self.length.unwrap_or(match self.format {
+            Format::Hex | Format::Base64 | Format::Base64url | Format::Alnum => 32,
+            Format::Uuid => 0,
+            Format::Words => 8,
+        })
+    }
+
+    /// Produce a fresh random value.
+    pub fn generate(&self) -> Result<String, EnvkeepError> {
+        self.validate()?;
+
+        let rng = SystemRandom::new();
+        let length = self.effective_length();
+
+        let body = match self.format {
+            Format::Hex => random_bytes(&rng, length)?
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+            Format::Base64 => STANDARD.encode(random_bytes(&rng, length)?),
+            Format::Base64url => URL_SAFE_NO_PAD.encode(random_bytes(&rng, length)?),
+            Format::Uuid => uuid::Uuid::new_v4().to_string(),
+            Format::Alnum => {
+                let charset: Vec<char> = match &self.charset {
+                    Some(charset) => {
+                        let mut chars: Vec<char> = charset.chars().collect();
+                        chars.sort_unstable();
+                        chars.dedup();
+                        chars
+                    }
+                    None => ALNUM.chars().collect(),
+                };
+                (0..length)
+                    .map(|_| random_index(&rng, charset.len()).map(|i| charset[i]))
+                    .collect::<Result<String, _>>()?
+            }
+            Format::Words => random_bytes(&rng, length)?
+                .iter()
+                .map(|b| WORDS[*b as usize])
+                .collect::<Vec<_>>()
+                .join("-"),
+        };
+
+        Ok(format!("{}{}", self.prefix.as_deref().unwrap_or(""), body))
+    }
+
+    /// Short human-readable summary, eg `base64url, 48 bytes, prefix "sk_"`.
+    pub fn describe(&self) -> String {
+        let length = self.effective_length();
+        let mut parts = vec![format!("{:?}", self.format).to_lowercase()];
+
+        match self.format {
+            Format::Hex | Format::Base64 | Format::Base64url => parts.push(format!("{} bytes", length)),
+            Format::Alnum => parts.push(format!("{} characters", length)),
+            Format::Words => parts.push(format!("{} words", length)),
+            Format::Uuid => {}
+        }
+        if let Some(charset) = &self.charset {
+            parts.push(format!("charset {:?}", charset));
+        }
+        if let Some(prefix) = &self.prefix {
+            parts.push(format!("prefix {:?}", prefix));
+        }
+
+        parts.join(", ")
+    }
+}
+
+fn random_bytes(rng: &SystemRandom, len: usize) -> Result<Vec<u8>, EnvkeepError> {
+    let mut bytes = vec![0u8; len];
+    rng.fill(&mut bytes)
+        .map_err(|_| EnvkeepError::EncryptionError("RNG failed".to_string()))?;
+    Ok(bytes)
+}
+
+/// Uniform index in `0..n` (n <= 256), rejecting bytes that would bias the result.
+fn random_index(rng: &SystemRandom, n: usize) -> Result<usize, EnvkeepError> {
+    let limit = 256 - (256 % n);
+    loop {
+        let byte = random_bytes(rng, 1)?[0] as usize;
+        if byte < limit {
+            return Ok(byte % n);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate(format: Format, length: Option<usize>) -> String {
+        Generator::new(format, length, None, None).unwrap().generate().unwrap()
+    }
+
+    fn invalid(format: Format, length: Option<usize>, charset: Option<&str>) -> String {
+        match Generator::new(format, length, charset.map(str::to_string), None) {
+            Err(EnvkeepError::InvalidGenerator(message)) => message,
+            other => panic!("expected an invalid generator, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn byte_formats_have_the_requested_length() {
+        for length in [None, Some(1), Some(16), Some(48)] {
+            let bytes = length.unwrap_or(32);
+
+            let hex = generate(Format::Hex, length);
+            assert_eq!(hex.len(), bytes * 2);
+            assert!(hex.chars().all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase()), "{}", hex);
+
+            let base64 = generate(Format::Base64, length);
+            assert_eq!(STANDARD.decode(&base64).unwrap().len(), bytes, "{}", base64);
+
+            let base64url = generate(Format::Base64url, length);
+            assert!(!base64url.contains(['+', '/', '=']), "{}", base64url);
+            assert_eq!(URL_SAFE_NO_PAD.decode(&base64url).unwrap().len(), bytes, "{}", base64url);
+        }
+    }
+
+    #[test]
+    fn uuid_ignores_length() {
+        for length in [None, Some(5)] {
+            let uuid = uuid::Uuid::parse_str(&generate(Format::Uuid, length)).unwrap();
+            assert_eq!(uuid.get_version_num(), 4);
+        }
+    }
+
+    #[test]
+    fn alnum_uses_letters_and_digits() {
+        for length in [None, Some(1), Some(100)] {
+            let value = generate(Format::Alnum, length);
+            assert_eq!(value.chars().count(), length.unwrap_or(32));
+            assert!(value.chars().all(|c| c.is_ascii_alphanumeric()), "{}", value);
+        }
+    }
+
+    #[test]
+    fn alnum_uses_only_the_charset() {
+        let generator = Generator::new(Format::Alnum, Some(200), Some("aéé🔑".to_string()), None).unwrap();
+        let value = generator.generate().unwrap();
+
+        assert_eq!(value.chars().count(), 200);
+        assert!(value.chars().all(|c| "aé🔑".contains(c)), "{}", value);
+        for c in ['a', 'é', '🔑'] {
+            assert!(value.contains(c), "{} never drawn in {}", c, value);
+        }
+    }
+
+    #[test]
+    fn words_come_from_the_list() {
+        for length in [None, Some(1), Some(12)] {
+            let value = generate(Format::Words, length);
+            let words: Vec<&str> = value.split('-').collect();
+            assert_eq!(words.len(), length.unwrap_or(8));
+            assert!(words.iter().all(|w| WORDS.contains(w)), "{}", value);
+        }
+    }
+
+    #[test]
+    fn word_list_has_no_duplicates() {
+        let distinct: std::collections::BTreeSet<&str> = WORDS.iter().copied().collect();
+        assert_eq!(distinct.len(), WORDS.len());
+    }
+
+    #[test]
+    fn prefix_is_prepended() {
+        let generator = Generator::new(Format::Hex, Some(4), None, Some("sk_".to_string())).unwrap();
+        let value = generator.generate().unwrap();
+        assert!(value.starts_with("sk_"), "{}", value);
+        assert_eq!(value.len(), 3 + 8);
+    }
+
+    #[test]
+    fn invalid_generators_are_rejected() {
+        assert!(invalid(Format::Hex, Some(0), None).contains("at least 1"));
+        assert!(invalid(Format::Hex, None, Some("abc")).contains("only applies"));
+        assert!(invalid(Format::Words, None, Some("abc")).contains("only applies"));
+        assert!(invalid(Format::Alnum, None, Some("")).contains("distinct"));
+        assert!(invalid(Format::Alnum, None, Some("aaaa")).contains("distinct"));
+
+        let too_many: String = (0..257u32).filter_map(|i| char::from_u32(0x100 + i)).collect();
+        assert!(invalid(Format::Alnum, None, Some(&too_many)).contains("distinct"));
+        let just_enough: String = too_many.chars().take(256).collect();
+        assert!(Generator::new(Format::Alnum, None, Some(just_enough), None).is_ok());
+    }
+
+    #[test]
+    fn stored_generator_is_validated_again() {
+        let generator = Generator { format: Format::Hex, length: Some(0), charset: None, prefix: None };
+        assert!(matches!(generator.generate(), Err(EnvkeepError::InvalidGenerator(_))));
+    }
+
+    #[test]
+    fn random_index_stays_in_range() {
+        let rng = SystemRandom::new();
+        for n in 1..=256 {
+            for _ in 0..20 {
+                assert!(random_index(&rng, n).unwrap() < n, "n = {}", n);
+            }
+        }
+
+        let mut seen = [false; 3];
+        for _ in 0..300 {
+            seen[random_index(&rng, 3).unwrap()] = true;
+        }
+        assert_eq!(seen, [true; 3]);
+    }
+}
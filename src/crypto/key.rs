@@ -1,8 +1,14 @@
-// Master key derivation will go here.
+// Master key derivation.
+use argon2::{Algorithm, Argon2, Params, Version};
+use ring::digest;
+use ring::hkdf;
 use ring::pbkdf2;
 use ring::rand::{SystemRandom, SecureRandom};
+use serde::{Deserialize, Serialize};
 use std::num::NonZeroU32;
+use std::time::{Duration, Instant};
 
+use crate::crypto::secret::SecretKey;
 use crate::errors::EnvkeepError;
 
 const CREDENTIAL_LEN: usize = 32; //AE 256 need 32 bytes
@@ -11,6 +17,63 @@ const ITERATIONS: u32 = 100_000; // 100k (OWASP recommendation)
 
 static ALGORITHM: pbkdf2::Algorithm = pbkdf2::PBKDF2_HMAC_SHA256;
 
+/// Which KDF turns the master password into the value key, and with what cost.
+///
+/// Stored as JSON in `metadata` under `kdf`, next to `salt`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "algorithm", rename_all = "lowercase")]
+pub enum KdfParams {
+    /// PBKDF2-HMAC-SHA256, used by vaults created before Argon2id.
+    Pbkdf2 { iterations: u32 },
+    Argon2id {
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+        version: u32,
+    },
+}
+
+impl KdfParams {
+    /// What vaults without a `kdf` entry were created with.
+    pub fn legacy() -> Self {
+        KdfParams::Pbkdf2 { iterations: ITERATIONS }
+    }
+
+    /// Argon2id (version 0x13, one lane) with the given memory and passes.
+    pub fn argon2id(memory_kib: u32, iterations: u32) -> Self {
+        KdfParams::Argon2id {
+            memory_kib,
+            iterations,
+            parallelism: 1,
+            version: Version::V0x13 as u32,
+        }
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        matches!(self, KdfParams::Pbkdf2 { .. })
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            KdfParams::Pbkdf2 { iterations } => format!("PBKDF2-HMAC-SHA256, {} iterations", iterations),
+            KdfParams::Argon2id { memory_kib, iterations, parallelism, version } => format!(
+                "Argon2id v{:#x}, {} MiB, {} passes, {} lane(s)",
+                version,
+                memory_kib / 1024,
+                iterations,
+                parallelism
+            ),
+        }
+    }
+}
+
+impl Default for KdfParams {
+    /// Argon2id with 64 MiB, 3 passes, 1 lane (RFC 9106, second recommended option).
+    fn default() -> Self {
+        KdfParams::argon2id(64 * 1024, 3)
+    }
+}
+
 ///Generate a random salt for key derivation
 pub fn generate_salt() -> Result<[u8; SALT_LEN], EnvkeepError> {
     let rng = SystemRandom::new();
@@ -19,30 +82,244 @@ pub fn generate_salt() -> Result<[u8; SALT_LEN], EnvkeepError> {
     Ok(salt)
 }
 
-/// Derive a 32-byte encryption ket from a password and salt
-/// Derive a 32-byte encryption key from a password and salt.
-pub fn derive_key(password: &str, salt: &[u8]) -> [u8; CREDENTIAL_LEN] {
-    let iterations = NonZeroU32::new(ITERATIONS).unwrap();
-    let mut key = [0u8; CREDENTIAL_LEN];
-    pbkdf2::derive(
-        ALGORITHM,
-        iterations,
-        salt,
-        password.as_bytes(),
-        &mut key,
-    );
-    key
-}
-
-/// Verify that a password matches a previously derived key.
-pub fn verify_password(password: &str, salt: &[u8], expected_key: &[u8]) -> bool {
-    let iterations = NonZeroU32::new(ITERATIONS).unwrap();
-    pbkdf2::verify(
-        ALGORITHM,
-        iterations,
-        salt,
-        password.as_bytes(),
-        expected_key,
-    )
-    .is_ok()
-}
\ No newline at end of file
+/// Derive a 32-byte encryption key from a password and salt with PBKDF2.
+///
+/// Export files still use this; the vault itself uses `derive_key_with`.
+pub fn derive_key(password: &str, salt: &[u8]) -> SecretKey {
+    pbkdf2_key(password, salt, ITERATIONS)
+}
+
+fn pbkdf2_key(password: &str, salt: &[u8], iterations: u32) -> SecretKey {
+    let iterations = NonZeroU32::new(iterations.max(1)).unwrap();
+    let key: Result<_, ()> = SecretKey::init(|key| {
+        pbkdf2::derive(ALGORITHM, iterations, salt, password.as_bytes(), key);
+        Ok(())
+    });
+    key.expect("PBKDF2 cannot fail")
+}
+
+/// Derive a 32-byte encryption key with the given KDF parameters.
+pub fn derive_key_with(
+    password: &str,
+    salt: &[u8],
+    params: &KdfParams,
+) -> Result<SecretKey, EnvkeepError> {
+    match params {
+        KdfParams::Pbkdf2 { iterations } => Ok(pbkdf2_key(password, salt, *iterations)),
+        KdfParams::Argon2id { memory_kib, iterations, parallelism, version } => {
+            let version = Version::try_from(*version)
+                .map_err(|e| EnvkeepError::KeyDerivationError(e.to_string()))?;
+            let params = Params::new(*memory_kib, *iterations, *parallelism, Some(CREDENTIAL_LEN))
+                .map_err(|e| EnvkeepError::KeyDerivationError(e.to_string()))?;
+
+            SecretKey::init(|key| {
+                Argon2::new(Algorithm::Argon2id, version, params)
+                    .hash_password_into(password.as_bytes(), salt, key)
+                    .map_err(|e| EnvkeepError::KeyDerivationError(e.to_string()))
+            })
+        }
+    }
+}
+
+/// A fresh random 256-bit key.
+pub fn generate_key() -> Result<SecretKey, EnvkeepError> {
+    let rng = SystemRandom::new();
+    SecretKey::init(|key| {
+        rng.fill(key).map_err(|_| EnvkeepError::KeyDerivationError("Failed to generate key".to_string()))
+    })
+}
+
+/// Derive an independent 32-byte key for one purpose from a high-entropy key (HKDF-SHA256).
+pub fn subkey(key: &[u8; CREDENTIAL_LEN], purpose: &str) -> SecretKey {
+    struct Len;
+    impl hkdf::KeyType for Len {
+        fn len(&self) -> usize {
+            CREDENTIAL_LEN
+        }
+    }
+
+    let info = [purpose.as_bytes()];
+    let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, b"envkeep").extract(key);
+    SecretKey::init(|out| prk.expand(&info, Len).and_then(|okm| okm.fill(out)))
+        .expect("HKDF output length is valid")
+}
+
+/// Hash of a derived key, stored so a wrong password is caught before any
+/// value is decrypted. Never the key itself.
+pub fn verification_hash(key: &[u8]) -> String {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(b"envkeep-verification\0");
+    ctx.update(key);
+    ctx.finish()
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Check a derived key against the stored verification value.
+///
+/// Vaults from before Argon2id stored the key itself, which is accepted too.
+pub fn verification_matches(key: &[u8], stored: &str) -> bool {
+    let legacy: String = key.iter().map(|b| format!("{:02x}", b)).collect();
+    let expected = verification_hash(key);
+    constant_time_eq(expected.as_bytes(), stored.as_bytes())
+        || constant_time_eq(legacy.as_bytes(), stored.as_bytes())
+}
+
+/// Compare without stopping at the first differing byte.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Verify that a password matches a stored verification value.
+///
+/// Returns the derived key if it does, so callers derive only once.
+pub fn verify_password(
+    password: &str,
+    salt: &[u8],
+    params: &KdfParams,
+    stored: &str,
+) -> Result<Option<SecretKey>, EnvkeepError> {
+    let key = derive_key_with(password, salt, params)?;
+    Ok(verification_matches(&*key, stored).then_some(key))
+}
+
+/// Time one derivation with the given parameters.
+pub fn time_derivation(params: &KdfParams) -> Result<Duration, EnvkeepError> {
+    let salt = generate_salt()?;
+    let start = Instant::now();
+    derive_key_with("envkeep benchmark password", &salt, params)?;
+    Ok(start.elapsed())
+}
+
+/// Pick Argon2id parameters that take about `target` to derive on this machine.
+///
+/// Starts from 64 MiB and one pass, adds passes up to 4, then grows memory
+/// (up to 1 GiB). Too slow machines get less memory, but never below 19 MiB.
+pub fn calibrate(target: Duration) -> Result<(KdfParams, Duration), EnvkeepError> {
+    const MIN_MEMORY_KIB: u32 = 19 * 1024;
+    const BASE_MEMORY_KIB: u32 = 64 * 1024;
+    const MAX_MEMORY_KIB: u32 = 1024 * 1024;
+    const MAX_PASSES: u32 = 4;
+
+    let base = time_derivation(&KdfParams::argon2id(BASE_MEMORY_KIB, 1))?;
+    // Cost grows roughly linearly with memory x passes
+    let units = target.as_secs_f64() / base.as_secs_f64().max(0.001);
+
+    let params = if units < 1.0 {
+        let memory = (BASE_MEMORY_KIB as f64 * units) as u32;
+        KdfParams::argon2id(memory.max(MIN_MEMORY_KIB), 1)
+    } else if units <= MAX_PASSES as f64 {
+        KdfParams::argon2id(BASE_MEMORY_KIB, units.round() as u32)
+    } else {
+        let memory = (BASE_MEMORY_KIB as f64 * units / MAX_PASSES as f64) as u32;
+        // Keep memory a whole number of MiB
+        let memory = (memory.min(MAX_MEMORY_KIB) / 1024) * 1024;
+        KdfParams::argon2id(memory, MAX_PASSES)
+    };
+
+    let measured = time_derivation(&params)?;
+    Ok((params, measured))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7u8; 32];
+    /// `verification_hash(&KEY)`, as vaults store it.
+    const KEY_VERIFICATION: &str = "39010a4d465a0baf8ec60be9fdd6866535ee9ecaacdb5b58bc7c63ec56bc79f1";
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn kdf_params_serde_round_trip() {
+        for (params, json) in [
+            (KdfParams::legacy(), r#"{"algorithm":"pbkdf2","iterations":100000}"#),
+            (
+                KdfParams::default(),
+                r#"{"algorithm":"argon2id","memory_kib":65536,"iterations":3,"parallelism":1,"version":19}"#,
+            ),
+        ] {
+            assert_eq!(serde_json::to_string(&params).unwrap(), json);
+            assert_eq!(serde_json::from_str::<KdfParams>(json).unwrap(), params);
+        }
+
+        for json in [r#"{"algorithm":"scrypt","n":16384}"#, r#"{"algorithm":"argon2id","memory_kib":65536}"#, "{}"] {
+            assert!(serde_json::from_str::<KdfParams>(json).is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn legacy_params_are_the_original_pbkdf2() {
+        assert!(KdfParams::legacy().is_legacy());
+        assert!(!KdfParams::default().is_legacy());
+
+        // What vaults from before Argon2id were unlocked with
+        let key = derive_key_with("password1", b"0123456789abcdef", &KdfParams::legacy()).unwrap();
+        assert_eq!(hex(&key[..]), "b11900f05ec1812ab8ae6def51347b5795da52fa5a33ce3d44690649556dc50a");
+        assert_eq!(key[..], derive_key("password1", b"0123456789abcdef")[..]);
+
+        // RFC 7914, section 11
+        let key = derive_key_with("passwd", b"salt", &KdfParams::Pbkdf2 { iterations: 1 }).unwrap();
+        assert_eq!(hex(&key[..]), "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc");
+    }
+
+    #[test]
+    fn argon2id_depends_on_every_parameter() {
+        let salt = b"0123456789abcdef";
+        let derive = |params: &KdfParams| hex(&derive_key_with("password1", salt, params).unwrap()[..]);
+        let base = derive(&KdfParams::argon2id(1024, 1));
+
+        assert_eq!(derive(&KdfParams::argon2id(1024, 1)), base);
+        assert_ne!(derive(&KdfParams::argon2id(2048, 1)), base);
+        assert_ne!(derive(&KdfParams::argon2id(1024, 2)), base);
+        assert_ne!(derive(&KdfParams::Argon2id { memory_kib: 1024, iterations: 1, parallelism: 2, version: 0x13 }), base);
+        assert_ne!(derive(&KdfParams::Argon2id { memory_kib: 1024, iterations: 1, parallelism: 1, version: 0x10 }), base);
+        assert!(derive_key_with("password1", salt, &KdfParams::Argon2id {
+            memory_kib: 1024,
+            iterations: 1,
+            parallelism: 1,
+            version: 0x42,
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn verification_matches_stored_values() {
+        assert_eq!(verification_hash(&KEY), KEY_VERIFICATION);
+        assert!(verification_matches(&KEY, KEY_VERIFICATION));
+        // Vaults from before Argon2id stored the key itself
+        assert!(verification_matches(&KEY, &hex(&KEY)));
+
+        let other = [8u8; 32];
+        assert!(!verification_matches(&other, KEY_VERIFICATION));
+        assert!(!verification_matches(&other, &hex(&KEY)));
+        assert!(!verification_matches(&KEY, &KEY_VERIFICATION[..63]));
+        assert!(!verification_matches(&KEY, &KEY_VERIFICATION.to_uppercase()));
+        assert!(!verification_matches(&KEY, ""));
+    }
+
+    #[test]
+    fn verify_password_returns_the_key() {
+        let salt = b"0123456789abcdef";
+        let params = KdfParams::Pbkdf2 { iterations: 1 };
+        let key = derive_key_with("password1", salt, &params).unwrap();
+        let stored = verification_hash(&key[..]);
+
+        let verified = verify_password("password1", salt, &params, &stored).unwrap().unwrap();
+        assert_eq!(verified[..], key[..]);
+        assert!(verify_password("password2", salt, &params, &stored).unwrap().is_none());
+        assert!(verify_password("password1", salt, &params, &hex(&key[..])).unwrap().is_some());
+    }
+
+    #[test]
+    fn subkeys_differ_by_purpose() {
+        assert_eq!(subkey(&KEY, "envkeep value key"), subkey(&KEY, "envkeep value key"));
+        assert_ne!(subkey(&KEY, "envkeep value key"), subkey(&KEY, "envkeep database key"));
+        assert_ne!(subkey(&KEY, "envkeep value key"), subkey(&[8u8; 32], "envkeep value key"));
+    }
+}
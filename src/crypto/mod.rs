@@ -1,26 +1,39 @@
 pub mod key;
+pub mod generator;
+pub mod recovery;
+pub mod recipients;
+pub mod secret;
+pub mod shamir;
+pub mod signing;
 // Encryption/decryption functions will go here.
 
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD_NO_PAD;
 use ring::aead::{self, Aad, BoundKey, NONCE_LEN, Nonce, NonceSequence, UnboundKey};
+use ring::digest;
 use ring::error::Unspecified;
 use ring::rand::{SecureRandom, SystemRandom};
 use rpassword::read_password;
 use std::io::{self, Write};
+use zeroize::Zeroize;
 
 use crate::errors::EnvkeepError;
+use secret::SecretString;
 
 /// Prompt the user for a password (input is hidden).
-pub fn prompt_password(prompt: &str) -> Result<String, EnvkeepError> {
-    print!("{}", prompt);
-    io::stdout()
+///
+/// The prompt goes to stderr so stdout stays clean for `$(envkeep get ...)`.
+pub fn prompt_password(prompt: &str) -> Result<SecretString, EnvkeepError> {
+    eprint!("{}", prompt);
+    io::stderr()
         .flush()
         .map_err(|e| EnvkeepError::EncryptionError(e.to_string()))?;
     let password = read_password().map_err(|e| EnvkeepError::EncryptionError(e.to_string()))?;
-    Ok(password)
+    Ok(SecretString::new(password))
 }
 
 /// Prompt for a new master password with confirmation.
-pub fn prompt_new_password() -> Result<String, EnvkeepError> {
+pub fn prompt_new_password() -> Result<SecretString, EnvkeepError> {
     let password = prompt_password("Enter master password: ")?;
 
     if password.len() < 8 {
@@ -31,7 +44,7 @@ pub fn prompt_new_password() -> Result<String, EnvkeepError> {
 
     let confirm = prompt_password("Confirm master password: ")?;
 
-    if password != confirm {
+    if password.as_str() != confirm.as_str() {
         return Err(EnvkeepError::EncryptionError(
             "Passwords do not match".to_string(),
         ));
@@ -41,7 +54,10 @@ pub fn prompt_new_password() -> Result<String, EnvkeepError> {
 }
 
 /// Prompt for the existing master password (single prompt, no confirmation).
-pub fn prompt_existing_password() -> Result<String, EnvkeepError> {
+///
+/// Commands get the password through `crate::unlock`, which only prompts when
+/// no other source is set.
+pub fn prompt_existing_password() -> Result<SecretString, EnvkeepError> {
     prompt_password("Master password: ")
 }
 
@@ -71,6 +87,12 @@ impl NonceSequence for SingleNonce {
 ///
 /// Returns: nonce (12 bytes) + ciphertext + tag (16 bytes)
 pub fn encrypt(key: &[u8; 32], plaintext: &[u8]) -> Result<Vec<u8>, EnvkeepError> {
+    encrypt_with_aad(key, plaintext, &[])
+}
+
+/// Encrypt like `encrypt`, authenticating `aad` along with the plaintext.
+/// The same `aad` must be given to decrypt.
+pub fn encrypt_with_aad(key: &[u8; 32], plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, EnvkeepError> {
     let rng = SystemRandom::new();
 
     // Generate a random 12-byte nonce
@@ -86,7 +108,7 @@ pub fn encrypt(key: &[u8; 32], plaintext: &[u8]) -> Result<Vec<u8>, EnvkeepError
     // Encrypt in place
     let mut in_out = plaintext.to_vec();
     sealing_key
-        .seal_in_place_append_tag(Aad::empty(), &mut in_out)
+        .seal_in_place_append_tag(Aad::from(aad), &mut in_out)
         .map_err(|_| EnvkeepError::EncryptionError("Encryption failed".to_string()))?;
 
     // Prepend nonce to ciphertext: [nonce (12) | ciphertext | tag (16)]
@@ -101,6 +123,11 @@ pub fn encrypt(key: &[u8; 32], plaintext: &[u8]) -> Result<Vec<u8>, EnvkeepError
 ///
 /// Input format: nonce (12 bytes) + ciphertext + tag (16 bytes)
 pub fn decrypt(key: &[u8; 32], encrypted: &[u8]) -> Result<Vec<u8>, EnvkeepError> {
+    decrypt_with_aad(key, encrypted, &[])
+}
+
+/// Decrypt ciphertext produced by encrypt_with_aad() with the same `aad`.
+pub fn decrypt_with_aad(key: &[u8; 32], encrypted: &[u8], aad: &[u8]) -> Result<Vec<u8>, EnvkeepError> {
     if encrypted.len() < NONCE_LEN + TAG_LEN {
         return Err(EnvkeepError::DecryptionError(
             "Ciphertext too short".to_string(),
@@ -120,7 +147,7 @@ pub fn decrypt(key: &[u8; 32], encrypted: &[u8]) -> Result<Vec<u8>, EnvkeepError
     // Decrypt in place
     let mut in_out = ciphertext_with_tag.to_vec();
     let plaintext = opening_key
-        .open_in_place(Aad::empty(), &mut in_out)
+        .open_in_place(Aad::from(aad), &mut in_out)
         .map_err(|_| {
             EnvkeepError::DecryptionError(
                 "Decryption failed (wrong password or corrupted data)".to_string(),
@@ -132,28 +159,205 @@ pub fn decrypt(key: &[u8; 32], encrypted: &[u8]) -> Result<Vec<u8>, EnvkeepError
 
 // helper functions
 
-/// Encrypt a string value. Returns base64-encoded ciphertext.
-pub fn encrypt_value(key: &[u8; 32], value: &str) -> Result<String, EnvkeepError> {
-    let encrypted = encrypt(key, value.as_bytes())?;
-    Ok(base64_encode(&encrypted))
+/// Prefix of values bound to their row but still hex encoded (format version 2).
+const VALUE_V2_PREFIX: &str = "v2:";
+
+/// Prefix of values stored in an `Envelope`.
+const ENVELOPE_PREFIX: &str = "ek:";
+
+/// Envelope format version, the first header byte.
+const ENVELOPE_VERSION: u8 = 3;
+
+/// version (1) | algorithm (1) | key id (4)
+const HEADER_LEN: usize = 6;
+
+/// Length of the key id that names which key a value was encrypted with.
+const KEY_ID_LEN: usize = 4;
+
+/// Cipher a stored value is encrypted with. Ids are written to disk; never reuse one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Algorithm {
+    Aes256Gcm = 1,
 }
 
-/// Decrypt a base64-encoded ciphertext back to a string.
-pub fn decrypt_value(key: &[u8; 32], encoded: &str) -> Result<String, EnvkeepError> {
-    let encrypted = base64_decode(encoded)?;
-    let decrypted = decrypt(key, &encrypted)?;
-    String::from_utf8(decrypted).map_err(|e| EnvkeepError::DecryptionError(e.to_string()))
+impl Algorithm {
+    fn from_id(id: u8) -> Result<Self, EnvkeepError> {
+        match id {
+            1 => Ok(Algorithm::Aes256Gcm),
+            _ => Err(EnvkeepError::DecryptionError(format!(
+                "unknown cipher id {} (written by a newer envkeep?)",
+                id
+            ))),
+        }
+    }
+}
+
+/// Short public identifier of a key, so a value names the key it needs
+/// without revealing anything about it.
+pub fn key_id(key: &[u8; 32]) -> [u8; KEY_ID_LEN] {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(b"envkeep-key-id\0");
+    ctx.update(key);
+    let mut id = [0u8; KEY_ID_LEN];
+    id.copy_from_slice(&ctx.finish().as_ref()[..KEY_ID_LEN]);
+    id
+}
+
+/// A stored value: `ek:` then base64 of
+/// version (1) | algorithm (1) | key id (4) | nonce (12) | ciphertext | tag (16).
+///
+/// The header is authenticated along with the row binding, so it cannot be
+/// edited without the value failing to decrypt.
+struct Envelope {
+    algorithm: Algorithm,
+    key_id: [u8; KEY_ID_LEN],
+    /// nonce | ciphertext | tag
+    sealed: Vec<u8>,
+}
+
+impl Envelope {
+    fn header(algorithm: Algorithm, key_id: &[u8; KEY_ID_LEN]) -> [u8; HEADER_LEN] {
+        let mut header = [0u8; HEADER_LEN];
+        header[0] = ENVELOPE_VERSION;
+        header[1] = algorithm as u8;
+        header[2..].copy_from_slice(key_id);
+        header
+    }
+
+    fn encode(&self) -> String {
+        let mut bytes = Self::header(self.algorithm, &self.key_id).to_vec();
+        bytes.extend_from_slice(&self.sealed);
+        format!("{}{}", ENVELOPE_PREFIX, STANDARD_NO_PAD.encode(bytes))
+    }
+
+    fn decode(body: &str) -> Result<Self, EnvkeepError> {
+        let bytes = STANDARD_NO_PAD
+            .decode(body)
+            .map_err(|e| EnvkeepError::DecryptionError(format!("Invalid base64: {}", e)))?;
+        if bytes.len() < HEADER_LEN {
+            return Err(EnvkeepError::DecryptionError("Envelope too short".to_string()));
+        }
+        if bytes[0] != ENVELOPE_VERSION {
+            return Err(EnvkeepError::DecryptionError(format!(
+                "unknown value format version {} (written by a newer envkeep?)",
+                bytes[0]
+            )));
+        }
+
+        let mut key_id = [0u8; KEY_ID_LEN];
+        key_id.copy_from_slice(&bytes[2..HEADER_LEN]);
+        Ok(Envelope {
+            algorithm: Algorithm::from_id(bytes[1])?,
+            key_id,
+            sealed: bytes[HEADER_LEN..].to_vec(),
+        })
+    }
+
+    /// Associated data: the header, then the row the value belongs to.
+    fn aad(algorithm: Algorithm, key_id: &[u8; KEY_ID_LEN], binding: &ValueBinding) -> Vec<u8> {
+        let mut aad = Self::header(algorithm, key_id).to_vec();
+        aad.extend_from_slice(&binding.aad());
+        aad
+    }
+}
+
+/// The row a stored value belongs to.
+///
+/// It is bound into the ciphertext as associated data, so a value copied to
+/// another project, environment, secret or key no longer decrypts.
+#[derive(Debug, Clone, Copy)]
+pub enum ValueBinding<'a> {
+    Variable {
+        project_id: &'a str,
+        environment: &'a str,
+        key: &'a str,
+    },
+    Secret {
+        secret_id: &'a str,
+        key: &'a str,
+    },
+}
+
+impl ValueBinding<'_> {
+    fn aad(&self) -> Vec<u8> {
+        // Unit separators keep field boundaries unambiguous
+        match self {
+            ValueBinding::Variable { project_id, environment, key } => {
+                format!("envkeep/v2\x1fvariable\x1f{}\x1f{}\x1f{}", project_id, environment, key)
+            }
+            ValueBinding::Secret { secret_id, key } => {
+                format!("envkeep/v2\x1fsecret\x1f{}\x1f{}", secret_id, key)
+            }
+        }
+        .into_bytes()
+    }
 }
 
-// Simple base64 encoding/decoding (no external dependency needed)
-fn base64_encode(data: &[u8]) -> String {
-    use ring::test::rand::FixedByteRandom; // not this
-    // Actually, use the `base64` crate or manual impl.
-    // For simplicity, store as hex:
-    data.iter().map(|b| format!("{:02x}", b)).collect()
+/// Encrypt a string value for the given row.
+pub fn encrypt_value(
+    key: &[u8; 32],
+    value: &str,
+    binding: &ValueBinding,
+) -> Result<String, EnvkeepError> {
+    let algorithm = Algorithm::Aes256Gcm;
+    let key_id = key_id(key);
+    let sealed = encrypt_with_aad(key, value.as_bytes(), &Envelope::aad(algorithm, &key_id, binding))?;
+    Ok(Envelope { algorithm, key_id, sealed }.encode())
 }
 
-fn base64_decode(hex: &str) -> Result<Vec<u8>, EnvkeepError> {
+/// Decrypt a value stored for the given row back to a string.
+///
+/// Accepts envelopes and the older hex encoded `v2:` values. Fails if the
+/// value was written for a different row.
+pub fn decrypt_value(
+    key: &[u8; 32],
+    encoded: &str,
+    binding: &ValueBinding,
+) -> Result<SecretString, EnvkeepError> {
+    let decrypted = if let Some(body) = encoded.strip_prefix(ENVELOPE_PREFIX) {
+        let envelope = Envelope::decode(body)?;
+        if envelope.key_id != key_id(key) {
+            return Err(EnvkeepError::DecryptionError(
+                "value was encrypted with a different key".to_string(),
+            ));
+        }
+        let aad = Envelope::aad(envelope.algorithm, &envelope.key_id, binding);
+        match envelope.algorithm {
+            Algorithm::Aes256Gcm => decrypt_with_aad(key, &envelope.sealed, &aad)?,
+        }
+    } else if let Some(body) = encoded.strip_prefix(VALUE_V2_PREFIX) {
+        decrypt_with_aad(key, &hex_decode(body)?, &binding.aad())?
+    } else {
+        return Err(EnvkeepError::DecryptionError(
+            "value is in the old unbound format (run 'envkeep migrate')".to_string(),
+        ));
+    };
+    String::from_utf8(decrypted).map(SecretString::new).map_err(|e| {
+        let message = e.utf8_error().to_string();
+        e.into_bytes().zeroize();
+        EnvkeepError::DecryptionError(message)
+    })
+}
+
+/// Whether a stored value is already in the current envelope format.
+pub fn is_current_format(encoded: &str) -> bool {
+    encoded.starts_with(ENVELOPE_PREFIX)
+}
+
+/// Decrypt a value written before values were bound to their row.
+///
+/// Only the migration that re-encrypts those values should need this.
+pub fn decrypt_legacy_value(key: &[u8; 32], encoded: &str) -> Result<String, EnvkeepError> {
+    let encrypted = hex_decode(encoded)?;
+    let decrypted = decrypt(key, &encrypted)?;
+    String::from_utf8(decrypted).map_err(|e| EnvkeepError::DecryptionError(e.to_string()))
+}
+
+/// Older vaults stored values (and their salt) as hex.
+pub fn hex_decode(hex: &str) -> Result<Vec<u8>, EnvkeepError> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(EnvkeepError::DecryptionError("Invalid hex".to_string()));
+    }
     (0..hex.len())
         .step_by(2)
         .map(|i| {
@@ -162,3 +366,88 @@ fn base64_decode(hex: &str) -> Result<Vec<u8>, EnvkeepError> {
         })
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7u8; 32];
+
+    fn variable<'a>(project_id: &'a str, environment: &'a str, key: &'a str) -> ValueBinding<'a> {
+        ValueBinding::Variable { project_id, environment, key }
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn envelope_round_trip() {
+        let binding = variable("p1", "dev", "API_KEY");
+        let encrypted = encrypt_value(&KEY, "héllo wörld", &binding).unwrap();
+
+        assert!(encrypted.starts_with(ENVELOPE_PREFIX));
+        assert!(is_current_format(&encrypted));
+        assert_eq!(decrypt_value(&KEY, &encrypted, &binding).unwrap().as_str(), "héllo wörld");
+
+        let envelope = Envelope::decode(&encrypted[ENVELOPE_PREFIX.len()..]).unwrap();
+        assert_eq!(envelope.algorithm, Algorithm::Aes256Gcm);
+        assert_eq!(envelope.key_id, key_id(&KEY));
+        assert_eq!(envelope.encode(), encrypted);
+    }
+
+    #[test]
+    fn tampered_header_does_not_decrypt() {
+        let binding = variable("p1", "dev", "API_KEY");
+        let encrypted = encrypt_value(&KEY, "secret", &binding).unwrap();
+        let bytes = STANDARD_NO_PAD.decode(&encrypted[ENVELOPE_PREFIX.len()..]).unwrap();
+
+        for i in 0..HEADER_LEN {
+            let mut tampered = bytes.clone();
+            tampered[i] ^= 0x01;
+            let tampered = format!("{}{}", ENVELOPE_PREFIX, STANDARD_NO_PAD.encode(tampered));
+            assert!(decrypt_value(&KEY, &tampered, &binding).is_err(), "header byte {}", i);
+        }
+    }
+
+    #[test]
+    fn key_id_mismatch_is_reported() {
+        let binding = variable("p1", "dev", "API_KEY");
+        let encrypted = encrypt_value(&KEY, "secret", &binding).unwrap();
+
+        match decrypt_value(&[8u8; 32], &encrypted, &binding) {
+            Err(EnvkeepError::DecryptionError(message)) => assert!(message.contains("different key"), "{}", message),
+            other => panic!("expected a key id mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn older_formats_still_decrypt() {
+        let binding = variable("p1", "dev", "API_KEY");
+
+        let v2 = format!("{}{}", VALUE_V2_PREFIX, hex(&encrypt_with_aad(&KEY, b"bound", &binding.aad()).unwrap()));
+        assert!(!is_current_format(&v2));
+        assert_eq!(decrypt_value(&KEY, &v2, &binding).unwrap().as_str(), "bound");
+        assert!(decrypt_value(&KEY, &v2, &variable("p2", "dev", "API_KEY")).is_err());
+
+        let legacy = hex(&encrypt(&KEY, b"unbound").unwrap());
+        assert_eq!(decrypt_legacy_value(&KEY, &legacy).unwrap(), "unbound");
+        assert!(decrypt_value(&KEY, &legacy, &binding).is_err());
+    }
+
+    #[test]
+    fn value_copied_to_another_row_does_not_decrypt() {
+        let binding = variable("p1", "dev", "API_KEY");
+        let encrypted = encrypt_value(&KEY, "secret", &binding).unwrap();
+        assert_eq!(decrypt_value(&KEY, &encrypted, &binding).unwrap().as_str(), "secret");
+
+        for other in [
+            variable("p1", "dev", "OTHER_KEY"),
+            variable("p1", "prod", "API_KEY"),
+            variable("p2", "dev", "API_KEY"),
+            ValueBinding::Secret { secret_id: "p1", key: "API_KEY" },
+        ] {
+            assert!(decrypt_value(&KEY, &encrypted, &other).is_err(), "{:?}", other);
+        }
+    }
+}
@@ -0,0 +1,210 @@
+// Public-key encryption to one or more X25519 recipients, for exports.
+//
+// A random file key encrypts the payload; it is wrapped once per recipient
+// with a key agreed between a one-off (ephemeral) key and the recipient's key.
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::crypto::key::{generate_key, subkey};
+use crate::crypto::secret::{SecretBytes, SecretKey};
+use crate::crypto::{decrypt_with_aad, encrypt_with_aad};
+use crate::errors::EnvkeepError;
+
+/// Prefix of a public key as printed by `envkeep identity show`.
+const PUBLIC_KEY_PREFIX: &str = "ekpk_";
+const KEY_LEN: usize = 32;
+/// nonce | wrapped file key | tag
+const WRAPPED_LEN: usize = 12 + KEY_LEN + 16;
+
+/// Print a public key for a human to pass around.
+pub fn format_public_key(public: &PublicKey) -> String {
+    format!("{}{}", PUBLIC_KEY_PREFIX, URL_SAFE_NO_PAD.encode(public.as_bytes()))
+}
+
+/// Parse a public key written by `format_public_key`.
+pub fn parse_public_key(input: &str) -> Result<PublicKey, EnvkeepError> {
+    let invalid = || EnvkeepError::InvalidPublicKey(input.to_string());
+    let encoded = input.trim().strip_prefix(PUBLIC_KEY_PREFIX).ok_or_else(invalid)?;
+    let bytes: [u8; KEY_LEN] = URL_SAFE_NO_PAD
+        .decode(encoded)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(invalid)?;
+    Ok(PublicKey::from(bytes))
+}
+
+/// Encrypt `plaintext` so that any of `recipients` can open it.
+///
+/// Layout: ephemeral public key | recipient count | (recipient public key |
+/// wrapped file key) per recipient | nonce | ciphertext | tag. Everything
+/// before the payload is authenticated with it.
+pub fn seal(plaintext: &[u8], recipients: &[PublicKey]) -> Result<Vec<u8>, EnvkeepError> {
+    if recipients.is_empty() || recipients.len() > u8::MAX as usize {
+        return Err(EnvkeepError::EncryptionError(format!(
+            "between 1 and {} recipients are supported",
+            u8::MAX
+        )));
+    }
+
+    let file_key = generate_key()?;
+    // Used for this file only, but agreed with every recipient
+    let ephemeral = StaticSecret::from(*generate_key()?);
+    let ephemeral_public = PublicKey::from(&ephemeral);
+
+    let mut header = ephemeral_public.as_bytes().to_vec();
+    header.push(recipients.len() as u8);
+
+    for recipient in recipients {
+        let kek = agree(&ephemeral, recipient)?;
+        header.extend_from_slice(recipient.as_bytes());
+        header.extend_from_slice(&encrypt_with_aad(&kek, &file_key[..], &wrap_aad(&ephemeral_public, recipient))?);
+    }
+
+    let mut sealed = header.clone();
+    sealed.extend_from_slice(&encrypt_with_aad(&file_key, plaintext, &header)?);
+    Ok(sealed)
+}
+
+/// Decrypt data written by `seal` with the identity of one of its recipients.
+pub fn open(sealed: &[u8], identity: &StaticSecret) -> Result<Vec<u8>, EnvkeepError> {
+    let truncated = || EnvkeepError::DecryptionError("truncated recipient header".to_string());
+
+    let ephemeral_public: [u8; KEY_LEN] = sealed
+        .get(..KEY_LEN)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(truncated)?;
+    let ephemeral_public = PublicKey::from(ephemeral_public);
+    let count = *sealed.get(KEY_LEN).ok_or_else(truncated)? as usize;
+
+    let header_len = KEY_LEN + 1 + count * (KEY_LEN + WRAPPED_LEN);
+    let header = sealed.get(..header_len).ok_or_else(truncated)?;
+    let own_public = PublicKey::from(identity);
+
+    let file_key = header[KEY_LEN + 1..]
+        .chunks(KEY_LEN + WRAPPED_LEN)
+        .find(|slot| slot[..KEY_LEN] == own_public.as_bytes()[..])
+        .map(|slot| -> Result<SecretKey, EnvkeepError> {
+            let kek = agree(identity, &ephemeral_public)?;
+            let file_key = SecretBytes::new(decrypt_with_aad(
+                &kek,
+                &slot[KEY_LEN..],
+                &wrap_aad(&ephemeral_public, &own_public),
+            )?);
+            SecretKey::copy_from(&file_key)
+                .ok_or_else(|| EnvkeepError::DecryptionError("wrapped file key has the wrong length".to_string()))
+        })
+        .ok_or(EnvkeepError::NotARecipient)??;
+
+    decrypt_with_aad(&file_key, &sealed[header_len..], header)
+}
+
+/// Key that wraps the file key for one recipient. Both public keys are bound
+/// in as associated data when it is used (see `wrap_aad`).
+fn agree(secret: &StaticSecret, peer: &PublicKey) -> Result<SecretKey, EnvkeepError> {
+    let shared = secret.diffie_hellman(peer);
+    // A low-order peer key gives a shared secret anyone can compute
+    if !shared.was_contributory() {
+        return Err(EnvkeepError::InvalidPublicKey(format_public_key(peer)));
+    }
+
+    Ok(subkey(shared.as_bytes(), "envkeep export recipient"))
+}
+
+fn wrap_aad(ephemeral_public: &PublicKey, recipient: &PublicKey) -> Vec<u8> {
+    let mut aad = b"envkeep/recipient\x1f".to_vec();
+    aad.extend_from_slice(ephemeral_public.as_bytes());
+    aad.extend_from_slice(recipient.as_bytes());
+    aad
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity() -> StaticSecret {
+        StaticSecret::from(*generate_key().unwrap())
+    }
+
+    fn public(identity: &StaticSecret) -> PublicKey {
+        PublicKey::from(identity)
+    }
+
+    #[test]
+    fn every_recipient_can_open() {
+        let identities: Vec<StaticSecret> = (0..3).map(|_| identity()).collect();
+        let recipients: Vec<PublicKey> = identities.iter().map(public).collect();
+        let sealed = seal(b"API_KEY=secret", &recipients).unwrap();
+
+        assert_eq!(sealed.len(), KEY_LEN + 1 + 3 * (KEY_LEN + WRAPPED_LEN) + 12 + 14 + 16);
+        for identity in &identities {
+            assert_eq!(open(&sealed, identity).unwrap(), b"API_KEY=secret");
+        }
+    }
+
+    #[test]
+    fn each_seal_uses_new_keys() {
+        let alice = identity();
+        let first = seal(b"same", &[public(&alice)]).unwrap();
+        let second = seal(b"same", &[public(&alice)]).unwrap();
+        assert_ne!(first[..KEY_LEN], second[..KEY_LEN]);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn non_recipient_cannot_open() {
+        let sealed = seal(b"secret", &[public(&identity()), public(&identity())]).unwrap();
+        assert!(matches!(open(&sealed, &identity()), Err(EnvkeepError::NotARecipient)));
+    }
+
+    #[test]
+    fn tampered_file_does_not_open() {
+        let alice = identity();
+        let sealed = seal(b"secret", &[public(&identity()), public(&alice)]).unwrap();
+
+        // Every byte of the header (ephemeral key, count, recipients) and the payload
+        for i in 0..sealed.len() {
+            let mut tampered = sealed.clone();
+            tampered[i] ^= 0x01;
+            assert!(open(&tampered, &alice).is_err(), "byte {}", i);
+        }
+        for len in [0, KEY_LEN, KEY_LEN + 1, sealed.len() - 1] {
+            assert!(open(&sealed[..len], &alice).is_err(), "truncated to {}", len);
+        }
+    }
+
+    #[test]
+    fn recipient_list_cannot_be_swapped() {
+        let alice = identity();
+        let sealed = seal(b"secret", &[public(&alice), public(&identity())]).unwrap();
+
+        // Swap the two recipient slots: the header no longer matches the payload
+        let slot = KEY_LEN + WRAPPED_LEN;
+        let mut swapped = sealed.clone();
+        swapped[KEY_LEN + 1..KEY_LEN + 1 + 2 * slot].rotate_left(slot);
+        assert!(matches!(open(&swapped, &alice), Err(EnvkeepError::DecryptionError(_))));
+    }
+
+    #[test]
+    fn recipient_count_is_checked() {
+        assert!(matches!(seal(b"secret", &[]), Err(EnvkeepError::EncryptionError(_))));
+    }
+
+    #[test]
+    fn low_order_public_key_is_refused() {
+        let zero = PublicKey::from([0u8; KEY_LEN]);
+        assert!(matches!(seal(b"secret", &[zero]), Err(EnvkeepError::InvalidPublicKey(_))));
+    }
+
+    #[test]
+    fn public_key_text_round_trip() {
+        let key = public(&identity());
+        let text = format_public_key(&key);
+        assert!(text.starts_with(PUBLIC_KEY_PREFIX));
+        assert_eq!(parse_public_key(&format!("  {}\n", text)).unwrap(), key);
+
+        for input in ["", &text[PUBLIC_KEY_PREFIX.len()..], &text[..text.len() - 1], "ekpk_!!!!", "eksig_AAAA"] {
+            assert!(matches!(parse_public_key(input), Err(EnvkeepError::InvalidPublicKey(_))), "{:?}", input);
+        }
+    }
+}
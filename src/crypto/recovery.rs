@@ -0,0 +1,99 @@
+// Recovery keys: 256 random bits, written out for a human to keep.
+use ring::digest;
+
+use crate::crypto::secret::{SecretBytes, SecretKey};
+use crate::errors::EnvkeepError;
+
+const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+const KEY_LEN: usize = 32;
+const CHECKSUM_LEN: usize = 3;
+const GROUP: usize = 4;
+
+/// Base32 (RFC 4648) in dash-separated groups of four, with a short checksum
+/// at the end so a mistyped key is reported as such.
+///
+/// 32 key bytes + 3 checksum bytes = 56 characters, eg `ABCD-EFGH-...`.
+pub fn format(key: &[u8; KEY_LEN]) -> String {
+    encode(key)
+}
+
+/// Parse a key written by `format`. Case, spaces and dashes do not matter.
+pub fn parse(input: &str) -> Result<SecretKey, EnvkeepError> {
+    let bytes = SecretBytes::new(decode(input, KEY_LEN).map_err(EnvkeepError::InvalidRecoveryKey)?);
+    Ok(SecretKey::copy_from(&bytes).expect("decode checks the length"))
+}
+
+/// The text form of `format`, for any bytes (recovery shares use it too).
+pub fn encode(data: &[u8]) -> String {
+    let mut bytes = data.to_vec();
+    bytes.extend_from_slice(&checksum(data));
+
+    let encoded = base32_encode(&bytes);
+    encoded
+        .as_bytes()
+        .chunks(GROUP)
+        .map(|chunk| std::str::from_utf8(chunk).unwrap())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Parse `len` bytes written by `encode`, or say what is wrong with the input.
+pub fn decode(input: &str, len: usize) -> Result<Vec<u8>, String> {
+    let cleaned: String = input
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+
+    let mut bytes = base32_decode(&cleaned).ok_or_else(|| "unexpected character".to_string())?;
+    let expected = ((len + CHECKSUM_LEN) * 8).div_ceil(5);
+    if bytes.len() != len + CHECKSUM_LEN || cleaned.len() != expected {
+        return Err(format!("expected {} characters, got {}", expected, cleaned.len()));
+    }
+
+    let sum = bytes.split_off(len);
+    if checksum(&bytes) != sum[..] {
+        return Err("checksum does not match (typo?)".to_string());
+    }
+
+    Ok(bytes)
+}
+
+fn checksum(data: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let digest = digest::digest(&digest::SHA256, data);
+    let mut sum = [0u8; CHECKSUM_LEN];
+    sum.copy_from_slice(&digest.as_ref()[..CHECKSUM_LEN]);
+    sum
+}
+
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    out
+}
+
+fn base32_decode(input: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in input.bytes() {
+        let value = ALPHABET.iter().position(|&a| a == c)? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
@@ -0,0 +1,292 @@
+// Holders for passwords, keys and decrypted values.
+//
+// The contents are wiped when the holder is dropped, kept out of swap with
+// mlock where the OS allows it, and never printed by `Debug`.
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::ops::Deref;
+use zeroize::Zeroize;
+
+/// A secret that is zeroized on drop and locked into RAM while it lives.
+///
+/// There is no mutable access, so the buffer that was locked is the one that
+/// holds the secret until it is wiped.
+pub struct Secret<T: Protect> {
+    value: Box<T>,
+    /// Start and length of the locked buffer (0 if nothing was locked)
+    region: (usize, usize),
+}
+
+pub type SecretString = Secret<String>;
+pub type SecretKey = Secret<[u8; 32]>;
+pub type SecretBytes = Secret<Vec<u8>>;
+
+/// Values whose memory can be locked and wiped.
+pub trait Protect: Zeroize {
+    /// The heap or inline buffer holding the secret bytes.
+    fn region(&self) -> (*const u8, usize);
+}
+
+impl Protect for String {
+    fn region(&self) -> (*const u8, usize) {
+        (self.as_ptr(), self.capacity())
+    }
+}
+
+impl Protect for Vec<u8> {
+    fn region(&self) -> (*const u8, usize) {
+        (self.as_ptr(), self.capacity())
+    }
+}
+
+impl<const N: usize> Protect for [u8; N] {
+    fn region(&self) -> (*const u8, usize) {
+        (self.as_ptr(), N)
+    }
+}
+
+impl<T: Protect> Secret<T> {
+    pub fn new(value: T) -> Self {
+        let value = Box::new(value);
+        let (ptr, len) = value.region();
+        let region = if len > 0 && memory::lock(ptr as usize, len) {
+            (ptr as usize, len)
+        } else {
+            (0, 0)
+        };
+        Secret { value, region }
+    }
+}
+
+impl<const N: usize> Secret<[u8; N]> {
+    /// Build a key in place, so it is never copied out of locked memory.
+    pub fn init<E>(fill: impl FnOnce(&mut [u8; N]) -> Result<(), E>) -> Result<Self, E> {
+        let mut secret = Secret::new([0u8; N]);
+        fill(&mut secret.value)?;
+        Ok(secret)
+    }
+
+    /// Copy a key out of a decrypted buffer, if it has the right length.
+    pub fn copy_from(bytes: &[u8]) -> Option<Self> {
+        Self::init(|key| {
+            if bytes.len() != N {
+                return Err(());
+            }
+            key.copy_from_slice(bytes);
+            Ok(())
+        })
+        .ok()
+    }
+}
+
+impl SecretString {
+    pub fn as_str(&self) -> &str {
+        &self.value
+    }
+}
+
+impl<T: Protect> Deref for Secret<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: Protect + Clone> Clone for Secret<T> {
+    fn clone(&self) -> Self {
+        Secret::new((*self.value).clone())
+    }
+}
+
+impl<T: Protect + Default> Default for Secret<T> {
+    fn default() -> Self {
+        Secret::new(T::default())
+    }
+}
+
+impl<T: Protect + PartialEq> PartialEq for Secret<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl<T: Protect + Eq> Eq for Secret<T> {}
+
+impl<T: Protect> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret([REDACTED])")
+    }
+}
+
+impl<T: Protect> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.value.zeroize();
+        let (start, len) = self.region;
+        if len > 0 {
+            memory::unlock(start, len);
+        }
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(value: String) -> Self {
+        Secret::new(value)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(value: &str) -> Self {
+        Secret::new(value.to_string())
+    }
+}
+
+/// Exports carry decrypted values; they are written as plain strings.
+impl Serialize for SecretString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(SecretString::new)
+    }
+}
+
+#[cfg(unix)]
+mod memory {
+    use std::collections::HashMap;
+    use std::sync::{Mutex, OnceLock};
+
+    /// How many live secrets use each locked page. mlock does not nest, so a
+    /// page is only unlocked when the last secret on it is dropped.
+    static PAGES: Mutex<Option<HashMap<usize, usize>>> = Mutex::new(None);
+
+    fn page_size() -> usize {
+        static PAGE_SIZE: OnceLock<usize> = OnceLock::new();
+        *PAGE_SIZE.get_or_init(|| {
+            // SAFETY: sysconf has no preconditions
+            let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+            if size > 0 { size as usize } else { 4096 }
+        })
+    }
+
+    fn pages(start: usize, len: usize) -> impl Iterator<Item = usize> {
+        let size = page_size();
+        let first = start / size * size;
+        (first..start + len).step_by(size)
+    }
+
+    /// Lock the pages under `start..start + len`. Returns false (and locks
+    /// nothing) if the OS refuses, eg over RLIMIT_MEMLOCK; the secret is
+    /// still zeroized then.
+    pub fn lock(start: usize, len: usize) -> bool {
+        let mut guard = PAGES.lock().unwrap_or_else(|e| e.into_inner());
+        let counts = guard.get_or_insert_with(HashMap::new);
+
+        let new: Vec<usize> = pages(start, len).filter(|page| !counts.contains_key(page)).collect();
+        for (i, page) in new.iter().enumerate() {
+            // SAFETY: the page is mapped, it holds part of a live allocation
+            if unsafe { libc::mlock(*page as *const libc::c_void, page_size()) } != 0 {
+                for page in &new[..i] {
+                    // SAFETY: locked just above
+                    unsafe { libc::munlock(*page as *const libc::c_void, page_size()) };
+                }
+                return false;
+            }
+        }
+
+        for page in pages(start, len) {
+            *counts.entry(page).or_insert(0) += 1;
+        }
+        true
+    }
+
+    pub fn unlock(start: usize, len: usize) {
+        let mut guard = PAGES.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(counts) = guard.as_mut() else { return };
+
+        for page in pages(start, len) {
+            if let Some(count) = counts.get_mut(&page) {
+                *count -= 1;
+                if *count == 0 {
+                    counts.remove(&page);
+                    // SAFETY: the page was locked by `lock`
+                    unsafe { libc::munlock(page as *const libc::c_void, page_size()) };
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod memory {
+    pub fn lock(_start: usize, _len: usize) -> bool {
+        false
+    }
+
+    pub fn unlock(_start: usize, _len: usize) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_is_redacted() {
+        let password = SecretString::from("hunter2");
+        let key = SecretKey::new([0x41; 32]);
+        let bytes = SecretBytes::new(b"API_KEY=secret".to_vec());
+
+        for debug in [format!("{:?}", password), format!("{:?}", key), format!("{:?}", bytes)] {
+            assert_eq!(debug, "Secret([REDACTED])");
+        }
+        assert!(!format!("{:?}", Some(password)).contains("hunter2"));
+    }
+
+    #[test]
+    fn copy_from_checks_the_length() {
+        let key = SecretKey::copy_from(&[9u8; 32]).unwrap();
+        assert_eq!(*key, [9u8; 32]);
+
+        for len in [0, 16, 31, 33, 64] {
+            assert!(SecretKey::copy_from(&vec![9u8; len]).is_none(), "length {}", len);
+        }
+    }
+
+    #[test]
+    fn init_builds_in_place_or_fails() {
+        let key: Result<SecretKey, ()> = Secret::init(|key| {
+            key[0] = 1;
+            key[31] = 2;
+            Ok(())
+        });
+        let key = key.unwrap();
+        assert_eq!((key[0], key[1], key[31]), (1, 0, 2));
+
+        let failed: Result<SecretKey, &str> = Secret::init(|_| Err("no entropy"));
+        assert_eq!(failed.unwrap_err(), "no entropy");
+    }
+
+    #[test]
+    fn contents_round_trip() {
+        let password = SecretString::from("pässwörd");
+        assert_eq!(password.as_str(), "pässwörd");
+        assert_eq!(password.len(), "pässwörd".len());
+        assert_eq!(password.clone(), password);
+        assert_ne!(password, SecretString::from("other"));
+
+        let bytes = SecretBytes::new(vec![1, 2, 3]);
+        assert_eq!(&bytes[..], &[1, 2, 3]);
+        assert!(SecretString::default().is_empty());
+    }
+
+    #[test]
+    fn string_serde_round_trip() {
+        let value = SecretString::from("a \"quoted\" value\n");
+        let json = serde_json::to_string(&value).unwrap();
+        assert_eq!(json, r#""a \"quoted\" value\n""#);
+        assert_eq!(serde_json::from_str::<SecretString>(&json).unwrap(), value);
+    }
+}
@@ -0,0 +1,259 @@
+// Shamir secret sharing over GF(256): any `threshold` of the shares rebuild
+// the secret, fewer reveal nothing about it.
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::crypto::recovery;
+use crate::crypto::secret::SecretKey;
+use crate::errors::EnvkeepError;
+
+const SHARE_VERSION: u8 = 1;
+const SECRET_LEN: usize = 32;
+/// version | threshold | x | set id (4) | share of each secret byte
+const SHARE_LEN: usize = 1 + 1 + 1 + 4 + SECRET_LEN;
+
+/// One share of a split secret.
+#[derive(Debug, Clone)]
+pub struct Share {
+    /// How many shares rebuild the secret
+    pub threshold: u8,
+    /// Point the polynomials are evaluated at (1..=255)
+    pub x: u8,
+    /// Random per split, so shares of different splits are not mixed up
+    pub set_id: [u8; 4],
+    y: [u8; SECRET_LEN],
+}
+
+impl Share {
+    /// Text for a human to keep: the recovery key format, so typos are caught.
+    pub fn encode(&self) -> String {
+        let mut bytes = vec![SHARE_VERSION, self.threshold, self.x];
+        bytes.extend_from_slice(&self.set_id);
+        bytes.extend_from_slice(&self.y);
+        recovery::encode(&bytes)
+    }
+
+    pub fn decode(input: &str) -> Result<Self, EnvkeepError> {
+        let bytes = recovery::decode(input, SHARE_LEN).map_err(EnvkeepError::InvalidShare)?;
+        if bytes[0] != SHARE_VERSION {
+            return Err(EnvkeepError::InvalidShare(format!("unknown share version {}", bytes[0])));
+        }
+        if bytes[1] < 2 || bytes[2] == 0 {
+            return Err(EnvkeepError::InvalidShare("damaged share header".to_string()));
+        }
+
+        let mut share = Share {
+            threshold: bytes[1],
+            x: bytes[2],
+            set_id: [0u8; 4],
+            y: [0u8; SECRET_LEN],
+        };
+        share.set_id.copy_from_slice(&bytes[3..7]);
+        share.y.copy_from_slice(&bytes[7..]);
+        Ok(share)
+    }
+}
+
+/// Split a secret into `shares` shares, any `threshold` of which rebuild it.
+pub fn split(secret: &[u8; SECRET_LEN], threshold: u8, shares: u8) -> Result<Vec<Share>, EnvkeepError> {
+    if threshold < 2 || threshold > shares {
+        return Err(EnvkeepError::InvalidShare(format!(
+            "the threshold must be between 2 and the number of shares ({})",
+            shares
+        )));
+    }
+
+    let rng = SystemRandom::new();
+    let random_error = |_| EnvkeepError::EncryptionError("failed to generate random bytes".to_string());
+
+    let mut set_id = [0u8; 4];
+    rng.fill(&mut set_id).map_err(random_error)?;
+
+    // One polynomial per secret byte: the byte is the constant term, the other
+    // threshold - 1 coefficients are random
+    let mut coefficients = vec![[0u8; SECRET_LEN]; threshold as usize];
+    coefficients[0] = *secret;
+    for coefficient in coefficients.iter_mut().skip(1) {
+        rng.fill(coefficient).map_err(random_error)?;
+    }
+
+    Ok((1..=shares)
+        .map(|x| {
+            let mut y = [0u8; SECRET_LEN];
+            for (i, byte) in y.iter_mut().enumerate() {
+                // Horner's rule, highest coefficient first
+                *byte = coefficients.iter().rev().fold(0, |acc, c| mul(acc, x) ^ c[i]);
+            }
+            Share { threshold, x, set_id, y }
+        })
+        .collect())
+}
+
+/// Rebuild the secret from at least `threshold` shares of the same split.
+pub fn combine(shares: &[Share]) -> Result<SecretKey, EnvkeepError> {
+    let first = shares.first().ok_or_else(|| EnvkeepError::InvalidShare("no shares given".to_string()))?;
+
+    if shares.iter().any(|s| s.set_id != first.set_id || s.threshold != first.threshold) {
+        return Err(EnvkeepError::InvalidShare("the shares come from different splits".to_string()));
+    }
+    for (i, share) in shares.iter().enumerate() {
+        if shares[..i].iter().any(|other| other.x == share.x) {
+            return Err(EnvkeepError::InvalidShare(format!("share {} was given twice", share.x)));
+        }
+    }
+    if shares.len() < first.threshold as usize {
+        return Err(EnvkeepError::InvalidShare(format!(
+            "{} shares are needed, got {}",
+            first.threshold,
+            shares.len()
+        )));
+    }
+
+    // Lagrange interpolation at x = 0 (in GF(256) subtraction is xor)
+    let used = &shares[..first.threshold as usize];
+    SecretKey::init(|secret| {
+        for share in used {
+            let mut basis = 1u8;
+            for other in used.iter().filter(|other| other.x != share.x) {
+                basis = mul(basis, div(other.x, other.x ^ share.x));
+            }
+            for (byte, y) in secret.iter_mut().zip(share.y.iter()) {
+                *byte ^= mul(basis, *y);
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Multiplication in GF(256) with the AES polynomial, without data-dependent branches.
+fn mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0u8;
+    for _ in 0..8 {
+        product ^= a & 0u8.wrapping_sub(b & 1);
+        let carry = 0u8.wrapping_sub(a >> 7);
+        a = (a << 1) ^ (0x1b & carry);
+        b >>= 1;
+    }
+    product
+}
+
+/// a / b in GF(256); b is never 0 here (share points are distinct).
+fn div(a: u8, b: u8) -> u8 {
+    // b^254 = b^-1
+    let mut inverse = 1u8;
+    for _ in 0..254 {
+        inverse = mul(inverse, b);
+    }
+    mul(a, inverse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: [u8; SECRET_LEN] = *b"0123456789abcdef0123456789abcdef";
+
+    /// The shares picked by the bits of `mask`.
+    fn pick(shares: &[Share], mask: u32) -> Vec<Share> {
+        shares
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| mask & (1 << i) != 0)
+            .map(|(_, share)| share.clone())
+            .collect()
+    }
+
+    #[test]
+    fn any_threshold_of_the_shares_rebuild_the_secret() {
+        let shares = split(&SECRET, 3, 5).unwrap();
+        assert_eq!(shares.len(), 5);
+
+        for mask in 1u32..1 << shares.len() {
+            let picked = pick(&shares, mask);
+            if picked.len() >= 3 {
+                assert_eq!(*combine(&picked).unwrap(), SECRET, "shares {:05b}", mask);
+            }
+        }
+    }
+
+    #[test]
+    fn fewer_than_threshold_shares_are_rejected() {
+        let shares = split(&SECRET, 3, 5).unwrap();
+
+        for mask in 1u32..1 << shares.len() {
+            let picked = pick(&shares, mask);
+            if picked.len() < 3 {
+                assert!(combine(&picked).is_err(), "shares {:05b}", mask);
+            }
+        }
+        assert!(combine(&[]).is_err());
+    }
+
+    #[test]
+    fn invalid_thresholds_are_rejected() {
+        assert!(split(&SECRET, 1, 5).is_err());
+        assert!(split(&SECRET, 6, 5).is_err());
+        assert_eq!(*combine(&split(&SECRET, 2, 255).unwrap()[253..]).unwrap(), SECRET);
+    }
+
+    #[test]
+    fn duplicate_shares_are_rejected() {
+        let shares = split(&SECRET, 3, 5).unwrap();
+        let duplicated = vec![shares[0].clone(), shares[0].clone(), shares[1].clone()];
+        assert!(combine(&duplicated).is_err());
+    }
+
+    #[test]
+    fn shares_of_different_splits_are_rejected() {
+        let first = split(&SECRET, 3, 5).unwrap();
+        let second = split(&SECRET, 3, 5).unwrap();
+        assert_ne!(first[0].set_id, second[0].set_id);
+
+        let mixed = vec![first[0].clone(), second[1].clone(), first[2].clone()];
+        assert!(combine(&mixed).is_err());
+    }
+
+    #[test]
+    fn share_text_round_trip() {
+        let shares = split(&SECRET, 2, 3).unwrap();
+        let decoded: Vec<Share> = shares.iter().map(|share| Share::decode(&share.encode()).unwrap()).collect();
+
+        for (share, decoded) in shares.iter().zip(&decoded) {
+            assert_eq!(decoded.threshold, share.threshold);
+            assert_eq!(decoded.x, share.x);
+            assert_eq!(decoded.set_id, share.set_id);
+            assert_eq!(decoded.y, share.y);
+        }
+        assert_eq!(*combine(&decoded[1..]).unwrap(), SECRET);
+    }
+
+    #[test]
+    fn damaged_share_header_is_rejected() {
+        let share = &split(&SECRET, 2, 3).unwrap()[0];
+        let mut bytes = vec![SHARE_VERSION, share.threshold, share.x];
+        bytes.extend_from_slice(&share.set_id);
+        bytes.extend_from_slice(&share.y);
+        assert!(Share::decode(&recovery::encode(&bytes)).is_ok());
+
+        for (index, value) in [(0, SHARE_VERSION + 1), (1, 1), (2, 0)] {
+            let mut damaged = bytes.clone();
+            damaged[index] = value;
+            assert!(Share::decode(&recovery::encode(&damaged)).is_err(), "byte {} = {}", index, value);
+        }
+    }
+
+    #[test]
+    fn multiplication_matches_the_aes_field() {
+        // Examples from FIPS-197, section 4.2
+        assert_eq!(mul(0x57, 0x83), 0xc1);
+        assert_eq!(mul(0x57, 0x13), 0xfe);
+
+        for a in 0..=255u8 {
+            assert_eq!(mul(a, 0), 0);
+            assert_eq!(mul(a, 1), a);
+            for b in 1..=255u8 {
+                assert_eq!(mul(a, b), mul(b, a));
+                assert_eq!(div(mul(a, b), b), a);
+            }
+        }
+    }
+}
@@ -0,0 +1,142 @@
+// Ed25519 signatures on exports, so an import can tell who wrote a file.
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use ring::digest;
+use ring::signature::{self, Ed25519KeyPair, KeyPair, UnparsedPublicKey};
+
+use crate::errors::EnvkeepError;
+
+/// Prefix of a signing key as printed by `envkeep identity show --signing`.
+const SIGNING_KEY_PREFIX: &str = "eksig_";
+pub const PUBLIC_KEY_LEN: usize = 32;
+pub const SIGNATURE_LEN: usize = 64;
+/// Signatures cover this and then the data, so they mean nothing elsewhere.
+const CONTEXT: &[u8] = b"envkeep export signature\x00";
+
+/// Signing key pair from the 32-byte seed kept in the keyring.
+pub fn key_pair(seed: &[u8; 32]) -> Result<Ed25519KeyPair, EnvkeepError> {
+    Ed25519KeyPair::from_seed_unchecked(seed)
+        .map_err(|_| EnvkeepError::EncryptionError("invalid signing key".to_string()))
+}
+
+pub fn public_key(key_pair: &Ed25519KeyPair) -> [u8; PUBLIC_KEY_LEN] {
+    key_pair
+        .public_key()
+        .as_ref()
+        .try_into()
+        .expect("Ed25519 public keys are 32 bytes")
+}
+
+pub fn sign(key_pair: &Ed25519KeyPair, data: &[u8]) -> [u8; SIGNATURE_LEN] {
+    let mut message = CONTEXT.to_vec();
+    message.extend_from_slice(data);
+    key_pair
+        .sign(&message)
+        .as_ref()
+        .try_into()
+        .expect("Ed25519 signatures are 64 bytes")
+}
+
+pub fn verify(public_key: &[u8; PUBLIC_KEY_LEN], data: &[u8], signature: &[u8]) -> Result<(), EnvkeepError> {
+    let mut message = CONTEXT.to_vec();
+    message.extend_from_slice(data);
+    UnparsedPublicKey::new(&signature::ED25519, public_key)
+        .verify(&message, signature)
+        .map_err(|_| EnvkeepError::BadSignature)
+}
+
+/// Print a signing key for a human to pass around (and `envkeep trust add`).
+pub fn format_key(public_key: &[u8; PUBLIC_KEY_LEN]) -> String {
+    format!("{}{}", SIGNING_KEY_PREFIX, URL_SAFE_NO_PAD.encode(public_key))
+}
+
+/// Parse a key written by `format_key`.
+pub fn parse_key(input: &str) -> Result<[u8; PUBLIC_KEY_LEN], EnvkeepError> {
+    let invalid = || EnvkeepError::InvalidSigningKey(input.to_string());
+    let encoded = input.trim().strip_prefix(SIGNING_KEY_PREFIX).ok_or_else(invalid)?;
+    URL_SAFE_NO_PAD
+        .decode(encoded)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(invalid)
+}
+
+/// Short form to compare by eye: the first 16 bytes of SHA-256, eg `3f2a:9c1d:...`.
+pub fn fingerprint(public_key: &[u8; PUBLIC_KEY_LEN]) -> String {
+    digest::digest(&digest::SHA256, public_key).as_ref()[..16]
+        .chunks(2)
+        .map(|pair| format!("{:02x}{:02x}", pair[0], pair[1]))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: [u8; 32] = [7u8; 32];
+
+    #[test]
+    fn signature_verifies() {
+        let pair = key_pair(&SEED).unwrap();
+        let signature = sign(&pair, b"export");
+
+        assert_eq!(public_key(&key_pair(&SEED).unwrap()), public_key(&pair));
+        assert!(verify(&public_key(&pair), b"export", &signature).is_ok());
+    }
+
+    #[test]
+    fn wrong_key_is_refused() {
+        let pair = key_pair(&SEED).unwrap();
+        let other = key_pair(&[8u8; 32]).unwrap();
+        let signature = sign(&pair, b"export");
+
+        assert!(matches!(verify(&public_key(&other), b"export", &signature), Err(EnvkeepError::BadSignature)));
+    }
+
+    #[test]
+    fn changed_data_or_signature_is_refused() {
+        let pair = key_pair(&SEED).unwrap();
+        let public = public_key(&pair);
+        let signature = sign(&pair, b"export");
+
+        assert!(matches!(verify(&public, b"exporT", &signature), Err(EnvkeepError::BadSignature)));
+        assert!(matches!(verify(&public, b"", &signature), Err(EnvkeepError::BadSignature)));
+        for i in 0..SIGNATURE_LEN {
+            let mut tampered = signature;
+            tampered[i] ^= 0x01;
+            assert!(verify(&public, b"export", &tampered).is_err(), "byte {}", i);
+        }
+        assert!(verify(&public, b"export", &signature[..SIGNATURE_LEN - 1]).is_err());
+    }
+
+    #[test]
+    fn signature_covers_the_context() {
+        // A plain Ed25519 signature of the same data is not an export signature
+        let pair = key_pair(&SEED).unwrap();
+        let plain = pair.sign(b"export");
+        assert!(verify(&public_key(&pair), b"export", plain.as_ref()).is_err());
+    }
+
+    #[test]
+    fn key_text_round_trip() {
+        let public = public_key(&key_pair(&SEED).unwrap());
+        let text = format_key(&public);
+        assert!(text.starts_with(SIGNING_KEY_PREFIX));
+        assert_eq!(parse_key(&format!(" {}\n", text)).unwrap(), public);
+
+        for input in ["", &text[SIGNING_KEY_PREFIX.len()..], &text[..text.len() - 1], "ekpk_AAAA"] {
+            assert!(matches!(parse_key(input), Err(EnvkeepError::InvalidSigningKey(_))), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn fingerprint_is_sixteen_bytes() {
+        let public = public_key(&key_pair(&SEED).unwrap());
+        let fingerprint = fingerprint(&public);
+
+        assert_eq!(fingerprint.split(':').count(), 8);
+        assert!(fingerprint.split(':').all(|group| group.len() == 4));
+        assert_ne!(fingerprint, self::fingerprint(&public_key(&key_pair(&[8u8; 32]).unwrap())));
+    }
+}
@@ -2,6 +2,7 @@ use std::collections::BTreeMap;
 use std::fs;
 use std::path::Path;
 
+use crate::crypto::secret::SecretString;
 use crate::errors::EnvkeepError;
 
 /// Parse a .env file into key-value pairs.
@@ -13,9 +14,12 @@ use crate::errors::EnvkeepError;
 /// - # comments (ignored)
 /// - Empty lines (ignored)
 /// - Inline comments after values
-pub fn parse_env_file(path: &Path) -> Result<BTreeMap<String, String>, EnvkeepError> {
-    let contents = fs::read_to_string(path)
-        .map_err(|e| EnvkeepError::FileReadError(path.display().to_string(), e))?;
+///
+/// Values are secrets, as is the file contents while it is parsed.
+pub fn parse_env_file(path: &Path) -> Result<BTreeMap<String, SecretString>, EnvkeepError> {
+    let contents = SecretString::new(
+        fs::read_to_string(path).map_err(|e| EnvkeepError::FileReadError(path.display().to_string(), e))?,
+    );
 
     let mut vars = BTreeMap::new();
 
@@ -36,12 +40,12 @@ pub fn parse_env_file(path: &Path) -> Result<BTreeMap<String, String>, EnvkeepEr
             let value = if (raw_value.starts_with('"') && raw_value.ends_with('"'))
                 || (raw_value.starts_with('\'') && raw_value.ends_with('\''))
             {
-                raw_value[1..raw_value.len() - 1].to_string()
+                SecretString::from(&raw_value[1..raw_value.len() - 1])
             } else {
                 // Remove inline comments (space + #)
                 match raw_value.find(" #") {
-                    Some(pos) => raw_value[..pos].trim().to_string(),
-                    None => raw_value.to_string(),
+                    Some(pos) => SecretString::from(raw_value[..pos].trim()),
+                    None => SecretString::from(raw_value),
                 }
             };
 
@@ -57,20 +61,25 @@ pub fn parse_env_file(path: &Path) -> Result<BTreeMap<String, String>, EnvkeepEr
 /// Write key-value pairs to a .env file.
 pub fn write_env_file(
     path: &Path,
-    vars: &BTreeMap<String, String>,
+    vars: &BTreeMap<String, SecretString>,
 ) -> Result<(), EnvkeepError> {
-    let mut content = String::new();
+    // Sized up front so the buffer never reallocates and leaves copies behind
+    let len = vars.iter().map(|(key, value)| key.len() + value.len() + 4).sum();
+    let mut content = String::with_capacity(len);
 
     for (key, value) in vars {
         // Quote values with spaces or special characters
-        if value.contains(' ') || value.contains('#') || value.contains('=') {
-            content.push_str(&format!("{}=\"{}\"\n", key, value));
-        } else {
-            content.push_str(&format!("{}={}\n", key, value));
-        }
+        let quote = if value.contains(' ') || value.contains('#') || value.contains('=') { "\"" } else { "" };
+        content.push_str(key);
+        content.push('=');
+        content.push_str(quote);
+        content.push_str(value);
+        content.push_str(quote);
+        content.push('\n');
     }
+    let content = SecretString::new(content);
 
-    fs::write(path, content)
+    fs::write(path, content.as_bytes())
         .map_err(|e| EnvkeepError::FileWriteError(path.display().to_string(), e))?;
 
     Ok(())
@@ -12,10 +12,73 @@ pub enum EnvkeepError {
     #[error("Failed to open vault database: {0}")]
     DatabaseError(#[from] rusqlite::Error),
 
+    #[error("Vault schema version {0} is newer than this envkeep supports (up to {1}). Upgrade envkeep.")]
+    VaultTooNew(u32, u32),
+
+    #[error("Migration to schema version {0} failed: {1}")]
+    MigrationFailed(u32, String),
+
+    #[error("{0} is not an envkeep vault: {1}")]
+    NotAVault(String, String),
+
+    #[error("The vault is corrupted: {0} (restore a backup with 'envkeep restore')")]
+    VaultCorrupted(String),
+
+    #[error("The vault metadata is missing {0} (restore a backup with 'envkeep restore')")]
+    MissingMetadata(String),
+
     // Crypto errors
     #[error("Wrong master password")]
     WrongPassword,
 
+    #[error("Could not read the master password: {0}")]
+    PasswordUnavailable(String),
+
+    #[error("Password file '{0}' is readable by everyone (run 'chmod 600 {0}')")]
+    InsecurePasswordFile(String),
+
+    #[error("Vault key file: {0}")]
+    KeyFileError(String),
+
+    #[error("Invalid recovery key: {0}")]
+    InvalidRecoveryKey(String),
+
+    #[error("Wrong recovery key")]
+    WrongRecoveryKey,
+
+    #[error("This vault has no recovery key (create one with 'envkeep recovery new')")]
+    NoRecoveryKey,
+
+    #[error("Too many wrong master passwords; try again in {0} seconds")]
+    UnlockThrottled(u64),
+
+    #[error("The vault is locked out after {0} wrong master passwords in a row (unlock it with 'envkeep recover' or 'envkeep recovery combine')")]
+    LockedOut(u32),
+
+    #[error("Unlock attempts file {0}")]
+    AttemptsFileError(String),
+
+    #[error("Invalid recovery share: {0}")]
+    InvalidShare(String),
+
+    #[error("Invalid public key '{0}' (expected the output of 'envkeep identity show')")]
+    InvalidPublicKey(String),
+
+    #[error("This file was not encrypted to this vault's identity")]
+    NotARecipient,
+
+    #[error("Invalid signing key '{0}' (expected the output of 'envkeep identity show --signing')")]
+    InvalidSigningKey(String),
+
+    #[error("The export's signature does not match its contents (the file was modified)")]
+    BadSignature,
+
+    #[error("The export is not signed (pass --allow-unsigned to import it anyway)")]
+    UnsignedExport,
+
+    #[error("The export is signed by {0}, which is not a trusted signer (check it with the sender, then 'envkeep trust add {1}')")]
+    UntrustedSigner(String, String),
+
     #[error("Encryption failed: {0}")]
     EncryptionError(String),
 
@@ -25,13 +88,31 @@ pub enum EnvkeepError {
     #[error("Key derivation failed: {0}")]
     KeyDerivationError(String),
 
+    #[error("No key in the keyring for {0} (the vault is damaged)")]
+    MissingOwnerKey(String),
+
+    #[error("Unlock agent: {0}")]
+    AgentError(String),
+
     // Project errors
     #[error("Project '{0}' not found")]
     ProjectNotFound(String),
 
-    #[error("Project '{0}' already exists")]
+    #[error("Project '{0}' already exists (use 'envkeep update {0}' to push .env changes)")]
     ProjectAlreadyExists(String),
 
+    #[error("Project '{0}' has no environment '{1}'")]
+    EnvironmentNotFound(String, String),
+
+    #[error("Environment '{1}' already exists in project '{0}'")]
+    EnvironmentAlreadyExists(String, String),
+
+    #[error("Variable '{1}' not found in {0}")]
+    VariableNotFound(String, String),
+
+    #[error("Invalid name '{0}' (use letters, digits, '.', '-' and '_')")]
+    InvalidName(String),
+
     // File errors
     #[error("No .env file found in current directory")]
     NoEnvFile,
@@ -46,13 +127,55 @@ pub enum EnvkeepError {
     #[error("Secret '{0}' not found")]
     SecretNotFound(String),
 
+    #[error("Secret '{0}' already exists")]
+    SecretAlreadyExists(String),
+
+    #[error("Secret '{0}' is linked to {1} project(s); unlink it first or pass --force")]
+    SecretStillLinked(String, usize),
+
+    #[error("'{0}' has {1} different values across projects; pick one with --from <project>")]
+    AmbiguousPromotion(String, usize),
+
+    #[error("Secret '{0}' has no hook #{1}")]
+    HookNotFound(String, i64),
+
+    #[error("Rotation hook #{0} failed: {1}")]
+    HookFailed(i64, String),
+
+    #[error("Invalid generator: {0}")]
+    InvalidGenerator(String),
+
     #[error("Invalid key=value format: '{0}'")]
     InvalidKeyValue(String),
 
+    #[error("Invalid rollback point '{0}' (use a revision number or a timestamp)")]
+    InvalidRollbackPoint(String),
+
+    #[error("Audit log has been tampered with at entry {0}: {1}")]
+    AuditChainBroken(i64, String),
+
     // Export/Import errors
     #[error("Invalid vault file format")]
     InvalidVaultFile,
 
     #[error("Backup file not found: {0}")]
     BackupNotFound(String),
-}
\ No newline at end of file
+}
+
+impl EnvkeepError {
+    /// Exit code of the process when a command fails with this error, so
+    /// scripts can tell unlock failures apart (1 for everything else).
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            EnvkeepError::VaultNotFound => 3,
+            EnvkeepError::WrongPassword | EnvkeepError::WrongRecoveryKey => 4,
+            EnvkeepError::NotAVault(..) => 5,
+            EnvkeepError::VaultCorrupted(_) => 6,
+            EnvkeepError::MissingMetadata(_) => 7,
+            EnvkeepError::VaultTooNew(..) => 8,
+            EnvkeepError::UnlockThrottled(_) => 9,
+            EnvkeepError::LockedOut(_) => 10,
+            _ => 1,
+        }
+    }
+}
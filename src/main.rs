@@ -1,26 +1,78 @@
+mod agent;
 mod env_parser;
 mod cli;
 mod crypto;
 mod errors;
 mod tui;
+mod unlock;
 mod vault;
 
 
 use anyhow::Result;
 use clap::Parser;
 use cli::{Cli, Commands, SecretsAction};
+use errors::EnvkeepError;
 
-fn main() -> Result<()> {
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {:?}", e);
+        // Unlock failures get their own exit codes (see `EnvkeepError::exit_code`)
+        let code = e
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<EnvkeepError>())
+            .map_or(1, EnvkeepError::exit_code);
+        std::process::exit(code);
+    }
+}
+
+fn run() -> Result<()> {
     let cli = Cli::parse();
+    unlock::configure(unlock::Options {
+        password_file: cli.password_file,
+        password_fd: cli.password_fd,
+        password_command: cli.password_command,
+    });
 
     match cli.command {
-        Commands::Init => cli::init::handle_init()?,
-        Commands::Add { name } => cli::add::handle_add(&name)?,
+        Commands::Init { recovery_file } => cli::init::handle_init(recovery_file.as_deref())?,
+        Commands::Recover { recovery_file } => cli::recover::handle_recover(recovery_file.as_deref())?,
+        Commands::Recovery { action } => cli::recovery::handle_recovery(action)?,
+        Commands::Add { name, env } => cli::add::handle_add(&name, env.as_deref())?,
         Commands::AddAuto => cli::add_auto::handle_add_auto()?,
         Commands::List => cli::list::handle_list()?,
-        Commands::Use { project } => cli::use_project::handle_use(&project)?,
+        Commands::Use { project, env } => cli::use_project::handle_use(&project, env.as_deref())?,
         Commands::Remove { name } => cli::remove::handle_remove(&name)?,
-        Commands::Inspect { name } => cli::inspect::handle_inspect(&name)?,
+        Commands::Inspect { name, env } => cli::inspect::handle_inspect(&name, env.as_deref())?,
+        Commands::History { project, key, env } => {
+            cli::history::handle_history(&project, key.as_deref(), env.as_deref())?
+        }
+        Commands::Rollback { project, key, to, env } => {
+            cli::rollback::handle_rollback(&project, key.as_deref(), &to, env.as_deref())?
+        }
+        Commands::Audit { action, project, since } => {
+            cli::audit::handle_audit(action, project.as_deref(), since.as_deref())?
+        }
+        Commands::Update { project, env, policy, yes } => {
+            cli::update::handle_update(&project, env.as_deref(), policy, yes)?
+        }
+        Commands::Passwd { recovery_file } => cli::passwd::handle_passwd(recovery_file.as_deref())?,
+        Commands::Kdf { action } => cli::kdf::handle_kdf(action)?,
+        Commands::Rekey { name, secret } => cli::rekey::handle_rekey(&name, secret)?,
+        Commands::Agent { action } => cli::agent::handle_agent(action)?,
+        Commands::Lock => cli::lock::handle_lock()?,
+        Commands::Lockout { action } => cli::lockout::handle_lockout(action)?,
+        Commands::Set { project, pairs, env } => {
+            cli::set::handle_set(&project, &pairs, env.as_deref())?
+        }
+        Commands::Get { project, key, env } => {
+            cli::get::handle_get(&project, &key, env.as_deref())?
+        }
+        Commands::Unset { project, keys, env } => {
+            cli::unset::handle_unset(&project, &keys, env.as_deref())?
+        }
+        Commands::Envs { project, set_default } => {
+            cli::envs::handle_envs(&project, set_default.as_deref())?
+        }
         Commands::Diff { project1, project2 } => cli::diff::handle_diff(&project1, &project2)?,
         Commands::Search { key } => cli::search::handle_search(&key)?,
         Commands::Unused { project } => cli::unused::handle_unused(&project)?,
@@ -29,13 +81,16 @@ fn main() -> Result<()> {
         Commands::Types { project } => cli::types::handle_types(&project)?,
         Commands::Sync { from, to } => cli::sync::handle_sync(&from, &to)?,
         Commands::Generate { template } => cli::generate::handle_generate(&template)?,
-        Commands::Export { project } => cli::export::handle_export(&project)?,
-        Commands::Import { file } => cli::import::handle_import(&file)?,
+        Commands::Export { project, to } => cli::export::handle_export(&project, &to)?,
+        Commands::Identity { action } => cli::identity::handle_identity(action)?,
+        Commands::Import { file, allow_unsigned } => cli::import::handle_import(&file, allow_unsigned)?,
+        Commands::Trust { action } => cli::trust::handle_trust(action)?,
         Commands::Backup => cli::backup::handle_backup()?,
         Commands::Restore { file } => cli::restore::handle_restore(&file)?,
         Commands::Status => cli::status::handle_status()?,
         Commands::Recent => cli::recent::handle_recent()?,
-        Commands::Migrate => cli::migrate::handle_migrate()?,
+        Commands::Migrate { dry_run } => cli::migrate::handle_migrate(dry_run)?,
+        Commands::Doctor => cli::doctor::handle_doctor()?,
         Commands::Tui => println!("envkeep tui: launching..."),
         // _=> {
         //     println!("Command not implemented yet.");
@@ -0,0 +1,192 @@
+// One place every command gets the master password from.
+use rusqlite::Connection;
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+use crate::agent;
+use crate::crypto;
+use crate::crypto::secret::{SecretBytes, SecretString};
+use crate::errors::EnvkeepError;
+use crate::vault;
+use crate::vault::keyring::Keyring;
+
+/// Environment variable holding the master password.
+pub const PASSWORD_ENV: &str = "ENVKEEP_PASSWORD";
+
+/// Environment variable holding a command that prints the master password.
+pub const PASSWORD_COMMAND_ENV: &str = "ENVKEEP_PASSWORD_COMMAND";
+
+/// Where to read the master password from, set once from the global CLI flags.
+#[derive(Debug, Default)]
+pub struct Options {
+    pub password_file: Option<PathBuf>,
+    pub password_fd: Option<u32>,
+    pub password_command: Option<String>,
+}
+
+static OPTIONS: OnceLock<Options> = OnceLock::new();
+
+/// Record the global flags. Call once, before any command runs.
+pub fn configure(options: Options) {
+    let _ = OPTIONS.set(options);
+}
+
+fn options() -> &'static Options {
+    OPTIONS.get_or_init(Options::default)
+}
+
+/// The master password, from the first source that is set:
+///
+/// 1. `--password-fd`, `--password-file` or `--password-command`
+/// 2. `ENVKEEP_PASSWORD`, then `ENVKEEP_PASSWORD_COMMAND`
+/// 3. a running agent (`envkeep agent start`)
+/// 4. a prompt on the terminal
+///
+/// The agent only holds the data key. When it is used the password returned
+/// is empty, and `vault::data_key` takes the key from the agent instead.
+pub fn password() -> Result<SecretString, EnvkeepError> {
+    if let Some(password) = non_interactive_password()? {
+        return Ok(password);
+    }
+    if agent::is_unlocked() {
+        return Ok(SecretString::default());
+    }
+    crypto::prompt_existing_password()
+}
+
+/// Like `password`, but never unlocks through the agent. For commands that
+/// must prove the password is known or need it, such as `passwd` and `agent start`.
+pub fn password_without_agent() -> Result<SecretString, EnvkeepError> {
+    match non_interactive_password()? {
+        Some(password) => Ok(password),
+        None => crypto::prompt_existing_password(),
+    }
+}
+
+/// A password for a new vault: from a non-interactive source if one is set,
+/// otherwise prompted twice.
+pub fn new_password() -> Result<SecretString, EnvkeepError> {
+    match non_interactive_password()? {
+        Some(password) if password.len() < 8 => Err(EnvkeepError::EncryptionError(
+            "Password must be at least 8 characters".to_string(),
+        )),
+        Some(password) => Ok(password),
+        None => crypto::prompt_new_password(),
+    }
+}
+
+/// Open the vault (applying pending migrations).
+pub fn open_vault() -> Result<Connection, EnvkeepError> {
+    let password = password()?;
+    vault::open_vault(&password)
+}
+
+/// Open the vault and unlock its keyring.
+pub fn open_vault_with_keyring() -> Result<(Connection, Keyring), EnvkeepError> {
+    let password = password()?;
+    let conn = vault::open_vault(&password)?;
+    let key = vault::get_encryption_key(&conn, &password)?;
+    Ok((conn, Keyring::new(key)))
+}
+
+fn non_interactive_password() -> Result<Option<SecretString>, EnvkeepError> {
+    let options = options();
+
+    if let Some(fd) = options.password_fd {
+        return read_fd(fd).map(Some);
+    }
+    if let Some(path) = &options.password_file {
+        return read_file(path).map(Some);
+    }
+    if let Some(command) = &options.password_command {
+        return run_command(command).map(Some);
+    }
+    if let Ok(password) = env::var(PASSWORD_ENV) {
+        return Ok(Some(SecretString::new(password)));
+    }
+    if let Ok(command) = env::var(PASSWORD_COMMAND_ENV) {
+        return run_command(&command).map(Some);
+    }
+
+    Ok(None)
+}
+
+/// Only the first line counts, so `echo`ed or hand-written files work.
+fn first_line(contents: &str) -> SecretString {
+    SecretString::from(contents.lines().next().unwrap_or(""))
+}
+
+fn read_fd(fd: u32) -> Result<SecretString, EnvkeepError> {
+    let path = format!("/dev/fd/{}", fd);
+    let contents = SecretString::new(fs::read_to_string(&path).map_err(|e| {
+        EnvkeepError::PasswordUnavailable(format!("could not read file descriptor {}: {}", fd, e))
+    })?);
+    Ok(first_line(&contents))
+}
+
+/// Refuses files everyone may read. Group-readable files are accepted, so a
+/// file can be shared with a CI group.
+fn read_file(path: &PathBuf) -> Result<SecretString, EnvkeepError> {
+    let read_error = |e| EnvkeepError::FileReadError(path.display().to_string(), e);
+    let mut file = fs::File::open(path).map_err(read_error)?;
+
+    // Check the file that was opened, so a swapped symlink cannot get past the check
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let metadata = file.metadata().map_err(read_error)?;
+        if metadata.permissions().mode() & 0o004 != 0 {
+            return Err(EnvkeepError::InsecurePasswordFile(path.display().to_string()));
+        }
+    }
+
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).map_err(read_error)?;
+    Ok(first_line(&SecretString::new(contents)))
+}
+
+/// Run `command` with `sh -c` (`cmd /C` on Windows) and take the first line it prints.
+fn run_command(command: &str) -> Result<SecretString, EnvkeepError> {
+    let output = shell(command)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| EnvkeepError::PasswordUnavailable(format!("could not run '{}': {}", command, e)))?;
+
+    if !output.status.success() {
+        return Err(EnvkeepError::PasswordUnavailable(format!(
+            "'{}' failed ({})",
+            command, output.status
+        )));
+    }
+
+    let stdout = SecretBytes::new(output.stdout);
+    let stdout = std::str::from_utf8(&stdout).map_err(|_| {
+        EnvkeepError::PasswordUnavailable(format!("'{}' did not print valid UTF-8", command))
+    })?;
+    Ok(first_line(stdout))
+}
+
+/// A shell running `command`: `sh -c`, or `cmd /C` on Windows. Also runs
+/// rotation hooks.
+#[cfg(not(windows))]
+pub fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+/// cmd parses its own command line, so the command is passed on unquoted.
+#[cfg(windows)]
+pub fn shell(command: &str) -> Command {
+    use std::os::windows::process::CommandExt;
+
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").raw_arg(command);
+    shell
+}
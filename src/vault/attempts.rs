@@ -0,0 +1,338 @@
+// Failed-unlock throttling: `~/.envkeep/unlock.json` counts wrong master
+// passwords in a row. Each failure doubles the wait before the next try, and
+// an optional lockout refuses the password altogether until `envkeep recover`.
+//
+// The file sits outside the vault because it is needed before the vault is
+// unlocked. It slows down guessing through envkeep; it cannot stop someone
+// who can edit files in ~/.envkeep.
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::errors::EnvkeepError;
+
+/// Longest wait between attempts, however many failed.
+const MAX_DELAY_SECS: i64 = 60 * 60;
+/// Attempts kept for `envkeep lockout status`.
+const HISTORY_LEN: usize = 20;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attempt {
+    pub at: String,
+    pub ok: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Attempts {
+    /// Wrong passwords in a row since the last successful unlock
+    pub failures: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_failure: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_success: Option<String>,
+    /// Lock the vault after this many failures in a row (None: never)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lockout_after: Option<u32>,
+    #[serde(default)]
+    pub locked_out: bool,
+    #[serde(default)]
+    pub history: Vec<Attempt>,
+}
+
+/// Path of the state file (`~/.envkeep/unlock.json`).
+pub fn path() -> PathBuf {
+    super::data_dir().join("unlock.json")
+}
+
+impl Attempts {
+    /// The saved state, or a fresh one if there is none yet.
+    pub fn load() -> Result<Self, EnvkeepError> {
+        Self::load_from(&path())
+    }
+
+    fn load_from(path: &Path) -> Result<Self, EnvkeepError> {
+        if !path.exists() {
+            return Ok(Attempts::default());
+        }
+        let contents = fs::read_to_string(path)
+            .map_err(|e| EnvkeepError::FileReadError(path.display().to_string(), e))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| EnvkeepError::AttemptsFileError(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Write to a temporary file first, like the key file.
+    pub fn save(&self) -> Result<(), EnvkeepError> {
+        self.save_to(&path())
+    }
+
+    fn save_to(&self, path: &Path) -> Result<(), EnvkeepError> {
+        let write_error = |e: std::io::Error| EnvkeepError::FileWriteError(path.display().to_string(), e);
+        let json = serde_json::to_string_pretty(self).map_err(|e| EnvkeepError::AttemptsFileError(e.to_string()))?;
+
+        // `restore` on a new machine runs before there is a data directory
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(write_error)?;
+        }
+
+        let tmp = path.with_extension("json.tmp");
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&tmp).map_err(write_error)?;
+        file.write_all(json.as_bytes()).map_err(write_error)?;
+        file.sync_all().map_err(write_error)?;
+        fs::rename(&tmp, path).map_err(write_error)?;
+        Ok(())
+    }
+
+    /// How long to wait after `failures` wrong passwords in a row: 1s, 2s, 4s, ...
+    pub fn delay(failures: u32) -> Duration {
+        if failures == 0 {
+            return Duration::zero();
+        }
+        // The wait is capped long before 2^62 s, and a bigger shift would overflow
+        let secs = 1i64 << (failures - 1).min(62);
+        Duration::seconds(secs.min(MAX_DELAY_SECS))
+    }
+
+    /// Seconds until the next attempt is allowed (0 if it is allowed now).
+    pub fn wait_secs(&self) -> i64 {
+        let Some(last) = self.last_failure.as_deref().and_then(|t| DateTime::parse_from_rfc3339(t).ok()) else {
+            return 0;
+        };
+        let next = last.with_timezone(&Utc) + Self::delay(self.failures);
+        // Round up, or a retry within the last second would slip through
+        let millis = (next - Utc::now()).num_milliseconds().max(0);
+        (millis + 999) / 1000
+    }
+
+    /// Refuse an attempt while locked out or still backing off.
+    pub fn check(&self) -> Result<(), EnvkeepError> {
+        if self.locked_out {
+            return Err(EnvkeepError::LockedOut(self.failures));
+        }
+        match self.wait_secs() {
+            0 => Ok(()),
+            secs => Err(EnvkeepError::UnlockThrottled(secs as u64)),
+        }
+    }
+
+    fn record(&mut self, ok: bool) {
+        let now = Utc::now().to_rfc3339();
+        if ok {
+            self.failures = 0;
+            self.last_success = Some(now.clone());
+        } else {
+            self.failures = self.failures.saturating_add(1);
+            self.last_failure = Some(now.clone());
+            if self.lockout_after.is_some_and(|limit| self.failures >= limit) {
+                self.locked_out = true;
+            }
+        }
+        self.history.push(Attempt { at: now, ok });
+        if self.history.len() > HISTORY_LEN {
+            self.history.drain(..self.history.len() - HISTORY_LEN);
+        }
+    }
+
+    /// After the vault was unlocked some other way (`envkeep recover`): start over.
+    /// The lockout setting is kept.
+    pub fn clear(&mut self) {
+        self.failures = 0;
+        self.locked_out = false;
+        self.last_failure = None;
+    }
+}
+
+/// Run one master password check (`verify`) under the throttle: refuse it while
+/// backing off or locked out, count a wrong password, and on success report
+/// the failures since the previous successful unlock.
+pub fn guard<T>(verify: impl FnOnce() -> Result<T, EnvkeepError>) -> Result<T, EnvkeepError> {
+    guard_at(&path(), verify)
+}
+
+fn guard_at<T>(path: &Path, verify: impl FnOnce() -> Result<T, EnvkeepError>) -> Result<T, EnvkeepError> {
+    let mut attempts = Attempts::load_from(path)?;
+    attempts.check()?;
+
+    match verify() {
+        Err(EnvkeepError::WrongPassword) => {
+            attempts.record(false);
+            attempts.save_to(path)?;
+            if attempts.locked_out {
+                return Err(EnvkeepError::LockedOut(attempts.failures));
+            }
+            Err(EnvkeepError::WrongPassword)
+        }
+        Ok(value) => {
+            if attempts.failures > 0 {
+                report(&attempts);
+            }
+            attempts.record(true);
+            attempts.save_to(path)?;
+            Ok(value)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Tell the user someone (maybe they) got the password wrong since they last unlocked.
+fn report(attempts: &Attempts) {
+    eprintln!(
+        "Warning: {} failed unlock attempt(s) since the last successful unlock{}; the last at {}.",
+        attempts.failures,
+        attempts.last_success.as_deref().map(|t| format!(" ({})", local_time(t))).unwrap_or_default(),
+        attempts.last_failure.as_deref().map(local_time).unwrap_or_default()
+    );
+}
+
+/// An RFC 3339 timestamp in local time, for messages.
+pub fn local_time(timestamp: &str) -> String {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|t| t.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|_| timestamp.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A state file of its own per test, outside the real `~/.envkeep`.
+    fn state_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir()
+            .join(format!("envkeep-attempts-{}", std::process::id()))
+            .join(format!("{}.json", name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn failed(failures: u32, ago: Duration) -> Attempts {
+        Attempts {
+            failures,
+            last_failure: Some((Utc::now() - ago).to_rfc3339()),
+            ..Attempts::default()
+        }
+    }
+
+    fn never_called() -> Result<(), EnvkeepError> {
+        panic!("the password was checked while it should have been refused");
+    }
+
+    #[test]
+    fn delay_doubles_up_to_the_cap() {
+        assert_eq!(Attempts::delay(0), Duration::zero());
+        assert_eq!(Attempts::delay(1), Duration::seconds(1));
+        assert_eq!(Attempts::delay(2), Duration::seconds(2));
+        assert_eq!(Attempts::delay(3), Duration::seconds(4));
+        assert_eq!(Attempts::delay(12), Duration::seconds(2048));
+        for failures in [13, 63, 64, 65, 1000, u32::MAX] {
+            assert_eq!(Attempts::delay(failures), Duration::seconds(MAX_DELAY_SECS), "{} failures", failures);
+        }
+    }
+
+    #[test]
+    fn check_allows_after_the_delay() {
+        assert!(Attempts::default().check().is_ok());
+        assert!(failed(1, Duration::seconds(2)).check().is_ok());
+        assert!(failed(3, Duration::seconds(5)).check().is_ok());
+        assert!(failed(64, Duration::hours(2)).check().is_ok());
+    }
+
+    #[test]
+    fn check_refuses_while_backing_off() {
+        assert!(matches!(failed(1, Duration::zero()).check(), Err(EnvkeepError::UnlockThrottled(1))));
+        assert!(matches!(failed(3, Duration::seconds(1)).check(), Err(EnvkeepError::UnlockThrottled(3))));
+        for failures in [64, u32::MAX] {
+            match failed(failures, Duration::zero()).check() {
+                Err(EnvkeepError::UnlockThrottled(secs)) => assert_eq!(secs, MAX_DELAY_SECS as u64),
+                other => panic!("{} failures: {:?}", failures, other),
+            }
+        }
+    }
+
+    #[test]
+    fn check_refuses_when_locked_out() {
+        let attempts = Attempts { locked_out: true, ..failed(5, Duration::hours(2)) };
+        assert!(matches!(attempts.check(), Err(EnvkeepError::LockedOut(5))));
+    }
+
+    #[test]
+    fn guard_counts_wrong_passwords() {
+        let path = state_file("count");
+
+        assert!(matches!(guard_at(&path, || Err::<(), _>(EnvkeepError::WrongPassword)), Err(EnvkeepError::WrongPassword)));
+        let attempts = Attempts::load_from(&path).unwrap();
+        assert_eq!(attempts.failures, 1);
+        assert!(attempts.last_failure.is_some());
+
+        // Right away again: refused without checking the password
+        assert!(matches!(guard_at(&path, never_called), Err(EnvkeepError::UnlockThrottled(1))));
+        assert_eq!(Attempts::load_from(&path).unwrap().failures, 1);
+    }
+
+    #[test]
+    fn guard_resets_on_success() {
+        let path = state_file("reset");
+        failed(3, Duration::seconds(10)).save_to(&path).unwrap();
+
+        assert_eq!(guard_at(&path, || Ok(42)).unwrap(), 42);
+        let attempts = Attempts::load_from(&path).unwrap();
+        assert_eq!(attempts.failures, 0);
+        assert!(attempts.last_success.is_some());
+        assert!(attempts.history.last().unwrap().ok);
+    }
+
+    #[test]
+    fn guard_ignores_other_errors() {
+        let path = state_file("other");
+        let result = guard_at(&path, || Err::<(), _>(EnvkeepError::KeyFileError("damaged".to_string())));
+
+        assert!(matches!(result, Err(EnvkeepError::KeyFileError(_))));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn guard_locks_out_at_the_threshold() {
+        let path = state_file("lockout");
+        Attempts { lockout_after: Some(3), ..failed(2, Duration::seconds(10)) }.save_to(&path).unwrap();
+
+        assert!(matches!(guard_at(&path, || Err::<(), _>(EnvkeepError::WrongPassword)), Err(EnvkeepError::LockedOut(3))));
+        assert!(Attempts::load_from(&path).unwrap().locked_out);
+
+        // Even the right password is refused until recovery
+        assert!(matches!(guard_at(&path, never_called), Err(EnvkeepError::LockedOut(3))));
+
+        let mut attempts = Attempts::load_from(&path).unwrap();
+        attempts.clear();
+        attempts.save_to(&path).unwrap();
+        assert!(guard_at(&path, || Ok(())).is_ok());
+        assert_eq!(Attempts::load_from(&path).unwrap().lockout_after, Some(3));
+    }
+
+    #[test]
+    fn guard_survives_many_failures() {
+        let path = state_file("many");
+        failed(64, Duration::hours(2)).save_to(&path).unwrap();
+
+        assert!(matches!(guard_at(&path, || Err::<(), _>(EnvkeepError::WrongPassword)), Err(EnvkeepError::WrongPassword)));
+        let attempts = Attempts::load_from(&path).unwrap();
+        assert_eq!(attempts.failures, 65);
+        assert!(matches!(attempts.check(), Err(EnvkeepError::UnlockThrottled(secs)) if secs == MAX_DELAY_SECS as u64));
+    }
+
+    #[test]
+    fn history_keeps_the_latest_attempts() {
+        let mut attempts = Attempts::default();
+        for i in 0..HISTORY_LEN + 5 {
+            attempts.record(i % 2 == 0);
+        }
+        assert_eq!(attempts.history.len(), HISTORY_LEN);
+        assert!(attempts.history.last().unwrap().ok);
+    }
+}
@@ -0,0 +1,311 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use chrono::{DateTime, Utc};
+use ring::digest;
+
+use crate::errors::EnvkeepError;
+
+/// `prev_hash` of the very first entry.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// What a command did with the vault.
+#[derive(Debug, Clone, Copy)]
+pub enum Access {
+    /// Looked at vault metadata only, no values were decrypted
+    Read,
+    /// Decrypted values
+    Decrypt,
+    /// Decrypted values and wrote them to a file on disk
+    WriteFile,
+    /// Decrypted values into an export file
+    Export,
+    /// Stored values from an export file
+    Import,
+    /// Replaced a secret with a generated value
+    Rotate,
+    /// Stored or changed values
+    Modify,
+    /// Removed values
+    Delete,
+}
+
+impl Access {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Access::Read => "read",
+            Access::Decrypt => "decrypt",
+            Access::WriteFile => "write-file",
+            Access::Export => "export",
+            Access::Import => "import",
+            Access::Rotate => "rotate",
+            Access::Modify => "modify",
+            Access::Delete => "delete",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    pub seq: i64,
+    pub timestamp: String,
+    pub command: String,
+    pub action: String,
+    pub project: Option<String>,
+    pub keys: String,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditEntry {
+    /// Recompute the hash this entry should have.
+    fn expected_hash(&self) -> String {
+        entry_hash(
+            self.seq,
+            &self.timestamp,
+            &self.command,
+            &self.action,
+            self.project.as_deref(),
+            &self.keys,
+            &self.prev_hash,
+        )
+    }
+}
+
+/// Outcome of walking the whole chain.
+#[derive(Debug)]
+pub enum ChainStatus {
+    Intact(i64),
+    /// The first entry (by seq) whose hash or link does not match.
+    Broken(i64, String),
+}
+
+fn entry_hash(
+    seq: i64,
+    timestamp: &str,
+    command: &str,
+    action: &str,
+    project: Option<&str>,
+    keys: &str,
+    prev_hash: &str,
+) -> String {
+    // Unit separators keep field boundaries unambiguous
+    let input = format!(
+        "{}\x1f{}\x1f{}\x1f{}\x1f{}\x1f{}\x1f{}",
+        seq,
+        timestamp,
+        command,
+        action,
+        project.unwrap_or(""),
+        keys,
+        prev_hash
+    );
+    digest::digest(&digest::SHA256, input.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Append an entry to the audit log, chained to the previous one.
+///
+/// `project` is a project name, optionally as `name:environment`.
+pub fn record(
+    conn: &Connection,
+    command: &str,
+    access: Access,
+    project: Option<&str>,
+    keys: &[String],
+) -> Result<(), EnvkeepError> {
+    let last: Option<(i64, String)> = conn
+        .query_row(
+            "SELECT seq, hash FROM audit_log ORDER BY seq DESC LIMIT 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+
+    let (seq, prev_hash) = match last {
+        Some((seq, hash)) => (seq + 1, hash),
+        None => (1, GENESIS_HASH.to_string()),
+    };
+
+    let timestamp = Utc::now().to_rfc3339();
+    let keys = keys.join(",");
+    let hash = entry_hash(seq, &timestamp, command, access.as_str(), project, &keys, &prev_hash);
+
+    conn.execute(
+        "INSERT INTO audit_log (seq, timestamp, command, action, project, keys, prev_hash, hash)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![seq, timestamp, command, access.as_str(), project, keys, prev_hash, hash],
+    )?;
+
+    // The head lets verify notice entries cut off the end of the chain
+    conn.execute(
+        "INSERT INTO metadata (key, value) VALUES ('audit_head', ?1)
+         ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        [format!("{}:{}", seq, hash)],
+    )?;
+
+    Ok(())
+}
+
+fn entry_from_row(row: &rusqlite::Row) -> rusqlite::Result<AuditEntry> {
+    Ok(AuditEntry {
+        seq: row.get(0)?,
+        timestamp: row.get(1)?,
+        command: row.get(2)?,
+        action: row.get(3)?,
+        project: row.get(4)?,
+        keys: row.get(5)?,
+        prev_hash: row.get(6)?,
+        hash: row.get(7)?,
+    })
+}
+
+/// Get audit entries, oldest first.
+///
+/// `project` matches the project name with or without an environment suffix.
+pub fn get_entries(
+    conn: &Connection,
+    project: Option<&str>,
+    since: Option<DateTime<Utc>>,
+) -> Result<Vec<AuditEntry>, EnvkeepError> {
+    let mut stmt = conn.prepare(
+        "SELECT seq, timestamp, command, action, project, keys, prev_hash, hash
+         FROM audit_log
+         WHERE ?1 IS NULL OR project = ?1 OR substr(project, 1, length(?1) + 1) = ?1 || ':'
+         ORDER BY seq",
+    )?;
+
+    let entries = stmt
+        .query_map([project], entry_from_row)?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let Some(since) = since else {
+        return Ok(entries);
+    };
+
+    Ok(entries
+        .into_iter()
+        .filter(|e| {
+            DateTime::parse_from_rfc3339(&e.timestamp)
+                .map(|t| t.with_timezone(&Utc) >= since)
+                .unwrap_or(true)
+        })
+        .collect())
+}
+
+/// Walk the whole log and check every hash, every link and the stored head.
+pub fn verify_chain(conn: &Connection) -> Result<ChainStatus, EnvkeepError> {
+    let entries = get_entries(conn, None, None)?;
+
+    let mut prev_hash = GENESIS_HASH.to_string();
+    for (i, entry) in entries.iter().enumerate() {
+        let expected_seq = i as i64 + 1;
+        if entry.seq != expected_seq {
+            return Ok(ChainStatus::Broken(
+                expected_seq,
+                "entry is missing".to_string(),
+            ));
+        }
+        if entry.prev_hash != prev_hash {
+            return Ok(ChainStatus::Broken(
+                entry.seq,
+                "does not link to the previous entry".to_string(),
+            ));
+        }
+        if entry.hash != entry.expected_hash() {
+            return Ok(ChainStatus::Broken(
+                entry.seq,
+                "contents were modified".to_string(),
+            ));
+        }
+        prev_hash = entry.hash.clone();
+    }
+
+    let head: Option<String> = conn
+        .query_row("SELECT value FROM metadata WHERE key = 'audit_head'", [], |row| row.get(0))
+        .optional()?;
+
+    let count = entries.len() as i64;
+    let actual_head = entries.last().map(|e| format!("{}:{}", e.seq, e.hash));
+    if head != actual_head {
+        return Ok(ChainStatus::Broken(
+            count + 1,
+            "entries were removed from the end of the log".to_string(),
+        ));
+    }
+
+    Ok(ChainStatus::Intact(count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A vault with three entries and the append-only triggers dropped, so tests can tamper.
+    fn tamperable_log() -> Connection {
+        let conn = crate::vault::memory_vault();
+        for key in ["A", "B", "C"] {
+            record(&conn, "set", Access::Modify, Some("web:dev"), &[key.to_string()]).unwrap();
+        }
+        assert!(matches!(verify_chain(&conn).unwrap(), ChainStatus::Intact(3)));
+
+        conn.execute_batch("DROP TRIGGER audit_log_no_update; DROP TRIGGER audit_log_no_delete;").unwrap();
+        conn
+    }
+
+    fn broken_at(conn: &Connection) -> (i64, String) {
+        match verify_chain(conn).unwrap() {
+            ChainStatus::Broken(seq, reason) => (seq, reason),
+            ChainStatus::Intact(count) => panic!("chain of {} entries reported intact", count),
+        }
+    }
+
+    #[test]
+    fn empty_log_is_intact() {
+        let conn = crate::vault::memory_vault();
+        assert!(matches!(verify_chain(&conn).unwrap(), ChainStatus::Intact(0)));
+    }
+
+    #[test]
+    fn edited_entry_is_detected() {
+        let conn = tamperable_log();
+        conn.execute("UPDATE audit_log SET keys = 'OTHER' WHERE seq = 2", []).unwrap();
+        assert_eq!(broken_at(&conn), (2, "contents were modified".to_string()));
+    }
+
+    #[test]
+    fn rehashed_entry_breaks_the_next_link() {
+        let conn = tamperable_log();
+        let entry = &get_entries(&conn, None, None).unwrap()[1];
+        let hash = entry_hash(2, &entry.timestamp, "set", "modify", Some("web:dev"), "OTHER", &entry.prev_hash);
+        conn.execute("UPDATE audit_log SET keys = 'OTHER', hash = ?1 WHERE seq = 2", [hash]).unwrap();
+        assert_eq!(broken_at(&conn), (3, "does not link to the previous entry".to_string()));
+    }
+
+    #[test]
+    fn removed_last_entry_is_detected() {
+        let conn = tamperable_log();
+        conn.execute("DELETE FROM audit_log WHERE seq = 3", []).unwrap();
+        assert_eq!(broken_at(&conn), (3, "entries were removed from the end of the log".to_string()));
+    }
+
+    #[test]
+    fn seq_gap_is_detected() {
+        let conn = tamperable_log();
+        conn.execute("DELETE FROM audit_log WHERE seq = 2", []).unwrap();
+        assert_eq!(broken_at(&conn), (2, "entry is missing".to_string()));
+    }
+
+    #[test]
+    fn log_is_append_only() {
+        let conn = crate::vault::memory_vault();
+        record(&conn, "set", Access::Modify, Some("web"), &["A".to_string()]).unwrap();
+
+        for sql in ["UPDATE audit_log SET keys = 'B'", "DELETE FROM audit_log"] {
+            let err = conn.execute(sql, []).unwrap_err();
+            assert!(err.to_string().contains("append-only"), "{}: {}", sql, err);
+        }
+        assert!(matches!(verify_chain(&conn).unwrap(), ChainStatus::Intact(1)));
+    }
+}
@@ -0,0 +1,279 @@
+use rusqlite::{params, Connection};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Utc};
+use std::collections::BTreeMap;
+
+use crate::errors::EnvkeepError;
+
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub revision: i64,
+    pub key: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub command: String,
+    pub changed_at: String,
+}
+
+impl HistoryEntry {
+    /// Short description of what this change did to the key.
+    pub fn change(&self) -> &'static str {
+        match (&self.old_value, &self.new_value) {
+            (None, Some(_)) => "added",
+            (Some(_), None) => "removed",
+            _ => "changed",
+        }
+    }
+}
+
+/// A point in time a project can be rolled back to.
+#[derive(Debug, Clone)]
+pub enum RollbackPoint {
+    /// State right after this revision was applied.
+    Revision(i64),
+    /// State as it was at this moment.
+    Timestamp(DateTime<Utc>),
+}
+
+impl RollbackPoint {
+    /// Parse `--to`: a revision number or anything `parse_timestamp` accepts.
+    pub fn parse(input: &str) -> Result<Self, EnvkeepError> {
+        if let Ok(revision) = input.parse::<i64>() {
+            return Ok(RollbackPoint::Revision(revision));
+        }
+
+        parse_timestamp(input)
+            .map(RollbackPoint::Timestamp)
+            .ok_or_else(|| EnvkeepError::InvalidRollbackPoint(input.to_string()))
+    }
+
+    /// Whether a change happened after this point (and so must be undone).
+    fn is_after(&self, entry: &HistoryEntry) -> bool {
+        match self {
+            RollbackPoint::Revision(revision) => entry.revision > *revision,
+            RollbackPoint::Timestamp(at) => DateTime::parse_from_rfc3339(&entry.changed_at)
+                .map(|changed| changed.with_timezone(&Utc) > *at)
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// Parse a point in time given on the command line.
+///
+/// Accepts an RFC 3339 timestamp, `YYYY-MM-DD HH:MM:SS` (UTC), a plain
+/// `YYYY-MM-DD` (start of that day, UTC) or an age like `30m`, `12h`, `7d`.
+pub fn parse_timestamp(input: &str) -> Option<DateTime<Utc>> {
+    if let Ok(dt) = DateTime::parse_from_rfc3339(input) {
+        return Some(dt.with_timezone(&Utc));
+    }
+
+    if let Ok(dt) = NaiveDateTime::parse_from_str(input, "%Y-%m-%d %H:%M:%S") {
+        return Some(dt.and_utc());
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+        return date.and_hms_opt(0, 0, 0).map(|dt| dt.and_utc());
+    }
+
+    // Relative ages, counted back from now
+    let unit = input.chars().last()?;
+    let amount: i64 = input[..input.len() - unit.len_utf8()].parse().ok()?;
+    let age = match unit {
+        'm' => Duration::minutes(amount),
+        'h' => Duration::hours(amount),
+        'd' => Duration::days(amount),
+        _ => return None,
+    };
+    Some(Utc::now() - age)
+}
+
+/// Record one change to a variable. `None` means the key did not exist on that side.
+pub fn record_change(
+    conn: &Connection,
+    project_id: &str,
+    environment: &str,
+    key: &str,
+    old_value: Option<&str>,
+    new_value: Option<&str>,
+    command: &str,
+) -> Result<(), EnvkeepError> {
+    conn.execute(
+        "INSERT INTO variable_history
+           (project_id, environment, key, old_value, new_value, command, changed_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            project_id,
+            environment,
+            key,
+            old_value,
+            new_value,
+            command,
+            Utc::now().to_rfc3339(),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Get the history of one environment of a project, oldest first.
+/// Pass a key to only get the changes to that variable.
+pub fn get_history(
+    conn: &Connection,
+    project_id: &str,
+    environment: &str,
+    key: Option<&str>,
+) -> Result<Vec<HistoryEntry>, EnvkeepError> {
+    let mut stmt = conn.prepare(
+        "SELECT revision, key, old_value, new_value, command, changed_at
+         FROM variable_history
+         WHERE project_id = ?1 AND environment = ?2 AND (?3 IS NULL OR key = ?3)
+         ORDER BY revision",
+    )?;
+
+    let entries = stmt
+        .query_map(params![project_id, environment, key], |row| {
+            Ok(HistoryEntry {
+                revision: row.get(0)?,
+                key: row.get(1)?,
+                old_value: row.get(2)?,
+                new_value: row.get(3)?,
+                command: row.get(4)?,
+                changed_at: row.get(5)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(entries)
+}
+
+/// Work out which encrypted values the keys had at `point`.
+///
+/// Only keys changed after `point` are returned. `None` means the key did not
+/// exist yet and has to be removed.
+pub fn values_at(
+    conn: &Connection,
+    project_id: &str,
+    environment: &str,
+    key: Option<&str>,
+    point: &RollbackPoint,
+) -> Result<BTreeMap<String, Option<String>>, EnvkeepError> {
+    let mut values = BTreeMap::new();
+
+    // The first change after the point still knows the value the key had at that point
+    for entry in get_history(conn, project_id, environment, key)? {
+        if point.is_after(&entry) && !values.contains_key(&entry.key) {
+            values.insert(entry.key.clone(), entry.old_value.clone());
+        }
+    }
+
+    Ok(values)
+}
+
+/// Delete the history of a project (used when the project itself is removed).
+pub fn delete_history(conn: &Connection, project_id: &str) -> Result<(), EnvkeepError> {
+    conn.execute("DELETE FROM variable_history WHERE project_id = ?1", [project_id])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn change(conn: &Connection, environment: &str, key: &str, old: Option<&str>, new: Option<&str>, at: &str) {
+        conn.execute(
+            "INSERT INTO variable_history
+               (project_id, environment, key, old_value, new_value, command, changed_at)
+             VALUES ('p1', ?1, ?2, ?3, ?4, 'set', ?5)",
+            params![environment, key, old, new, at],
+        )
+        .unwrap();
+    }
+
+    /// A added at 10:00 and changed at 11:00, B added at 12:00, and noise in another environment.
+    fn vault_with_history() -> Connection {
+        let conn = crate::vault::memory_vault();
+        conn.execute(
+            "INSERT INTO projects (id, name, created_at, updated_at) VALUES ('p1', 'web', '', '')",
+            [],
+        )
+        .unwrap();
+        change(&conn, "dev", "A", None, Some("a1"), "2024-05-01T10:00:00+00:00");
+        change(&conn, "dev", "A", Some("a1"), Some("a2"), "2024-05-01T11:00:00+00:00");
+        change(&conn, "dev", "B", None, Some("b1"), "2024-05-01T12:00:00+00:00");
+        change(&conn, "prod", "A", None, Some("p1"), "2024-05-01T10:30:00+00:00");
+        conn
+    }
+
+    fn at(hour: u32, min: u32) -> RollbackPoint {
+        RollbackPoint::Timestamp(Utc.with_ymd_and_hms(2024, 5, 1, hour, min, 0).unwrap())
+    }
+
+    fn values(conn: &Connection, key: Option<&str>, point: &RollbackPoint) -> Vec<(String, Option<String>)> {
+        values_at(conn, "p1", "dev", key, point).unwrap().into_iter().collect()
+    }
+
+    fn entry(key: &str, value: Option<&str>) -> (String, Option<String>) {
+        (key.to_string(), value.map(str::to_string))
+    }
+
+    #[test]
+    fn values_before_between_and_after_changes() {
+        let conn = vault_with_history();
+
+        assert_eq!(values(&conn, None, &at(9, 0)), vec![entry("A", None), entry("B", None)]);
+        assert_eq!(values(&conn, None, &at(10, 30)), vec![entry("A", Some("a1")), entry("B", None)]);
+        assert_eq!(values(&conn, None, &at(11, 30)), vec![entry("B", None)]);
+        assert_eq!(values(&conn, None, &at(13, 0)), vec![]);
+    }
+
+    #[test]
+    fn change_at_the_exact_point_is_kept() {
+        let conn = vault_with_history();
+        assert_eq!(values(&conn, None, &at(11, 0)), vec![entry("B", None)]);
+    }
+
+    #[test]
+    fn values_at_revision() {
+        let conn = vault_with_history();
+
+        assert_eq!(values(&conn, None, &RollbackPoint::Revision(0)), vec![entry("A", None), entry("B", None)]);
+        assert_eq!(values(&conn, None, &RollbackPoint::Revision(1)), vec![entry("A", Some("a1")), entry("B", None)]);
+        assert_eq!(values(&conn, None, &RollbackPoint::Revision(3)), vec![]);
+    }
+
+    #[test]
+    fn values_of_one_key() {
+        let conn = vault_with_history();
+        assert_eq!(values(&conn, Some("A"), &at(9, 0)), vec![entry("A", None)]);
+        assert_eq!(values(&conn, Some("B"), &at(10, 30)), vec![entry("B", None)]);
+    }
+
+    #[test]
+    fn parses_absolute_timestamps() {
+        let expected = Utc.with_ymd_and_hms(2024, 5, 1, 10, 0, 0).unwrap();
+        assert_eq!(parse_timestamp("2024-05-01T12:00:00+02:00"), Some(expected));
+        assert_eq!(parse_timestamp("2024-05-01 10:00:00"), Some(expected));
+        assert_eq!(parse_timestamp("2024-05-01"), Some(Utc.with_ymd_and_hms(2024, 5, 1, 0, 0, 0).unwrap()));
+    }
+
+    #[test]
+    fn parses_ages() {
+        for (input, age) in [("30m", Duration::minutes(30)), ("12h", Duration::hours(12)), ("7d", Duration::days(7))] {
+            let parsed = parse_timestamp(input).unwrap();
+            let off = (Utc::now() - age - parsed).num_seconds().abs();
+            assert!(off < 5, "{} is {}s off", input, off);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_points() {
+        for input in ["", "yesterday", "5x", "m", "d7", "2024-13-01", "2024-05-01 25:00:00", "1.5h"] {
+            assert_eq!(parse_timestamp(input), None, "{:?}", input);
+            assert!(
+                matches!(RollbackPoint::parse(input), Err(EnvkeepError::InvalidRollbackPoint(ref i)) if i == input),
+                "{:?}",
+                input
+            );
+        }
+        assert!(matches!(RollbackPoint::parse("42"), Ok(RollbackPoint::Revision(42))));
+    }
+}
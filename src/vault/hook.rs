@@ -0,0 +1,55 @@
+use rusqlite::{params, Connection};
+use chrono::Utc;
+
+use crate::errors::EnvkeepError;
+
+/// A command run after a secret is rotated. It gets the new value on stdin.
+#[derive(Debug, Clone)]
+pub struct Hook {
+    pub id: i64,
+    pub command: String,
+    pub created_at: String,
+}
+
+/// Attach a hook command to a secret. Hooks run in the order they were added.
+pub fn add_hook(conn: &Connection, secret_id: &str, command: &str) -> Result<i64, EnvkeepError> {
+    conn.execute(
+        "INSERT INTO secret_hooks (secret_id, command, created_at) VALUES (?1, ?2, ?3)",
+        params![secret_id, command, Utc::now().to_rfc3339()],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn list_hooks(conn: &Connection, secret_id: &str) -> Result<Vec<Hook>, EnvkeepError> {
+    let mut stmt = conn.prepare(
+        "SELECT id, command, created_at FROM secret_hooks
+         WHERE secret_id = ?1 ORDER BY id",
+    )?;
+
+    let hooks = stmt
+        .query_map([secret_id], |row| {
+            Ok(Hook {
+                id: row.get(0)?,
+                command: row.get(1)?,
+                created_at: row.get(2)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(hooks)
+}
+
+/// Remove one hook of a secret. Returns false if the secret has no hook with that id.
+pub fn remove_hook(conn: &Connection, secret_id: &str, id: i64) -> Result<bool, EnvkeepError> {
+    let removed = conn.execute(
+        "DELETE FROM secret_hooks WHERE secret_id = ?1 AND id = ?2",
+        params![secret_id, id],
+    )?;
+    Ok(removed > 0)
+}
+
+/// Delete every hook of a secret (used when the secret itself is removed).
+pub fn delete_hooks(conn: &Connection, secret_id: &str) -> Result<(), EnvkeepError> {
+    conn.execute("DELETE FROM secret_hooks WHERE secret_id = ?1", [secret_id])?;
+    Ok(())
+}
@@ -0,0 +1,207 @@
+// Key slots: the vault's random data key, wrapped once per way of unlocking it.
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::crypto;
+use crate::crypto::key::{KdfParams, derive_key_with, generate_salt, subkey};
+use crate::crypto::secret::{SecretBytes, SecretKey};
+use crate::errors::EnvkeepError;
+
+const KEYFILE_VERSION: u32 = 1;
+
+/// How a slot is unlocked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SlotKind {
+    /// The master password, stretched with the slot's KDF
+    Password,
+    /// The recovery key shown at `init`; already random, so no KDF
+    Recovery,
+}
+
+impl SlotKind {
+    fn aad(&self) -> Vec<u8> {
+        match self {
+            SlotKind::Password => b"envkeep/keyslot\x1fpassword".to_vec(),
+            SlotKind::Recovery => b"envkeep/keyslot\x1frecovery".to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeySlot {
+    pub kind: SlotKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kdf: Option<KdfParams>,
+    /// Hex encoded KDF salt (password slots only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub salt: Option<String>,
+    /// base64 of nonce | wrapped data key | tag
+    pub wrapped: String,
+    pub created_at: String,
+}
+
+/// `~/.envkeep/vault.keys`: the key slots of the vault next to it.
+///
+/// Losing this file loses the vault, so backups copy it along with `vault.db`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyFile {
+    pub version: u32,
+    pub slots: Vec<KeySlot>,
+    /// When `recovery split` last shared out this data key (None: never)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shares_split_at: Option<String>,
+}
+
+/// Key file that belongs to a vault (or backup) file: same name, `.keys` extension.
+pub fn path_for(vault_file: &Path) -> PathBuf {
+    vault_file.with_extension("keys")
+}
+
+impl Default for KeyFile {
+    fn default() -> Self {
+        KeyFile {
+            version: KEYFILE_VERSION,
+            slots: Vec::new(),
+            shares_split_at: None,
+        }
+    }
+}
+
+impl KeyFile {
+    pub fn load(path: &Path) -> Result<Self, EnvkeepError> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| EnvkeepError::FileReadError(path.display().to_string(), e))?;
+        let keyfile: KeyFile = serde_json::from_str(&contents)
+            .map_err(|e| EnvkeepError::KeyFileError(format!("{}: {}", path.display(), e)))?;
+
+        if keyfile.version > KEYFILE_VERSION {
+            return Err(EnvkeepError::KeyFileError(format!(
+                "{} was written by a newer envkeep (version {})",
+                path.display(),
+                keyfile.version
+            )));
+        }
+        Ok(keyfile)
+    }
+
+    /// Write to a temporary file first, so a crash never leaves half a key file.
+    pub fn save(&self, path: &Path) -> Result<(), EnvkeepError> {
+        let write_error = |e: std::io::Error| EnvkeepError::FileWriteError(path.display().to_string(), e);
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| EnvkeepError::KeyFileError(e.to_string()))?;
+
+        let tmp = path.with_extension("keys.tmp");
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&tmp).map_err(write_error)?;
+        file.write_all(json.as_bytes()).map_err(write_error)?;
+        file.sync_all().map_err(write_error)?;
+        fs::rename(&tmp, path).map_err(write_error)?;
+        Ok(())
+    }
+
+    fn slot(&self, kind: SlotKind) -> Option<&KeySlot> {
+        self.slots.iter().find(|slot| slot.kind == kind)
+    }
+
+    pub fn has_recovery(&self) -> bool {
+        self.slot(SlotKind::Recovery).is_some()
+    }
+
+    /// KDF parameters of the password slot.
+    pub fn kdf_params(&self) -> Result<KdfParams, EnvkeepError> {
+        self.slot(SlotKind::Password)
+            .and_then(|slot| slot.kdf.clone())
+            .ok_or_else(|| EnvkeepError::KeyFileError("no password slot".to_string()))
+    }
+
+    /// Unwrap the data key with the master password.
+    pub fn unlock_with_password(&self, password: &str) -> Result<SecretKey, EnvkeepError> {
+        let slot = self
+            .slot(SlotKind::Password)
+            .ok_or_else(|| EnvkeepError::KeyFileError("no password slot".to_string()))?;
+        let salt = slot
+            .salt
+            .as_deref()
+            .map(crypto::hex_decode)
+            .transpose()
+            .map_err(|_| EnvkeepError::KeyFileError("invalid salt".to_string()))?
+            .unwrap_or_default();
+        let params = self.kdf_params()?;
+
+        let kek = derive_key_with(password, &salt, &params)?;
+        unwrap(slot, &kek).map_err(|_| EnvkeepError::WrongPassword)
+    }
+
+    /// Unwrap the data key with the recovery key.
+    pub fn unlock_with_recovery(&self, recovery_key: &[u8; 32]) -> Result<SecretKey, EnvkeepError> {
+        let slot = self.slot(SlotKind::Recovery).ok_or(EnvkeepError::NoRecoveryKey)?;
+        unwrap(slot, &recovery_kek(recovery_key)).map_err(|_| EnvkeepError::WrongRecoveryKey)
+    }
+
+    /// Replace the password slot (fresh salt).
+    pub fn set_password(
+        &mut self,
+        data_key: &[u8; 32],
+        password: &str,
+        params: &KdfParams,
+    ) -> Result<(), EnvkeepError> {
+        let salt = generate_salt()?;
+        let kek = derive_key_with(password, &salt, params)?;
+        let slot = KeySlot {
+            kind: SlotKind::Password,
+            kdf: Some(params.clone()),
+            salt: Some(salt.iter().map(|b| format!("{:02x}", b)).collect()),
+            wrapped: wrap(SlotKind::Password, &kek, data_key)?,
+            created_at: chrono::Utc::now().to_rfc3339(),
+        };
+        self.replace(slot);
+        Ok(())
+    }
+
+    /// Replace the recovery slot; the previous recovery key stops working.
+    pub fn set_recovery(&mut self, data_key: &[u8; 32], recovery_key: &[u8; 32]) -> Result<(), EnvkeepError> {
+        let slot = KeySlot {
+            kind: SlotKind::Recovery,
+            kdf: None,
+            salt: None,
+            wrapped: wrap(SlotKind::Recovery, &recovery_kek(recovery_key), data_key)?,
+            created_at: chrono::Utc::now().to_rfc3339(),
+        };
+        self.replace(slot);
+        Ok(())
+    }
+
+    fn replace(&mut self, slot: KeySlot) {
+        self.slots.retain(|s| s.kind != slot.kind);
+        self.slots.push(slot);
+    }
+}
+
+fn recovery_kek(recovery_key: &[u8; 32]) -> SecretKey {
+    subkey(recovery_key, "envkeep recovery slot")
+}
+
+fn wrap(kind: SlotKind, kek: &[u8; 32], data_key: &[u8; 32]) -> Result<String, EnvkeepError> {
+    let sealed = crypto::encrypt_with_aad(kek, data_key, &kind.aad())?;
+    Ok(STANDARD.encode(sealed))
+}
+
+fn unwrap(slot: &KeySlot, kek: &[u8; 32]) -> Result<SecretKey, EnvkeepError> {
+    let sealed = STANDARD
+        .decode(&slot.wrapped)
+        .map_err(|e| EnvkeepError::KeyFileError(e.to_string()))?;
+    let data_key = SecretBytes::new(crypto::decrypt_with_aad(kek, &sealed, &slot.kind.aad())?);
+    SecretKey::copy_from(&data_key)
+        .ok_or_else(|| EnvkeepError::KeyFileError("wrapped key has the wrong length".to_string()))
+}
@@ -0,0 +1,213 @@
+// Per-owner keys: every project and shared secret encrypts its values with its
+// own random key, stored in the `keyring` table wrapped by the master key. The
+// vault's identity and signing keys for exports live there too.
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use ring::signature::Ed25519KeyPair;
+use x25519_dalek::StaticSecret;
+
+use crate::crypto::{self, signing, ValueBinding};
+use crate::crypto::key::generate_key;
+use crate::crypto::secret::{SecretBytes, SecretKey, SecretString};
+use crate::errors::EnvkeepError;
+
+/// The master key plus the owner keys unwrapped so far in this process.
+pub struct Keyring {
+    master: SecretKey,
+    keys: RefCell<HashMap<String, SecretKey>>,
+}
+
+impl Keyring {
+    /// `master` is the vault's value key (see `vault::get_encryption_key`).
+    pub fn new(master: SecretKey) -> Self {
+        Keyring {
+            master,
+            keys: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Encrypt a value with the key of the project or secret it is bound to,
+    /// creating that key on first use.
+    pub fn encrypt(&self, conn: &Connection, value: &str, binding: &ValueBinding) -> Result<String, EnvkeepError> {
+        let key = self.owner_key(conn, binding, true)?;
+        crypto::encrypt_value(&key, value, binding)
+    }
+
+    pub fn decrypt(&self, conn: &Connection, encrypted: &str, binding: &ValueBinding) -> Result<SecretString, EnvkeepError> {
+        let key = self.owner_key(conn, binding, false)?;
+        crypto::decrypt_value(&key, encrypted, binding)
+    }
+
+    /// Give `owner_id` a fresh key, returning the old one (None if it had none).
+    ///
+    /// Run this in the same transaction that re-encrypts the owner's values.
+    pub fn replace(
+        &self,
+        conn: &Connection,
+        kind: OwnerKind,
+        owner_id: &str,
+    ) -> Result<(Option<SecretKey>, SecretKey), EnvkeepError> {
+        let old = load(conn, &self.master, owner_id)?;
+        let new = generate_key()?;
+        store(conn, &self.master, kind, owner_id, &new)?;
+        self.keys.borrow_mut().insert(owner_id.to_string(), new.clone());
+        Ok((old, new))
+    }
+
+    /// The vault's X25519 identity for exports, created on first use.
+    pub fn identity(&self, conn: &Connection) -> Result<StaticSecret, EnvkeepError> {
+        let key = self.key(conn, OwnerKind::Identity, IDENTITY_OWNER, true)?;
+        Ok(StaticSecret::from(*key))
+    }
+
+    /// The vault's Ed25519 key for signing exports, created on first use.
+    pub fn signing_key(&self, conn: &Connection) -> Result<Ed25519KeyPair, EnvkeepError> {
+        let seed = self.key(conn, OwnerKind::Signing, SIGNING_OWNER, true)?;
+        signing::key_pair(&seed)
+    }
+
+    fn owner_key(&self, conn: &Connection, binding: &ValueBinding, create: bool) -> Result<SecretKey, EnvkeepError> {
+        let (kind, owner_id) = owner(binding);
+        self.key(conn, kind, owner_id, create)
+    }
+
+    fn key(&self, conn: &Connection, kind: OwnerKind, owner_id: &str, create: bool) -> Result<SecretKey, EnvkeepError> {
+        if let Some(key) = self.keys.borrow().get(owner_id) {
+            return Ok(key.clone());
+        }
+
+        let key = match load(conn, &self.master, owner_id)? {
+            Some(key) => key,
+            None if create => {
+                let key = generate_key()?;
+                store(conn, &self.master, kind, owner_id, &key)?;
+                key
+            }
+            None => return Err(EnvkeepError::MissingOwnerKey(owner_id.to_string())),
+        };
+
+        self.keys.borrow_mut().insert(owner_id.to_string(), key.clone());
+        Ok(key)
+    }
+}
+
+/// What a keyring entry belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OwnerKind {
+    Project,
+    Secret,
+    /// The vault's own identity key (one entry, see `Keyring::identity`)
+    Identity,
+    /// The vault's export signing key (one entry, see `Keyring::signing_key`)
+    Signing,
+}
+
+const IDENTITY_OWNER: &str = "identity";
+const SIGNING_OWNER: &str = "signing";
+
+impl OwnerKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OwnerKind::Project => "project",
+            OwnerKind::Secret => "secret",
+            OwnerKind::Identity => "identity",
+            OwnerKind::Signing => "signing",
+        }
+    }
+}
+
+fn owner<'a>(binding: &ValueBinding<'a>) -> (OwnerKind, &'a str) {
+    match binding {
+        ValueBinding::Variable { project_id, .. } => (OwnerKind::Project, project_id),
+        ValueBinding::Secret { secret_id, .. } => (OwnerKind::Secret, secret_id),
+    }
+}
+
+/// Wrapped keys are bound to their owner, so one cannot be swapped for another's.
+fn aad(kind: &str, owner_id: &str) -> Vec<u8> {
+    format!("envkeep/keyring\x1f{}\x1f{}", kind, owner_id).into_bytes()
+}
+
+fn load(conn: &Connection, master: &[u8; 32], owner_id: &str) -> Result<Option<SecretKey>, EnvkeepError> {
+    let row: Option<(String, String)> = conn
+        .query_row(
+            "SELECT owner_kind, wrapped FROM keyring WHERE owner_id = ?1",
+            [owner_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+
+    row.map(|(kind, wrapped)| unwrap(master, &kind, owner_id, &wrapped)).transpose()
+}
+
+fn store(
+    conn: &Connection,
+    master: &[u8; 32],
+    kind: OwnerKind,
+    owner_id: &str,
+    key: &[u8; 32],
+) -> Result<(), EnvkeepError> {
+    let wrapped = STANDARD.encode(crypto::encrypt_with_aad(master, key, &aad(kind.as_str(), owner_id))?);
+    conn.execute(
+        "INSERT INTO keyring (owner_id, owner_kind, wrapped, created_at) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT(owner_id) DO UPDATE SET
+           wrapped = excluded.wrapped,
+           created_at = excluded.created_at",
+        params![owner_id, kind.as_str(), wrapped, Utc::now().to_rfc3339()],
+    )?;
+    Ok(())
+}
+
+fn unwrap(master: &[u8; 32], kind: &str, owner_id: &str, wrapped: &str) -> Result<SecretKey, EnvkeepError> {
+    let sealed = STANDARD
+        .decode(wrapped)
+        .map_err(|e| EnvkeepError::DecryptionError(format!("keyring entry for {}: {}", owner_id, e)))?;
+    let key = SecretBytes::new(crypto::decrypt_with_aad(master, &sealed, &aad(kind, owner_id))?);
+    SecretKey::copy_from(&key)
+        .ok_or_else(|| EnvkeepError::DecryptionError(format!("keyring entry for {} has the wrong length", owner_id)))
+}
+
+/// Wrap every owner key again under a new master key. The values themselves
+/// do not change. Returns the number of keys rewrapped.
+pub fn rewrap_all(conn: &Connection, old_master: &[u8; 32], new_master: &[u8; 32]) -> Result<usize, EnvkeepError> {
+    let mut stmt = conn.prepare("SELECT owner_id, owner_kind, wrapped FROM keyring")?;
+    let rows = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for (owner_id, kind, wrapped) in &rows {
+        let key = unwrap(old_master, kind, owner_id, wrapped)?;
+        let rewrapped = STANDARD.encode(crypto::encrypt_with_aad(new_master, &key[..], &aad(kind, owner_id))?);
+        conn.execute(
+            "UPDATE keyring SET wrapped = ?1 WHERE owner_id = ?2",
+            params![rewrapped, owner_id],
+        )?;
+    }
+
+    Ok(rows.len())
+}
+
+/// Unwrap every owner key with the master key, to check none is damaged.
+/// Returns the number of keys checked.
+pub fn verify_all(conn: &Connection, master: &[u8; 32]) -> Result<usize, EnvkeepError> {
+    let mut stmt = conn.prepare("SELECT owner_id, owner_kind, wrapped FROM keyring")?;
+    let rows = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for (owner_id, kind, wrapped) in &rows {
+        unwrap(master, kind, owner_id, wrapped)?;
+    }
+
+    Ok(rows.len())
+}
+
+/// Forget the key of a deleted project or secret.
+pub fn delete_key(conn: &Connection, owner_id: &str) -> Result<(), EnvkeepError> {
+    conn.execute("DELETE FROM keyring WHERE owner_id = ?1", [owner_id])?;
+    Ok(())
+}
@@ -0,0 +1,533 @@
+use rusqlite::{Connection, Transaction};
+
+use crate::crypto;
+use crate::errors::EnvkeepError;
+use crate::vault::keyring::Keyring;
+use crate::vault::reencrypt;
+
+/// One step of the vault schema. Steps run in order, each in its own transaction.
+pub struct Migration {
+    /// Schema version the vault is at after this step.
+    pub version: u32,
+    pub description: &'static str,
+    up: fn(&Transaction, &Context) -> Result<(), EnvkeepError>,
+}
+
+/// What a step may need besides the database, eg to re-encrypt values.
+pub struct Context<'a> {
+    pub password: &'a str,
+}
+
+/// Every schema step ever shipped. Only ever append to this list.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create metadata, projects, variables and secrets tables",
+        up: v1_initial,
+    },
+    Migration {
+        version: 2,
+        description: "add environments to variables and a default environment to projects",
+        up: v2_environments,
+    },
+    Migration {
+        version: 3,
+        description: "keep the history of every variable change",
+        up: v3_history,
+    },
+    Migration {
+        version: 4,
+        description: "add the hash-chained audit log",
+        up: v4_audit,
+    },
+    Migration {
+        version: 5,
+        description: "keep the value history of shared secrets",
+        up: v5_secret_history,
+    },
+    Migration {
+        version: 6,
+        description: "remember how each shared secret is generated",
+        up: v6_secret_generators,
+    },
+    Migration {
+        version: 7,
+        description: "add rotation hooks to shared secrets",
+        up: v7_secret_hooks,
+    },
+    Migration {
+        version: 8,
+        description: "bind every encrypted value to its project or secret and key",
+        up: v8_bind_values,
+    },
+    Migration {
+        version: 9,
+        description: "store encrypted values in a versioned base64 envelope",
+        up: v9_value_envelopes,
+    },
+    Migration {
+        version: 10,
+        description: "give every project and shared secret its own data key",
+        up: v10_keyring,
+    },
+    Migration {
+        version: 11,
+        description: "keep the signing keys trusted for imports",
+        up: v11_trusted_signers,
+    },
+];
+
+/// The schema version this binary writes.
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+/// Read the schema version from metadata (0 for a brand new database).
+pub fn schema_version(conn: &Connection) -> u32 {
+    conn.query_row(
+        "SELECT value FROM metadata WHERE key = 'schema_version'",
+        [],
+        |row| row.get::<_, String>(0),
+    )
+    .ok()
+    .and_then(|v| v.parse().ok())
+    .unwrap_or(0)
+}
+
+/// The steps this vault still needs.
+///
+/// Fails when the vault was written by a newer envkeep than this one.
+pub fn pending(conn: &Connection) -> Result<Vec<&'static Migration>, EnvkeepError> {
+    let current = schema_version(conn);
+    let latest = latest_version();
+
+    if current > latest {
+        return Err(EnvkeepError::VaultTooNew(current, latest));
+    }
+
+    Ok(MIGRATIONS.iter().filter(|m| m.version > current).collect())
+}
+
+/// Apply the given steps in order. A failing step leaves the vault at the previous version.
+pub fn apply(conn: &mut Connection, steps: &[&Migration], ctx: &Context) -> Result<(), EnvkeepError> {
+    for step in steps {
+        let tx = conn.transaction()?;
+
+        (step.up)(&tx, ctx).map_err(|e| EnvkeepError::MigrationFailed(step.version, e.to_string()))?;
+
+        tx.execute(
+            "INSERT INTO metadata (key, value) VALUES ('schema_version', ?1)
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+            [step.version.to_string()],
+        )?;
+
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+/// v1: the original schema.
+fn v1_initial(tx: &Transaction, _ctx: &Context) -> Result<(), EnvkeepError> {
+    tx.execute_batch(
+        "
+        -- Metadata table for vault configuration
+        CREATE TABLE IF NOT EXISTS metadata (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );
+
+        -- Projects table
+        CREATE TABLE IF NOT EXISTS projects (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE,
+            directory TEXT,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            last_used_at TEXT
+        );
+
+        -- Variables table (encrypted values)
+        CREATE TABLE IF NOT EXISTS variables (
+            id TEXT PRIMARY KEY,
+            project_id TEXT NOT NULL,
+            key TEXT NOT NULL,
+            encrypted_value TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE,
+            UNIQUE(project_id, key)
+        );
+
+        -- Shared secrets table
+        CREATE TABLE IF NOT EXISTS secrets (
+            id TEXT PRIMARY KEY,
+            key TEXT NOT NULL UNIQUE,
+            encrypted_value TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        );
+
+        -- Secret-to-project links
+        CREATE TABLE IF NOT EXISTS secret_links (
+            secret_id TEXT NOT NULL,
+            project_id TEXT NOT NULL,
+            PRIMARY KEY (secret_id, project_id),
+            FOREIGN KEY (secret_id) REFERENCES secrets(id) ON DELETE CASCADE,
+            FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE
+        );
+        ",
+    )?;
+
+    Ok(())
+}
+
+/// v2: variables belong to a named environment inside their project, and
+/// every project has a default environment. Existing rows land in `dev`.
+fn v2_environments(tx: &Transaction, _ctx: &Context) -> Result<(), EnvkeepError> {
+    tx.execute_batch(
+        "
+        ALTER TABLE projects
+            ADD COLUMN default_environment TEXT NOT NULL DEFAULT 'dev';
+
+        -- SQLite cannot change a UNIQUE constraint in place, so rebuild the table
+        CREATE TABLE variables_v2 (
+            id TEXT PRIMARY KEY,
+            project_id TEXT NOT NULL,
+            environment TEXT NOT NULL DEFAULT 'dev',
+            key TEXT NOT NULL,
+            encrypted_value TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE,
+            UNIQUE(project_id, environment, key)
+        );
+
+        INSERT INTO variables_v2
+            (id, project_id, environment, key, encrypted_value, created_at, updated_at)
+        SELECT id, project_id, 'dev', key, encrypted_value, created_at, updated_at
+        FROM variables;
+
+        DROP TABLE variables;
+        ALTER TABLE variables_v2 RENAME TO variables;
+        ",
+    )?;
+
+    Ok(())
+}
+
+/// v3: every change to a variable keeps the previous and new ciphertext.
+fn v3_history(tx: &Transaction, _ctx: &Context) -> Result<(), EnvkeepError> {
+    tx.execute_batch(
+        "
+        -- One row per change. NULL old_value = key was added, NULL new_value = key was removed
+        CREATE TABLE variable_history (
+            revision INTEGER PRIMARY KEY AUTOINCREMENT,
+            project_id TEXT NOT NULL,
+            environment TEXT NOT NULL,
+            key TEXT NOT NULL,
+            old_value TEXT,
+            new_value TEXT,
+            command TEXT NOT NULL,
+            changed_at TEXT NOT NULL,
+            FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE
+        );
+
+        CREATE INDEX idx_variable_history_project
+            ON variable_history (project_id, environment, key);
+        ",
+    )?;
+
+    Ok(())
+}
+
+/// v4: append-only, hash-chained log of what each command did with the vault.
+fn v4_audit(tx: &Transaction, _ctx: &Context) -> Result<(), EnvkeepError> {
+    tx.execute_batch(
+        "
+        CREATE TABLE audit_log (
+            seq INTEGER PRIMARY KEY,
+            timestamp TEXT NOT NULL,
+            command TEXT NOT NULL,
+            action TEXT NOT NULL,
+            project TEXT,
+            keys TEXT NOT NULL,
+            prev_hash TEXT NOT NULL,
+            hash TEXT NOT NULL
+        );
+
+        -- Entries can only be appended
+        CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log
+        BEGIN
+            SELECT RAISE(ABORT, 'audit log is append-only');
+        END;
+
+        CREATE TRIGGER audit_log_no_delete BEFORE DELETE ON audit_log
+        BEGIN
+            SELECT RAISE(ABORT, 'audit log is append-only');
+        END;
+        ",
+    )?;
+
+    Ok(())
+}
+
+/// v5: every change to a shared secret keeps the previous and new ciphertext.
+fn v5_secret_history(tx: &Transaction, _ctx: &Context) -> Result<(), EnvkeepError> {
+    tx.execute_batch(
+        "
+        -- Same layout as variable_history; key is the secret's name at the time
+        CREATE TABLE secret_history (
+            revision INTEGER PRIMARY KEY AUTOINCREMENT,
+            secret_id TEXT NOT NULL,
+            key TEXT NOT NULL,
+            old_value TEXT,
+            new_value TEXT,
+            command TEXT NOT NULL,
+            changed_at TEXT NOT NULL
+        );
+
+        CREATE INDEX idx_secret_history_secret ON secret_history (secret_id);
+        ",
+    )?;
+
+    Ok(())
+}
+
+/// v6: JSON description of the generator `secrets rotate` uses for a secret.
+fn v6_secret_generators(tx: &Transaction, _ctx: &Context) -> Result<(), EnvkeepError> {
+    tx.execute_batch("ALTER TABLE secrets ADD COLUMN generator TEXT;")?;
+
+    Ok(())
+}
+
+/// v7: commands run after a secret is rotated.
+fn v7_secret_hooks(tx: &Transaction, _ctx: &Context) -> Result<(), EnvkeepError> {
+    tx.execute_batch(
+        "
+        CREATE TABLE secret_hooks (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            secret_id TEXT NOT NULL,
+            command TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            FOREIGN KEY (secret_id) REFERENCES secrets(id) ON DELETE CASCADE
+        );
+        ",
+    )?;
+
+    Ok(())
+}
+
+/// v8: re-encrypt every value with its row (project id, environment and key, or
+/// secret id and key) as associated data, in the `v2:` format.
+fn v8_bind_values(tx: &Transaction, ctx: &Context) -> Result<(), EnvkeepError> {
+    // A vault being created has no values and no key material yet
+    let has_salt: bool = tx.query_row(
+        "SELECT EXISTS (SELECT 1 FROM metadata WHERE key = 'salt')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_salt {
+        return Ok(());
+    }
+
+    let key = crate::vault::derive_value_key(tx, ctx.password)?;
+    reencrypt::rewrite_all(tx, |encrypted, binding| {
+        let value = crypto::decrypt_legacy_value(&key, encrypted)?;
+        crypto::encrypt_value(&key, &value, binding)
+    })?;
+
+    Ok(())
+}
+
+/// v9: store every value in the versioned `ek:` envelope, which names its
+/// format, cipher and key.
+fn v9_value_envelopes(tx: &Transaction, ctx: &Context) -> Result<(), EnvkeepError> {
+    // A vault being created has no values and no key material yet
+    let has_salt: bool = tx.query_row(
+        "SELECT EXISTS (SELECT 1 FROM metadata WHERE key = 'salt')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_salt {
+        return Ok(());
+    }
+
+    // The header is authenticated, so hex values are encrypted again rather than just re-encoded
+    let key = crate::vault::derive_value_key(tx, ctx.password)?;
+    reencrypt::rewrite_all(tx, |encrypted, binding| {
+        if crypto::is_current_format(encrypted) {
+            return Ok(encrypted.to_string());
+        }
+        let value = crypto::decrypt_value(&key, encrypted, binding)?;
+        crypto::encrypt_value(&key, &value, binding)
+    })?;
+
+    Ok(())
+}
+
+/// v10: a random key per project and per shared secret, wrapped by the master
+/// key, and every value re-encrypted with the key of its owner.
+fn v10_keyring(tx: &Transaction, ctx: &Context) -> Result<(), EnvkeepError> {
+    tx.execute_batch(
+        "
+        CREATE TABLE keyring (
+            owner_id TEXT PRIMARY KEY,
+            owner_kind TEXT NOT NULL,
+            wrapped TEXT NOT NULL,
+            created_at TEXT NOT NULL
+        );
+        ",
+    )?;
+
+    // A vault being created has nothing to re-encrypt
+    let has_values: bool = tx.query_row(
+        "SELECT EXISTS (SELECT 1 FROM variables) OR EXISTS (SELECT 1 FROM variable_history)
+             OR EXISTS (SELECT 1 FROM secrets) OR EXISTS (SELECT 1 FROM secret_history)",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_values {
+        return Ok(());
+    }
+
+    let master = crate::vault::get_encryption_key(tx, ctx.password)?;
+    let keyring = Keyring::new(master.clone());
+    reencrypt::rewrite_all(tx, |encrypted, binding| {
+        let value = crypto::decrypt_value(&master, encrypted, binding)?;
+        keyring.encrypt(tx, &value, binding)
+    })?;
+
+    Ok(())
+}
+
+/// v11: signing keys whose exports `import` accepts.
+fn v11_trusted_signers(tx: &Transaction, _ctx: &Context) -> Result<(), EnvkeepError> {
+    tx.execute_batch(
+        "
+        CREATE TABLE trusted_signers (
+            public_key TEXT PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE,
+            added_at TEXT NOT NULL
+        );
+        ",
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::key::{derive_key, generate_salt};
+
+    const PASSWORD: &str = "password1";
+
+    fn context() -> Context<'static> {
+        Context { password: PASSWORD }
+    }
+
+    fn table_exists(conn: &Connection, name: &str) -> bool {
+        conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = ?1)",
+            [name],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    /// A vault as the first release left it: v1 tables, a PBKDF2 salt and the
+    /// derived key itself as the verification value.
+    fn v1_vault() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        apply(&mut conn, &[&MIGRATIONS[0]], &context()).unwrap();
+
+        let salt = generate_salt().unwrap();
+        let key = derive_key(PASSWORD, &salt);
+        let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        conn.execute(
+            "INSERT INTO metadata (key, value) VALUES ('salt', ?1), ('verification_key', ?2)",
+            [hex(&salt), hex(&key[..])],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO projects (id, name, created_at, updated_at) VALUES ('p1', 'web', '', '')",
+            [],
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn versions_are_in_order() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, i as u32 + 1, "{}", migration.description);
+        }
+    }
+
+    #[test]
+    fn v1_vault_upgrades_to_latest() {
+        let mut conn = v1_vault();
+        assert_eq!(schema_version(&conn), 1);
+
+        let steps = pending(&conn).unwrap();
+        assert_eq!(steps.len(), MIGRATIONS.len() - 1);
+        apply(&mut conn, &steps, &context()).unwrap();
+
+        assert_eq!(schema_version(&conn), latest_version());
+        assert!(pending(&conn).unwrap().is_empty());
+        for table in ["variable_history", "audit_log", "secret_history", "secret_hooks", "keyring", "trusted_signers"] {
+            assert!(table_exists(&conn, table), "{}", table);
+        }
+        let environment: String = conn
+            .query_row("SELECT default_environment FROM projects WHERE id = 'p1'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(environment, "dev");
+    }
+
+    #[test]
+    fn v1_vault_with_wrong_password_stops_at_v7() {
+        let mut conn = v1_vault();
+        let steps = pending(&conn).unwrap();
+
+        match apply(&mut conn, &steps, &Context { password: "wrong" }) {
+            Err(EnvkeepError::MigrationFailed(8, _)) => {}
+            other => panic!("expected v8 to fail, got {:?}", other),
+        }
+        assert_eq!(schema_version(&conn), 7);
+    }
+
+    #[test]
+    fn newer_vault_is_refused() {
+        let conn = crate::vault::memory_vault();
+        let newer = latest_version() + 1;
+        conn.execute("UPDATE metadata SET value = ?1 WHERE key = 'schema_version'", [newer.to_string()])
+            .unwrap();
+
+        match pending(&conn) {
+            Err(EnvkeepError::VaultTooNew(found, latest)) => assert_eq!((found, latest), (newer, latest_version())),
+            other => panic!("expected VaultTooNew, got {:?}", other.map(|steps| steps.len())),
+        }
+    }
+
+    #[test]
+    fn failing_step_leaves_the_previous_version() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        apply(&mut conn, &[&MIGRATIONS[0], &MIGRATIONS[1]], &context()).unwrap();
+
+        // v3 creates variable_history, then fails on an index name that is taken
+        conn.execute_batch(
+            "CREATE TABLE clash (x); CREATE INDEX idx_variable_history_project ON clash (x);",
+        )
+        .unwrap();
+
+        let steps = pending(&conn).unwrap();
+        match apply(&mut conn, &steps, &context()) {
+            Err(EnvkeepError::MigrationFailed(3, _)) => {}
+            other => panic!("expected v3 to fail, got {:?}", other),
+        }
+        assert_eq!(schema_version(&conn), 2);
+        assert!(!table_exists(&conn, "variable_history"));
+        assert!(!table_exists(&conn, "audit_log"));
+    }
+}
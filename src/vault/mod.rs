@@ -1,12 +1,30 @@
 pub mod project;
 pub mod variable;
 pub mod secret;
-
-use rusqlite::Connection;
-use std::path::PathBuf;
-
+pub mod hook;
+pub mod history;
+pub mod audit;
+pub mod migrations;
+pub mod reencrypt;
+pub mod resolve;
+pub mod keyfile;
+pub mod keyring;
+pub mod trust;
+pub mod attempts;
+
+use rusqlite::{Connection, OptionalExtension};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::agent;
 use crate::errors::EnvkeepError;
-use crate::crypto::key::generate_salt;
+use crate::crypto::key::{
+    generate_key, subkey, verification_hash, verification_matches, verify_password, KdfParams,
+};
+use crate::crypto::recovery;
+use crate::crypto::secret::{SecretKey, SecretString};
+use keyfile::KeyFile;
 
 ///Get the path to envkeep data directory
 pub fn data_dir() -> PathBuf {
@@ -22,35 +40,252 @@ pub fn vault_path() -> PathBuf {
     path
 }
 
+/// Path of the key file next to the vault (`~/.envkeep/vault.keys`).
+pub fn keyfile_path() -> PathBuf {
+    keyfile::path_for(&vault_path())
+}
+
 ///Check if the vault exists.
 pub fn vault_exists() -> bool {
     vault_path().exists()
 }
 
+/// SQLCipher's default page size.
+const PAGE_SIZE: u64 = 4096;
+
+/// Data key of the unlocked vault, kept for the rest of the process so the
+/// password KDF runs once per command.
+static DATA_KEY: Mutex<Option<(SecretString, SecretKey)>> = Mutex::new(None);
+
+/// Vaults from before data keys have no key file; SQLCipher is keyed with the
+/// password itself. Opening one moves it to a data key.
+fn is_legacy() -> bool {
+    !keyfile_path().exists()
+}
+
 ///open the vault database with the master password
 ///
 /// This sets the SQLCipher encryption ket and returns a connection.
+/// Pending schema migrations are applied first, after backing up the vault.
 pub fn open_vault(password: &str) -> Result<Connection, EnvkeepError>{
+    let mut conn = open_vault_unmigrated(password)?;
+
+    let pending = migrations::pending(&conn)?;
+    if !pending.is_empty() {
+        let from = migrations::schema_version(&conn);
+        let backup = backup_vault(&format!("pre-v{}", migrations::latest_version()))?;
+        eprintln!(
+            "Upgrading vault schema from version {} to {} (backup: {})",
+            from,
+            migrations::latest_version(),
+            backup.display()
+        );
+        migrations::apply(&mut conn, &pending, &migrations::Context { password })?;
+    }
+
+    if is_legacy() {
+        conn = adopt_data_key(conn, password)?;
+    }
+
+    Ok(conn)
+}
+
+/// Open the vault without applying migrations (for `envkeep migrate`).
+///
+/// Still refuses vaults written by a newer envkeep.
+pub fn open_vault_unmigrated(password: &str) -> Result<Connection, EnvkeepError> {
     let path = vault_path();
 
     if !path.exists() {
         return Err(EnvkeepError::VaultNotFound);
     }
 
-    let conn = Connection::open(&path)?;
+    let conn = open_database(password)?;
+    check_metadata(&conn, is_legacy())?;
+    migrations::pending(&conn)?;
+
+    Ok(conn)
+}
+
+/// Decrypt the vault file without checking its contents (for `envkeep doctor`).
+pub fn open_database(password: &str) -> Result<Connection, EnvkeepError> {
+    let path = vault_path();
+    check_file(&path)?;
+
+    if is_legacy() {
+        attempts::guard(|| open_file(&path, password))
+    } else {
+        open_with_data_key(&path, &*data_key(password)?)
+    }
+}
+
+/// Open the vault with its data key directly (for `envkeep recover`).
+pub fn open_vault_with_data_key(data_key: &[u8; 32]) -> Result<Connection, EnvkeepError> {
+    let conn = open_with_data_key(&vault_path(), data_key)?;
+    check_metadata(&conn, false)?;
+    migrations::pending(&conn)?;
+    if !key_matches(&conn, &value_key(data_key))? {
+        return Err(EnvkeepError::KeyFileError("the key file does not belong to this vault".to_string()));
+    }
+    Ok(conn)
+}
+
+/// Open a vault backup, using the key file saved next to it if there is one.
+///
+/// Backups share the live vault's password, so guesses count against the
+/// same throttle and lockout.
+pub fn open_backup(path: &Path, password: &str) -> Result<Connection, EnvkeepError> {
+    let keys = keyfile::path_for(path);
+    if keys.exists() {
+        let keys = KeyFile::load(&keys)?;
+        let data_key = attempts::guard(|| keys.unlock_with_password(password))?;
+        open_file(path, &database_key(&data_key))
+    } else {
+        attempts::guard(|| open_file(path, password))
+    }
+}
+
+/// Open a SQLCipher file with a passphrase, or a raw key written as `x'...'`.
+fn open_file(path: &Path, key: &str) -> Result<Connection, EnvkeepError> {
+    let conn = Connection::open(path)?;
 
     //Set the sql cipher encryption key
-    conn.pragma_update(None, "key", &password)?;
+    conn.pragma_update(None, "key", key)?;
 
     //Test that the key is correct by querying the schema
-    conn.execute_batch("SELECT count(*) FROM sqlite_master;")
-        .map_err(|_| EnvkeepError::WrongPassword)?;
+    if conn.execute_batch("SELECT count(*) FROM sqlite_master;").is_err() {
+        // A damaged file fails the same way as a wrong key, so rule that out first
+        check_file(path)?;
+        return Err(EnvkeepError::WrongPassword);
+    }
 
     Ok(conn)
 }
 
+/// Open a SQLCipher file with a data key that was unwrapped successfully. If
+/// it does not decrypt, the password was right and the file is the problem.
+fn open_with_data_key(path: &Path, data_key: &[u8; 32]) -> Result<Connection, EnvkeepError> {
+    open_file(path, &database_key(data_key)).map_err(|e| match e {
+        EnvkeepError::WrongPassword => EnvkeepError::VaultCorrupted(
+            "it does not decrypt with the data key from its key file (damaged, or the key file belongs to another vault)"
+                .to_string(),
+        ),
+        e => e,
+    })
+}
+
+/// SQLCipher writes whole pages, so a vault is never empty, never a plain
+/// SQLite file and always a multiple of the page size.
+pub fn check_file(path: &Path) -> Result<(), EnvkeepError> {
+    let name = path.display().to_string();
+    let mut header = [0u8; 16];
+    let mut file = std::fs::File::open(path).map_err(|e| EnvkeepError::FileReadError(name.clone(), e))?;
+    let len = file.metadata().map_err(|e| EnvkeepError::FileReadError(name.clone(), e))?.len();
+
+    if len == 0 {
+        return Err(EnvkeepError::NotAVault(name, "the file is empty".to_string()));
+    }
+    if file.read_exact(&mut header).is_ok() && &header == b"SQLite format 3\0" {
+        return Err(EnvkeepError::NotAVault(name, "it is an unencrypted SQLite database".to_string()));
+    }
+    if len % PAGE_SIZE != 0 {
+        return Err(EnvkeepError::VaultCorrupted(format!(
+            "the file is {} bytes, not a whole number of {}-byte pages (truncated?)",
+            len, PAGE_SIZE
+        )));
+    }
+
+    Ok(())
+}
+
+/// Check that the entries every vault has are there. `legacy` vaults from
+/// before data keys also keep their password salt in `metadata`.
+pub fn check_metadata(conn: &Connection, legacy: bool) -> Result<(), EnvkeepError> {
+    let has_table: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'metadata')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_table {
+        return Err(EnvkeepError::MissingMetadata("its metadata table".to_string()));
+    }
+
+    let mut required = vec!["schema_version", "verification_key"];
+    if legacy {
+        required.push("salt");
+    }
+    for key in required {
+        let found: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM metadata WHERE key = ?1)",
+            [key],
+            |row| row.get(0),
+        )?;
+        if !found {
+            return Err(EnvkeepError::MissingMetadata(format!("the '{}' entry", key)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Check every page against its SQLCipher HMAC, then the SQLite structure.
+pub fn check_integrity(conn: &Connection) -> Result<(), EnvkeepError> {
+    // Returns one row per page that fails to authenticate
+    let mut stmt = conn.prepare("PRAGMA cipher_integrity_check")?;
+    let errors = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(first) = errors.first() {
+        return Err(EnvkeepError::VaultCorrupted(format!("{} page(s) failed authentication ({})", errors.len(), first)));
+    }
+
+    let result: String = conn.query_row("PRAGMA quick_check", [], |row| row.get(0))?;
+    if result != "ok" {
+        return Err(EnvkeepError::VaultCorrupted(result));
+    }
+
+    Ok(())
+}
+
+/// Copy the vault file (and its key file) to `~/.envkeep/backups/` before a risky change.
+///
+/// Returns the path of the copy.
+pub fn backup_vault(label: &str) -> Result<PathBuf, EnvkeepError> {
+    let dir = data_dir().join("backups");
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| EnvkeepError::FileWriteError(dir.display().to_string(), e))?;
+
+    let timestamp = chrono::Utc::now().format("%Y%m%d_%H%M%S");
+    let backup = dir.join(format!("vault-{}-{}.db", label, timestamp));
+
+    std::fs::copy(vault_path(), &backup)
+        .map_err(|e| EnvkeepError::FileWriteError(backup.display().to_string(), e))?;
+
+    if !is_legacy() {
+        let keys = keyfile::path_for(&backup);
+        std::fs::copy(keyfile_path(), &keys)
+            .map_err(|e| EnvkeepError::FileWriteError(keys.display().to_string(), e))?;
+    }
+
+    Ok(backup)
+}
+
+/// Delete a backup made by `backup_vault`, with its key file.
+pub fn remove_backup(backup: &Path) -> Result<(), EnvkeepError> {
+    let keys = keyfile::path_for(backup);
+    if keys.exists() {
+        std::fs::remove_file(&keys)
+            .map_err(|e| EnvkeepError::FileWriteError(keys.display().to_string(), e))?;
+    }
+    std::fs::remove_file(backup)
+        .map_err(|e| EnvkeepError::FileWriteError(backup.display().to_string(), e))
+}
+
 
 ///create a new vault database with the master password
+///
+/// The database and every value are encrypted under a random data key, which
+/// the key file holds wrapped with the password.
 pub fn create_vault(password: &str) -> Result<Connection, EnvkeepError> {
     let dir = data_dir();
     let path = vault_path();
@@ -65,109 +300,320 @@ pub fn create_vault(password: &str) -> Result<Connection, EnvkeepError> {
     std::fs::create_dir_all(&dir)
         .map_err(|e| EnvkeepError::FileWriteError(dir.display().to_string(), e))?;
 
-    let conn = Connection::open(&path)?;
+    let data_key = generate_key()?;
+    let mut keys = KeyFile::default();
+    keys.set_password(&data_key, password, &KdfParams::default())?;
+    keys.save(&keyfile_path())?;
 
-    //Set the sql cipher encryption key
-    conn.pragma_update(None, "key", &password)?;
+    let mut conn = open_file(&path, &database_key(&data_key))?;
 
     // Run Schema mirations
-    run_migrations(&conn)?;
+    let pending = migrations::pending(&conn)?;
+    migrations::apply(&mut conn, &pending, &migrations::Context { password })?;
 
-    let salt = generate_salt()?;
-    let salt_hex: String = salt.iter().map(|b| format!("{:02x}", b)).collect();
-    conn.execute(
-        "INSERT INTO metadata (key, value) VALUES ('salt', ?1)",
-        [&salt_hex],
-    )?;
+    // verification hash so a key file that does not match is caught
+    set_metadata(&conn, "verification_key", &verification_hash(&value_key(&data_key)[..]))?;
+    remember(password, &data_key);
+
+    Ok(conn)
+
+}
 
-    // verification hash so we can verify the password later
-    let verification_key = crate::crypto::key::derive_key(password, &salt);
-    let verification_hex: String = verification_key.iter().map(|b| format!("{:02x}", b)).collect();
+/// Insert or replace one metadata entry.
+pub fn set_metadata(conn: &Connection, key: &str, value: &str) -> Result<(), EnvkeepError> {
     conn.execute(
-        "INSERT INTO metadata (key, value) VALUES ('verification_key', ?1)",
-        [&verification_hex],
+        "INSERT INTO metadata (key, value) VALUES (?1, ?2)
+         ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        [key, value],
     )?;
+    Ok(())
+}
 
-    Ok(conn)
+/// An empty, unencrypted vault in memory at the latest schema, for tests.
+#[cfg(test)]
+pub(crate) fn memory_vault() -> Connection {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let pending = migrations::pending(&conn).unwrap();
+    migrations::apply(&mut conn, &pending, &migrations::Context { password: "" }).unwrap();
+    conn
+}
 
+/// The KDF parameters that turn the master password into the key that
+/// unwraps the data key.
+pub fn kdf_params() -> Result<KdfParams, EnvkeepError> {
+    KeyFile::load(&keyfile_path())?.kdf_params()
 }
 
-/// Get the encryption key by reading the salt from the database and deriving the key.
-pub fn get_encryption_key(conn: &Connection, password: &str) -> Result<[u8; 32], EnvkeepError> {
-    let salt_hex: String = conn.query_row(
-        "SELECT value FROM metadata WHERE key = 'salt'",
-        [],
-        |row| row.get(0),
-    )?;
+/// KDF parameters stored in `metadata` by vaults from before data keys.
+/// Vaults from before Argon2id have none stored and use PBKDF2.
+fn legacy_kdf_params(conn: &Connection) -> Result<KdfParams, EnvkeepError> {
+    let json: Option<String> = conn
+        .query_row("SELECT value FROM metadata WHERE key = 'kdf'", [], |row| row.get(0))
+        .optional()?;
+
+    match json {
+        Some(json) => serde_json::from_str(&json)
+            .map_err(|e| EnvkeepError::KeyDerivationError(format!("invalid kdf parameters: {}", e))),
+        None => Ok(KdfParams::legacy()),
+    }
+}
 
-    let salt: Vec<u8> = (0..salt_hex.len())
-        .step_by(2)
-        .map(|i| u8::from_str_radix(&salt_hex[i..i + 2], 16).unwrap())
-        .collect();
+/// The vault's data key, unwrapped from the key file with the master password
+/// (or handed over by the agent).
+pub fn data_key(password: &str) -> Result<SecretKey, EnvkeepError> {
+    if let Some((cached_password, key)) = DATA_KEY.lock().unwrap_or_else(|e| e.into_inner()).as_ref()
+        && cached_password.as_str() == password
+    {
+        return Ok(key.clone());
+    }
 
-    Ok(crate::crypto::key::derive_key(password, &salt))
+    let key = match agent::key_for(password) {
+        Some(key) => key,
+        None => {
+            let keys = KeyFile::load(&keyfile_path())?;
+            attempts::guard(|| keys.unlock_with_password(password))?
+        }
+    };
+    remember(password, &key);
+    Ok(key)
 }
 
+fn remember(password: &str, data_key: &[u8; 32]) {
+    *DATA_KEY.lock().unwrap_or_else(|e| e.into_inner()) = Some((SecretString::from(password), SecretKey::new(*data_key)));
+}
 
-///run all the database migrations
-fn run_migrations(conn: &Connection) -> Result<(), EnvkeepError> {
-    conn.execute_batch(
-        "
-        -- Metadata table for vault configuration
-        CREATE TABLE IF NOT EXISTS metadata (
-            key TEXT PRIMARY KEY,
-            value TEXT NOT NULL
-        );
+/// SQLCipher raw key for a data key, written as `x'...'`.
+fn database_key(data_key: &[u8; 32]) -> SecretString {
+    let key = subkey(data_key, "envkeep database key");
+    // Sized up front so the buffer never reallocates and leaves copies behind
+    let mut raw = String::with_capacity(3 + key.len() * 2);
+    raw.push_str("x'");
+    for byte in key.iter() {
+        raw.push(char::from_digit((byte >> 4) as u32, 16).unwrap_or('0'));
+        raw.push(char::from_digit((byte & 0xf) as u32, 16).unwrap_or('0'));
+    }
+    raw.push('\'');
+    SecretString::new(raw)
+}
 
-        -- Projects table
-        CREATE TABLE IF NOT EXISTS projects (
-            id TEXT PRIMARY KEY,
-            name TEXT NOT NULL UNIQUE,
-            directory TEXT,
-            created_at TEXT NOT NULL,
-            updated_at TEXT NOT NULL,
-            last_used_at TEXT
-        );
+/// Key the values in the vault are encrypted with.
+fn value_key(data_key: &[u8; 32]) -> SecretKey {
+    subkey(data_key, "envkeep value key")
+}
 
-        -- Variables table (encrypted values)
-        CREATE TABLE IF NOT EXISTS variables (
-            id TEXT PRIMARY KEY,
-            project_id TEXT NOT NULL,
-            key TEXT NOT NULL,
-            encrypted_value TEXT NOT NULL,
-            created_at TEXT NOT NULL,
-            updated_at TEXT NOT NULL,
-            FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE,
-            UNIQUE(project_id, key)
-        );
+/// Get the key the values in the vault are encrypted with.
+pub fn get_encryption_key(conn: &Connection, password: &str) -> Result<SecretKey, EnvkeepError> {
+    // Only reachable through `open_vault_unmigrated`
+    if is_legacy() {
+        return derive_value_key(conn, password);
+    }
 
-        -- Shared secrets table
-        CREATE TABLE IF NOT EXISTS secrets (
-            id TEXT PRIMARY KEY,
-            key TEXT NOT NULL UNIQUE,
-            encrypted_value TEXT NOT NULL,
-            created_at TEXT NOT NULL,
-            updated_at TEXT NOT NULL
-        );
+    let key = value_key(&*data_key(password)?);
+    if !key_matches(conn, &key)? {
+        return Err(EnvkeepError::KeyFileError("the key file does not belong to this vault".to_string()));
+    }
 
-        -- Secret-to-project links
-        CREATE TABLE IF NOT EXISTS secret_links (
-            secret_id TEXT NOT NULL,
-            project_id TEXT NOT NULL,
-            PRIMARY KEY (secret_id, project_id),
-            FOREIGN KEY (secret_id) REFERENCES secrets(id) ON DELETE CASCADE,
-            FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE
-        );
+    Ok(key)
+}
 
-        -- Store the key derivation salt
-        INSERT OR IGNORE INTO metadata (key, value)
-        VALUES ('schema_version', '1');
-        ",
-    )?;
+/// Derive the value key of a vault from before data keys with its salt and
+/// KDF parameters, and check it against the stored verification hash.
+pub fn derive_value_key(conn: &Connection, password: &str) -> Result<SecretKey, EnvkeepError> {
+    let salt_hex: String = conn
+        .query_row("SELECT value FROM metadata WHERE key = 'salt'", [], |row| row.get(0))
+        .optional()?
+        .ok_or_else(|| EnvkeepError::MissingMetadata("the 'salt' entry".to_string()))?;
+    let verification: String = conn
+        .query_row("SELECT value FROM metadata WHERE key = 'verification_key'", [], |row| row.get(0))
+        .optional()?
+        .ok_or_else(|| EnvkeepError::MissingMetadata("the 'verification_key' entry".to_string()))?;
+
+    let salt = crate::crypto::hex_decode(&salt_hex)
+        .map_err(|_| EnvkeepError::VaultCorrupted("the 'salt' entry is not valid hex".to_string()))?;
+
+    let params = legacy_kdf_params(conn)?;
+    verify_password(password, &salt, &params, &verification)?.ok_or(EnvkeepError::WrongPassword)
+}
+
+/// Check a value key against the stored verification hash (true if there is none).
+fn key_matches(conn: &Connection, key: &[u8; 32]) -> Result<bool, EnvkeepError> {
+    let verification: Option<String> = conn
+        .query_row("SELECT value FROM metadata WHERE key = 'verification_key'", [], |row| row.get(0))
+        .optional()?;
+    Ok(verification.is_none_or(|verification| verification_matches(key, &verification)))
+}
 
+/// Move a vault from before data keys to a random data key: rewrap the keyring,
+/// write the key file and rekey SQLCipher. On failure the backup taken
+/// first is put back.
+fn adopt_data_key(conn: Connection, password: &str) -> Result<Connection, EnvkeepError> {
+    let old_key = derive_value_key(&conn, password)?;
+    let params = match legacy_kdf_params(&conn)? {
+        params if params.is_legacy() => KdfParams::default(),
+        params => params,
+    };
+
+    let backup = backup_vault("pre-data-key")?;
+    eprintln!(
+        "Moving the vault to a random data key, unlocked with {} (backup: {})",
+        params.describe(),
+        backup.display()
+    );
+
+    let data_key = generate_key()?;
+    let new_key = value_key(&data_key);
+    let mut keys = KeyFile::default();
+    keys.set_password(&data_key, password, &params)?;
+
+    let result = (|| -> Result<(), EnvkeepError> {
+        let tx = conn.unchecked_transaction()?;
+        keyring::rewrap_all(&tx, &old_key, &new_key)?;
+        tx.execute("DELETE FROM metadata WHERE key IN ('salt', 'kdf')", [])?;
+        set_metadata(&tx, "verification_key", &verification_hash(&new_key[..]))?;
+        tx.commit()?;
+
+        keys.save(&keyfile_path())?;
+        conn.pragma_update(None, "rekey", database_key(&data_key).as_str())?;
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        drop(conn);
+        let _ = std::fs::remove_file(keyfile_path());
+        std::fs::copy(&backup, vault_path())
+            .map_err(|e| EnvkeepError::FileWriteError(vault_path().display().to_string(), e))?;
+        return Err(e);
+    }
+
+    remember(password, &data_key);
+    Ok(conn)
+}
+
+/// Re-wrap the data key under a new master password (same KDF parameters).
+///
+/// Only the key file changes; the database and its values stay as they are.
+pub fn set_password(data_key: &[u8; 32], new_password: &str) -> Result<(), EnvkeepError> {
+    let mut keys = KeyFile::load(&keyfile_path())?;
+    let params = keys.kdf_params()?;
+    keys.set_password(data_key, new_password, &params)?;
+    keys.save(&keyfile_path())?;
+    remember(new_password, data_key);
     Ok(())
 }
 
+/// Change the master password and give the vault a new data key: rewrap the
+/// keyring, write a new key file and rekey SQLCipher. The old password and an
+/// old copy of the key file no longer open it.
+///
+/// The recovery slot wraps the old key, so a vault that had a recovery key
+/// gets a new one, returned formatted for the user. Recovery shares of the old
+/// key stop working too. On failure `backup` is put back.
+pub fn rotate_data_key(
+    conn: Connection,
+    old_data_key: &[u8; 32],
+    new_password: &str,
+    backup: &Path,
+) -> Result<(Connection, Option<String>), EnvkeepError> {
+    let old_keys = KeyFile::load(&keyfile_path())?;
+    let data_key = generate_key()?;
+    let mut keys = KeyFile::default();
+    keys.set_password(&data_key, new_password, &old_keys.kdf_params()?)?;
+
+    let recovery_key = if old_keys.has_recovery() {
+        let recovery_key = generate_key()?;
+        keys.set_recovery(&data_key, &recovery_key)?;
+        Some(recovery::format(&recovery_key))
+    } else {
+        None
+    };
+
+    let old_key = value_key(old_data_key);
+    let new_key = value_key(&data_key);
+    let result = (|| -> Result<(), EnvkeepError> {
+        let tx = conn.unchecked_transaction()?;
+        keyring::rewrap_all(&tx, &old_key, &new_key)?;
+        set_metadata(&tx, "verification_key", &verification_hash(&new_key[..]))?;
+        tx.commit()?;
+
+        keys.save(&keyfile_path())?;
+        conn.pragma_update(None, "rekey", database_key(&data_key).as_str())?;
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        drop(conn);
+        std::fs::copy(backup, vault_path())
+            .map_err(|e| EnvkeepError::FileWriteError(vault_path().display().to_string(), e))?;
+        std::fs::copy(keyfile::path_for(backup), keyfile_path())
+            .map_err(|e| EnvkeepError::FileWriteError(keyfile_path().display().to_string(), e))?;
+        return Err(e);
+    }
+
+    remember(new_password, &data_key);
+    Ok((conn, recovery_key))
+}
+
+/// Re-wrap the data key under the same password with new KDF parameters.
+pub fn set_kdf(password: &str, params: &KdfParams) -> Result<(), EnvkeepError> {
+    let data_key = data_key(password)?;
+    let mut keys = KeyFile::load(&keyfile_path())?;
+    keys.set_password(&data_key, password, params)?;
+    keys.save(&keyfile_path())
+}
+
+/// Whether the vault has a recovery key.
+pub fn has_recovery_key() -> Result<bool, EnvkeepError> {
+    Ok(KeyFile::load(&keyfile_path())?.has_recovery())
+}
+
+/// Create a recovery key that unwraps the data key on its own, replacing any
+/// previous one. Returns it formatted for the user; it is not stored anywhere.
+pub fn new_recovery_key(data_key: &[u8; 32]) -> Result<String, EnvkeepError> {
+    let recovery_key = generate_key()?;
+    let mut keys = KeyFile::load(&keyfile_path())?;
+    keys.set_recovery(data_key, &recovery_key)?;
+    keys.save(&keyfile_path())?;
+    Ok(recovery::format(&recovery_key))
+}
+
+/// Note in the key file that the data key was split into recovery shares.
+pub fn mark_shares_split() -> Result<(), EnvkeepError> {
+    let mut keys = KeyFile::load(&keyfile_path())?;
+    keys.shares_split_at = Some(chrono::Utc::now().to_rfc3339());
+    keys.save(&keyfile_path())
+}
+
+/// Whether recovery shares of the current data key were handed out.
+pub fn has_recovery_shares() -> Result<bool, EnvkeepError> {
+    Ok(KeyFile::load(&keyfile_path())?.shares_split_at.is_some())
+}
+
+/// Unwrap the data key with a recovery key as typed by the user.
+pub fn data_key_from_recovery(recovery_key: &str) -> Result<SecretKey, EnvkeepError> {
+    if is_legacy() {
+        return Err(EnvkeepError::NoRecoveryKey);
+    }
+    let recovery_key = recovery::parse(recovery_key)?;
+    KeyFile::load(&keyfile_path())?.unlock_with_recovery(&recovery_key)
+}
 
 pub use project::Project;
-pub use variable::Variable;
\ No newline at end of file
+pub use variable::Variable;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vault_without_kdf_entry_uses_pbkdf2() {
+        let conn = memory_vault();
+        assert_eq!(legacy_kdf_params(&conn).unwrap(), KdfParams::legacy());
+
+        let params = KdfParams::argon2id(19 * 1024, 2);
+        set_metadata(&conn, "kdf", &serde_json::to_string(&params).unwrap()).unwrap();
+        assert_eq!(legacy_kdf_params(&conn).unwrap(), params);
+
+        set_metadata(&conn, "kdf", "{\"algorithm\":\"md5\"}").unwrap();
+        assert!(matches!(legacy_kdf_params(&conn), Err(EnvkeepError::KeyDerivationError(_))));
+    }
+}
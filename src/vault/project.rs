@@ -4,39 +4,87 @@ use uuid::Uuid;
 
 use crate::errors::EnvkeepError;
 
+/// Environment used when a project is created without `--env`.
+pub const DEFAULT_ENVIRONMENT: &str = "dev";
+
 #[derive(Debug, Clone)]
 pub struct Project {
     pub id: String,
     pub name: String,
     pub directory: Option<String>,
+    pub default_environment: String,
     pub created_at: String,
     pub updated_at: String,
     pub last_used_at: Option<String>,
 }
 
 impl Project {
-    pub fn new(name: &str, directory: Option<&str>) -> Self {
+    pub fn new(name: &str, directory: Option<&str>, default_environment: &str) -> Self {
         let now = Utc::now().to_rfc3339();
         Self {
             id: Uuid::new_v4().to_string(),
             name: name.to_string(),
             directory: directory.map(|d| d.to_string()),
+            default_environment: default_environment.to_string(),
             created_at: now.clone(),
             updated_at: now,
             last_used_at: None,
         }
     }
+
+    /// The requested environment, or this project's default one.
+    pub fn environment_or_default(&self, environment: Option<&str>) -> String {
+        environment
+            .unwrap_or(&self.default_environment)
+            .to_string()
+    }
+}
+
+/// Split a `project[:environment]` reference, e.g. `api:prod`.
+pub fn split_ref(spec: &str) -> (&str, Option<&str>) {
+    match spec.split_once(':') {
+        Some((name, env)) if !env.is_empty() => (name, Some(env)),
+        Some((name, _)) => (name, None),
+        None => (spec, None),
+    }
+}
+
+/// Check that a project or environment name is safe to use in `name:env` references.
+pub fn validate_name(name: &str) -> Result<(), EnvkeepError> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+
+    if !valid {
+        return Err(EnvkeepError::InvalidName(name.to_string()));
+    }
+    Ok(())
+}
+
+fn project_from_row(row: &rusqlite::Row) -> rusqlite::Result<Project> {
+    Ok(Project {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        directory: row.get(2)?,
+        default_environment: row.get(3)?,
+        created_at: row.get(4)?,
+        updated_at: row.get(5)?,
+        last_used_at: row.get(6)?,
+    })
 }
 
 /// Insert a new project into the vault.
 pub fn create_project(conn: &Connection, project: &Project) -> Result<(), EnvkeepError> {
     conn.execute(
-        "INSERT INTO projects (id, name, directory, created_at, updated_at, last_used_at)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        "INSERT INTO projects
+           (id, name, directory, default_environment, created_at, updated_at, last_used_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
         params![
             project.id,
             project.name,
             project.directory,
+            project.default_environment,
             project.created_at,
             project.updated_at,
             project.last_used_at,
@@ -55,51 +103,40 @@ pub fn create_project(conn: &Connection, project: &Project) -> Result<(), Envkee
 /// Get a project by name.
 pub fn get_project(conn: &Connection, name: &str) -> Result<Project, EnvkeepError> {
     conn.query_row(
-        "SELECT id, name, directory, created_at, updated_at, last_used_at
+        "SELECT id, name, directory, default_environment, created_at, updated_at, last_used_at
          FROM projects WHERE name = ?1",
         [name],
-        |row| {
-            Ok(Project {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                directory: row.get(2)?,
-                created_at: row.get(3)?,
-                updated_at: row.get(4)?,
-                last_used_at: row.get(5)?,
-            })
-        },
+        project_from_row,
     )
-    .map_err(|_| EnvkeepError::ProjectNotFound(name.to_string()))
+    .map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => EnvkeepError::ProjectNotFound(name.to_string()),
+        e => EnvkeepError::DatabaseError(e),
+    })
 }
 
 /// List all projects, ordered by last used (most recent first).
 pub fn list_projects(conn: &Connection) -> Result<Vec<Project>, EnvkeepError> {
     let mut stmt = conn.prepare(
-        "SELECT id, name, directory, created_at, updated_at, last_used_at
+        "SELECT id, name, directory, default_environment, created_at, updated_at, last_used_at
          FROM projects ORDER BY COALESCE(last_used_at, updated_at) DESC",
     )?;
 
     let projects = stmt
-        .query_map([], |row| {
-            Ok(Project {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                directory: row.get(2)?,
-                created_at: row.get(3)?,
-                updated_at: row.get(4)?,
-                last_used_at: row.get(5)?,
-            })
-        })?
+        .query_map([], project_from_row)?
         .collect::<Result<Vec<_>, _>>()?;
 
     Ok(projects)
 }
 
 /// Delete a project and all its variables (CASCADE).
+///
+/// Run this in a transaction, so a failure cannot leave half a project behind.
 pub fn delete_project(conn: &Connection, name: &str) -> Result<(), EnvkeepError> {
     let project = get_project(conn, name)?;
 
     conn.execute("DELETE FROM variables WHERE project_id = ?1", [&project.id])?;
+    crate::vault::history::delete_history(conn, &project.id)?;
+    crate::vault::keyring::delete_key(conn, &project.id)?;
     conn.execute("DELETE FROM secret_links WHERE project_id = ?1", [&project.id])?;
     conn.execute("DELETE FROM projects WHERE id = ?1", [&project.id])?;
 
@@ -130,3 +167,45 @@ pub fn count_variables(conn: &Connection, project_id: &str) -> Result<u32, Envke
     )?;
     Ok(count)
 }
+
+/// Change which environment `use`, `inspect` etc. pick when `--env` is not given.
+pub fn set_default_environment(
+    conn: &Connection,
+    name: &str,
+    environment: &str,
+) -> Result<(), EnvkeepError> {
+    let rows = conn.execute(
+        "UPDATE projects SET default_environment = ?1, updated_at = ?2 WHERE name = ?3",
+        params![environment, Utc::now().to_rfc3339(), name],
+    )?;
+
+    if rows == 0 {
+        return Err(EnvkeepError::ProjectNotFound(name.to_string()));
+    }
+
+    Ok(())
+}
+
+/// List the environments of a project with their variable counts.
+///
+/// The default environment is always included, even while it is empty.
+pub fn list_environments(
+    conn: &Connection,
+    project: &Project,
+) -> Result<Vec<(String, u32)>, EnvkeepError> {
+    let mut stmt = conn.prepare(
+        "SELECT environment, COUNT(*) FROM variables
+         WHERE project_id = ?1 GROUP BY environment ORDER BY environment",
+    )?;
+
+    let mut envs = stmt
+        .query_map([&project.id], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<(String, u32)>, _>>()?;
+
+    if !envs.iter().any(|(env, _)| env == &project.default_environment) {
+        envs.push((project.default_environment.clone(), 0));
+        envs.sort();
+    }
+
+    Ok(envs)
+}
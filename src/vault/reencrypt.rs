@@ -0,0 +1,97 @@
+use rusqlite::{params, Connection};
+
+use crate::crypto::{self, ValueBinding};
+use crate::errors::EnvkeepError;
+
+/// Every column holding an encrypted value, with the columns naming the row it
+/// is bound to: (table, value column, owner id column, environment column).
+const ENCRYPTED_COLUMNS: &[(&str, &str, &str, Option<&str>)] = &[
+    ("variables", "encrypted_value", "project_id", Some("environment")),
+    ("variable_history", "old_value", "project_id", Some("environment")),
+    ("variable_history", "new_value", "project_id", Some("environment")),
+    ("secrets", "encrypted_value", "id", None),
+    ("secret_history", "old_value", "secret_id", None),
+    ("secret_history", "new_value", "secret_id", None),
+];
+
+/// Replace every stored value with `rewrite(value, binding)`.
+///
+/// Run this inside a transaction; an error aborts the whole change.
+/// Returns the number of values rewritten.
+pub fn rewrite_all<F>(conn: &Connection, rewrite: F) -> Result<usize, EnvkeepError>
+where
+    F: FnMut(&str, &ValueBinding) -> Result<String, EnvkeepError>,
+{
+    rewrite_where(conn, None, rewrite)
+}
+
+/// Like `rewrite_all`, for the values of one project or secret only.
+pub fn rewrite_owner<F>(conn: &Connection, owner_id: &str, rewrite: F) -> Result<usize, EnvkeepError>
+where
+    F: FnMut(&str, &ValueBinding) -> Result<String, EnvkeepError>,
+{
+    rewrite_where(conn, Some(owner_id), rewrite)
+}
+
+fn rewrite_where<F>(conn: &Connection, owner_id: Option<&str>, mut rewrite: F) -> Result<usize, EnvkeepError>
+where
+    F: FnMut(&str, &ValueBinding) -> Result<String, EnvkeepError>,
+{
+    let mut count = 0;
+
+    for (table, column, owner, environment) in ENCRYPTED_COLUMNS {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT rowid, {column}, {owner}, {}, key FROM {table}
+             WHERE {column} IS NOT NULL AND (?1 IS NULL OR {owner} = ?1)",
+            environment.unwrap_or("NULL")
+        ))?;
+        let rows = stmt
+            .query_map([owner_id], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, String>(4)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (rowid, encrypted, owner_id, environment, key) in rows {
+            let binding = match &environment {
+                Some(environment) => ValueBinding::Variable {
+                    project_id: &owner_id,
+                    environment,
+                    key: &key,
+                },
+                None => ValueBinding::Secret {
+                    secret_id: &owner_id,
+                    key: &key,
+                },
+            };
+
+            let rewritten = rewrite(&encrypted, &binding)?;
+            conn.execute(
+                &format!("UPDATE {table} SET {column} = ?1 WHERE rowid = ?2"),
+                params![rewritten, rowid],
+            )?;
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+/// Decrypt the values of one project or secret with `old_key` and encrypt
+/// them again with `new_key`.
+pub fn reencrypt_owner(
+    conn: &Connection,
+    owner_id: &str,
+    old_key: &[u8; 32],
+    new_key: &[u8; 32],
+) -> Result<usize, EnvkeepError> {
+    rewrite_owner(conn, owner_id, |encrypted, binding| {
+        let value = crypto::decrypt_value(old_key, encrypted, binding)?;
+        crypto::encrypt_value(new_key, &value, binding)
+    })
+}
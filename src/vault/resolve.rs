@@ -0,0 +1,97 @@
+use rusqlite::Connection;
+use std::collections::BTreeMap;
+
+use crate::crypto::ValueBinding;
+use crate::errors::EnvkeepError;
+use crate::vault::project::Project;
+
+/// Where a resolved value comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// A variable stored in the project environment
+    Variable,
+    /// A shared secret linked to the project
+    Secret,
+}
+
+impl Source {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Source::Variable => "project",
+            Source::Secret => "shared secret",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ResolvedValue {
+    pub encrypted_value: String,
+    pub source: Source,
+    /// Project id for a variable, secret id for a secret.
+    pub owner_id: String,
+}
+
+impl ResolvedValue {
+    /// What this value is encrypted for, given its key and the resolved environment.
+    pub fn binding<'a>(&'a self, key: &'a str, environment: &'a str) -> ValueBinding<'a> {
+        match self.source {
+            Source::Variable => ValueBinding::Variable {
+                project_id: &self.owner_id,
+                environment,
+                key,
+            },
+            Source::Secret => ValueBinding::Secret {
+                secret_id: &self.owner_id,
+                key,
+            },
+        }
+    }
+}
+
+/// Everything a project environment sees, still encrypted.
+#[derive(Debug, Default)]
+pub struct Resolved {
+    pub values: BTreeMap<String, ResolvedValue>,
+    /// Keys set both in the project and by a linked secret.
+    pub conflicts: Vec<String>,
+}
+
+/// Merge the variables of one environment with the secrets linked to the project.
+///
+/// A project variable always wins over a linked secret with the same key, so a
+/// project can override a shared value without unlinking it.
+pub fn resolve(
+    conn: &Connection,
+    project: &Project,
+    environment: &str,
+) -> Result<Resolved, EnvkeepError> {
+    let mut resolved = Resolved::default();
+
+    for var in crate::vault::variable::get_variables(conn, &project.id, environment)? {
+        resolved.values.insert(
+            var.key,
+            ResolvedValue {
+                encrypted_value: var.encrypted_value,
+                source: Source::Variable,
+                owner_id: var.project_id,
+            },
+        );
+    }
+
+    for secret in crate::vault::secret::get_linked_secrets(conn, &project.id)? {
+        if resolved.values.contains_key(&secret.key) {
+            resolved.conflicts.push(secret.key);
+            continue;
+        }
+        resolved.values.insert(
+            secret.key,
+            ResolvedValue {
+                encrypted_value: secret.encrypted_value,
+                source: Source::Secret,
+                owner_id: secret.id,
+            },
+        );
+    }
+
+    Ok(resolved)
+}
@@ -1,7 +1,9 @@
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, OptionalExtension};
 use chrono::Utc;
 use uuid::Uuid;
 
+use crate::crypto::ValueBinding;
+use crate::crypto::generator::Generator;
 use crate::errors::EnvkeepError;
 
 #[derive(Debug, Clone)]
@@ -11,15 +13,83 @@ pub struct Secret {
     pub encrypted_value: String,
     pub created_at: String,
     pub updated_at: String,
+    /// JSON of the generator `secrets rotate` uses, if one was chosen.
+    pub generator: Option<String>,
 }
 
+impl Secret {
+    /// What this value is encrypted for (see `crypto::ValueBinding`).
+    pub fn binding(&self) -> ValueBinding<'_> {
+        ValueBinding::Secret {
+            secret_id: &self.id,
+            key: &self.key,
+        }
+    }
+
+    /// The generator to rotate this secret with (the default one if none was stored).
+    pub fn generator(&self) -> Generator {
+        self.generator
+            .as_deref()
+            .and_then(|json| serde_json::from_str(json).ok())
+            .unwrap_or_default()
+    }
+}
+
+fn secret_from_row(row: &rusqlite::Row) -> rusqlite::Result<Secret> {
+    Ok(Secret {
+        id: row.get(0)?,
+        key: row.get(1)?,
+        encrypted_value: row.get(2)?,
+        created_at: row.get(3)?,
+        updated_at: row.get(4)?,
+        generator: row.get(5)?,
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct SecretHistoryEntry {
+    pub revision: i64,
+    pub key: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub command: String,
+    pub changed_at: String,
+}
+
+impl SecretHistoryEntry {
+    /// Short description of what this change did to the secret.
+    pub fn change(&self) -> &'static str {
+        match (&self.old_value, &self.new_value) {
+            (None, Some(_)) => "added",
+            (Some(_), None) => "removed",
+            (Some(old), Some(new)) if old == new => "renamed",
+            _ => "changed",
+        }
+    }
+}
+
+/// The id of the secret stored under `key`, or a fresh one for a new secret.
+///
+/// Values are encrypted for their secret's id, so it is needed before `create_secret`.
+pub fn id_for_key(conn: &Connection, key: &str) -> Result<String, EnvkeepError> {
+    let id: Option<String> = conn
+        .query_row("SELECT id FROM secrets WHERE key = ?1", [key], |row| row.get(0))
+        .optional()?;
+    Ok(id.unwrap_or_else(|| Uuid::new_v4().to_string()))
+}
+
+/// Store a secret, replacing the value if the key already exists.
+///
+/// `id` comes from `id_for_key`. `command` is recorded in the secret's history.
 pub fn create_secret(
     conn: &Connection,
+    id: &str,
     key: &str,
     encrypted_value: &str,
+    command: &str,
 ) -> Result<Secret, EnvkeepError> {
     let now = Utc::now().to_rfc3339();
-    let id = Uuid::new_v4().to_string();
+    let previous = get_secret(conn, key).ok();
 
     conn.execute(
         "INSERT INTO secrets (id, key, encrypted_value, created_at, updated_at)
@@ -30,43 +100,145 @@ pub fn create_secret(
         params![id, key, encrypted_value, now],
     )?;
 
-    get_secret(conn, key)
+    let secret = get_secret(conn, key)?;
+    record_change(
+        conn,
+        &secret,
+        previous.as_ref().map(|p| p.encrypted_value.as_str()),
+        Some(encrypted_value),
+        command,
+    )?;
+
+    Ok(secret)
+}
+
+/// Remember the generator later rotations of this secret should use.
+pub fn set_generator(
+    conn: &Connection,
+    key: &str,
+    generator: &Generator,
+) -> Result<(), EnvkeepError> {
+    let json = serde_json::to_string(generator)
+        .map_err(|e| EnvkeepError::InvalidGenerator(e.to_string()))?;
+    conn.execute("UPDATE secrets SET generator = ?1 WHERE key = ?2", params![json, key])?;
+    Ok(())
+}
+
+/// Give a secret a new name. Links and history follow the secret.
+///
+/// The value is bound to the name, so `encrypted_value` must already be
+/// encrypted for the new name.
+pub fn rename_secret(
+    conn: &Connection,
+    old_key: &str,
+    new_key: &str,
+    encrypted_value: &str,
+) -> Result<Secret, EnvkeepError> {
+    let secret = get_secret(conn, old_key)?;
+    if get_secret(conn, new_key).is_ok() {
+        return Err(EnvkeepError::SecretAlreadyExists(new_key.to_string()));
+    }
+
+    conn.execute(
+        "UPDATE secrets SET key = ?1, encrypted_value = ?2, updated_at = ?3 WHERE id = ?4",
+        params![new_key, encrypted_value, Utc::now().to_rfc3339(), secret.id],
+    )?;
+
+    let renamed = get_secret(conn, new_key)?;
+    record_change(
+        conn,
+        &renamed,
+        Some(encrypted_value),
+        Some(encrypted_value),
+        "secrets rename",
+    )?;
+
+    Ok(renamed)
+}
+
+/// Delete a secret together with its links, history and hooks.
+///
+/// Run this in a transaction, so a failure cannot leave links or history behind.
+pub fn delete_secret(conn: &Connection, key: &str) -> Result<(), EnvkeepError> {
+    let secret = get_secret(conn, key)?;
+
+    conn.execute("DELETE FROM secret_links WHERE secret_id = ?1", [&secret.id])?;
+    conn.execute("DELETE FROM secret_history WHERE secret_id = ?1", [&secret.id])?;
+    crate::vault::hook::delete_hooks(conn, &secret.id)?;
+    crate::vault::keyring::delete_key(conn, &secret.id)?;
+    conn.execute("DELETE FROM secrets WHERE id = ?1", [&secret.id])?;
+
+    Ok(())
+}
+
+fn record_change(
+    conn: &Connection,
+    secret: &Secret,
+    old_value: Option<&str>,
+    new_value: Option<&str>,
+    command: &str,
+) -> Result<(), EnvkeepError> {
+    conn.execute(
+        "INSERT INTO secret_history (secret_id, key, old_value, new_value, command, changed_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            secret.id,
+            secret.key,
+            old_value,
+            new_value,
+            command,
+            Utc::now().to_rfc3339(),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Get the history of a secret, oldest first.
+pub fn get_secret_history(
+    conn: &Connection,
+    secret_id: &str,
+) -> Result<Vec<SecretHistoryEntry>, EnvkeepError> {
+    let mut stmt = conn.prepare(
+        "SELECT revision, key, old_value, new_value, command, changed_at
+         FROM secret_history
+         WHERE secret_id = ?1
+         ORDER BY revision",
+    )?;
+
+    let entries = stmt
+        .query_map([secret_id], |row| {
+            Ok(SecretHistoryEntry {
+                revision: row.get(0)?,
+                key: row.get(1)?,
+                old_value: row.get(2)?,
+                new_value: row.get(3)?,
+                command: row.get(4)?,
+                changed_at: row.get(5)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(entries)
 }
 
 pub fn get_secret(conn: &Connection, key: &str) -> Result<Secret, EnvkeepError> {
     conn.query_row(
-        "SELECT id, key, encrypted_value, created_at, updated_at
+        "SELECT id, key, encrypted_value, created_at, updated_at, generator
          FROM secrets WHERE key = ?1",
         [key],
-        |row| {
-            Ok(Secret {
-                id: row.get(0)?,
-                key: row.get(1)?,
-                encrypted_value: row.get(2)?,
-                created_at: row.get(3)?,
-                updated_at: row.get(4)?,
-            })
-        },
+        secret_from_row,
     )
     .map_err(|_| EnvkeepError::SecretNotFound(key.to_string()))
 }
 
 pub fn list_secrets(conn: &Connection) -> Result<Vec<Secret>, EnvkeepError> {
     let mut stmt = conn.prepare(
-        "SELECT id, key, encrypted_value, created_at, updated_at
+        "SELECT id, key, encrypted_value, created_at, updated_at, generator
          FROM secrets ORDER BY key",
     )?;
 
     let secrets = stmt
-        .query_map([], |row| {
-            Ok(Secret {
-                id: row.get(0)?,
-                key: row.get(1)?,
-                encrypted_value: row.get(2)?,
-                created_at: row.get(3)?,
-                updated_at: row.get(4)?,
-            })
-        })?
+        .query_map([], secret_from_row)?
         .collect::<Result<Vec<_>, _>>()?;
 
     Ok(secrets)
@@ -123,4 +295,24 @@ pub fn get_linked_projects(
         .collect::<Result<Vec<String>, _>>()?;
 
     Ok(names)
-}
\ No newline at end of file
+}
+
+/// Get the secrets linked to a project, ordered by key.
+pub fn get_linked_secrets(
+    conn: &Connection,
+    project_id: &str,
+) -> Result<Vec<Secret>, EnvkeepError> {
+    let mut stmt = conn.prepare(
+        "SELECT s.id, s.key, s.encrypted_value, s.created_at, s.updated_at, s.generator
+         FROM secrets s
+         JOIN secret_links sl ON s.id = sl.secret_id
+         WHERE sl.project_id = ?1
+         ORDER BY s.key",
+    )?;
+
+    let secrets = stmt
+        .query_map([project_id], secret_from_row)?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(secrets)
+}
@@ -0,0 +1,116 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use chrono::Utc;
+
+use crate::errors::EnvkeepError;
+
+/// A signing key whose exports `import` accepts.
+#[derive(Debug, Clone)]
+pub struct TrustedSigner {
+    /// As printed by `envkeep identity show --signing`
+    pub public_key: String,
+    pub name: String,
+    pub added_at: String,
+}
+
+/// Trust a signing key under a name. Adding a known key again renames it.
+pub fn add_signer(conn: &Connection, public_key: &str, name: &str) -> Result<(), EnvkeepError> {
+    conn.execute(
+        "INSERT INTO trusted_signers (public_key, name, added_at) VALUES (?1, ?2, ?3)
+         ON CONFLICT(public_key) DO UPDATE SET name = excluded.name",
+        params![public_key, name, Utc::now().to_rfc3339()],
+    )?;
+    Ok(())
+}
+
+pub fn list_signers(conn: &Connection) -> Result<Vec<TrustedSigner>, EnvkeepError> {
+    let mut stmt = conn.prepare(
+        "SELECT public_key, name, added_at FROM trusted_signers ORDER BY name",
+    )?;
+
+    let signers = stmt
+        .query_map([], |row| {
+            Ok(TrustedSigner {
+                public_key: row.get(0)?,
+                name: row.get(1)?,
+                added_at: row.get(2)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(signers)
+}
+
+/// The trusted signer with this key, if any.
+pub fn find_signer(conn: &Connection, public_key: &str) -> Result<Option<TrustedSigner>, EnvkeepError> {
+    let signer = conn
+        .query_row(
+            "SELECT public_key, name, added_at FROM trusted_signers WHERE public_key = ?1",
+            [public_key],
+            |row| {
+                Ok(TrustedSigner {
+                    public_key: row.get(0)?,
+                    name: row.get(1)?,
+                    added_at: row.get(2)?,
+                })
+            },
+        )
+        .optional()?;
+    Ok(signer)
+}
+
+/// Stop trusting a signer, given its name or key. Returns false if none matched.
+pub fn remove_signer(conn: &Connection, name_or_key: &str) -> Result<bool, EnvkeepError> {
+    let removed = conn.execute(
+        "DELETE FROM trusted_signers WHERE name = ?1 OR public_key = ?1",
+        [name_or_key],
+    )?;
+    Ok(removed > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::signing;
+
+    fn signing_key(seed: u8) -> String {
+        signing::format_key(&signing::public_key(&signing::key_pair(&[seed; 32]).unwrap()))
+    }
+
+    #[test]
+    fn valid_signature_from_an_untrusted_signer_is_not_trusted() {
+        let conn = crate::vault::memory_vault();
+        add_signer(&conn, &signing_key(1), "alice").unwrap();
+
+        // Verifying says who signed, not whether to trust them
+        let mallory = signing::key_pair(&[2u8; 32]).unwrap();
+        let signature = signing::sign(&mallory, b"export");
+        assert!(signing::verify(&signing::public_key(&mallory), b"export", &signature).is_ok());
+
+        assert!(find_signer(&conn, &signing_key(2)).unwrap().is_none());
+        assert_eq!(find_signer(&conn, &signing_key(1)).unwrap().unwrap().name, "alice");
+    }
+
+    #[test]
+    fn adding_again_renames() {
+        let conn = crate::vault::memory_vault();
+        add_signer(&conn, &signing_key(1), "alice").unwrap();
+        add_signer(&conn, &signing_key(1), "alice-laptop").unwrap();
+
+        let signers = list_signers(&conn).unwrap();
+        assert_eq!(signers.len(), 1);
+        assert_eq!(signers[0].name, "alice-laptop");
+    }
+
+    #[test]
+    fn removed_signer_is_untrusted() {
+        let conn = crate::vault::memory_vault();
+        add_signer(&conn, &signing_key(1), "alice").unwrap();
+        add_signer(&conn, &signing_key(2), "bob").unwrap();
+
+        assert!(remove_signer(&conn, "alice").unwrap());
+        assert!(remove_signer(&conn, &signing_key(2)).unwrap());
+        assert!(!remove_signer(&conn, "alice").unwrap());
+        assert!(find_signer(&conn, &signing_key(1)).unwrap().is_none());
+        assert!(list_signers(&conn).unwrap().is_empty());
+    }
+}
@@ -1,120 +1,196 @@
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, OptionalExtension};
 use chrono::Utc;
 use uuid::Uuid;
 
+use crate::crypto::ValueBinding;
 use crate::errors::EnvkeepError;
+use crate::vault::history;
 
 #[derive(Debug, Clone)]
 pub struct Variable {
     pub id: String,
     pub project_id: String,
+    pub environment: String,
     pub key: String,
     pub encrypted_value: String,
     pub created_at: String,
     pub updated_at: String,
 }
 
-/// Insert or update a variable for a project.
+impl Variable {
+    /// What this value is encrypted for (see `crypto::ValueBinding`).
+    pub fn binding(&self) -> ValueBinding<'_> {
+        ValueBinding::Variable {
+            project_id: &self.project_id,
+            environment: &self.environment,
+            key: &self.key,
+        }
+    }
+}
+
+fn variable_from_row(row: &rusqlite::Row) -> rusqlite::Result<Variable> {
+    Ok(Variable {
+        id: row.get(0)?,
+        project_id: row.get(1)?,
+        environment: row.get(2)?,
+        key: row.get(3)?,
+        encrypted_value: row.get(4)?,
+        created_at: row.get(5)?,
+        updated_at: row.get(6)?,
+    })
+}
+
+/// Insert or update a variable in one environment of a project.
+///
+/// The previous value is kept in the history, tagged with `command`.
 pub fn upsert_variable(
     conn: &Connection,
     project_id: &str,
+    environment: &str,
     key: &str,
     encrypted_value: &str,
+    command: &str,
 ) -> Result<(), EnvkeepError> {
     let now = Utc::now().to_rfc3339();
     let id = Uuid::new_v4().to_string();
 
+    let previous = current_value(conn, project_id, environment, key)?;
+    if previous.as_deref() == Some(encrypted_value) {
+        return Ok(());
+    }
+
     conn.execute(
-        "INSERT INTO variables (id, project_id, key, encrypted_value, created_at, updated_at)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?5)
-         ON CONFLICT(project_id, key) DO UPDATE SET
+        "INSERT INTO variables
+           (id, project_id, environment, key, encrypted_value, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)
+         ON CONFLICT(project_id, environment, key) DO UPDATE SET
            encrypted_value = excluded.encrypted_value,
            updated_at = excluded.updated_at",
-        params![id, project_id, key, encrypted_value, now],
+        params![id, project_id, environment, key, encrypted_value, now],
+    )?;
+
+    history::record_change(
+        conn,
+        project_id,
+        environment,
+        key,
+        previous.as_deref(),
+        Some(encrypted_value),
+        command,
     )?;
 
     Ok(())
 }
 
-/// Get all variables for a project.
+/// The encrypted value a key currently has, if it exists.
+fn current_value(
+    conn: &Connection,
+    project_id: &str,
+    environment: &str,
+    key: &str,
+) -> Result<Option<String>, EnvkeepError> {
+    let value = conn
+        .query_row(
+            "SELECT encrypted_value FROM variables
+             WHERE project_id = ?1 AND environment = ?2 AND key = ?3",
+            params![project_id, environment, key],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(value)
+}
+
+/// Get all variables in one environment of a project.
 pub fn get_variables(
     conn: &Connection,
     project_id: &str,
+    environment: &str,
 ) -> Result<Vec<Variable>, EnvkeepError> {
     let mut stmt = conn.prepare(
-        "SELECT id, project_id, key, encrypted_value, created_at, updated_at
-         FROM variables WHERE project_id = ?1 ORDER BY key",
+        "SELECT id, project_id, environment, key, encrypted_value, created_at, updated_at
+         FROM variables WHERE project_id = ?1 AND environment = ?2 ORDER BY key",
     )?;
 
     let vars = stmt
-        .query_map([project_id], |row| {
-            Ok(Variable {
-                id: row.get(0)?,
-                project_id: row.get(1)?,
-                key: row.get(2)?,
-                encrypted_value: row.get(3)?,
-                created_at: row.get(4)?,
-                updated_at: row.get(5)?,
-            })
-        })?
+        .query_map(params![project_id, environment], variable_from_row)?
         .collect::<Result<Vec<_>, _>>()?;
 
     Ok(vars)
 }
 
-/// Get a single variable by project ID and key name.
+/// Get a single variable by project ID, environment and key name.
 pub fn get_variable(
     conn: &Connection,
     project_id: &str,
+    environment: &str,
     key: &str,
 ) -> Result<Variable, EnvkeepError> {
     conn.query_row(
-        "SELECT id, project_id, key, encrypted_value, created_at, updated_at
-         FROM variables WHERE project_id = ?1 AND key = ?2",
-        params![project_id, key],
-        |row| {
-            Ok(Variable {
-                id: row.get(0)?,
-                project_id: row.get(1)?,
-                key: row.get(2)?,
-                encrypted_value: row.get(3)?,
-                created_at: row.get(4)?,
-                updated_at: row.get(5)?,
-            })
-        },
+        "SELECT id, project_id, environment, key, encrypted_value, created_at, updated_at
+         FROM variables WHERE project_id = ?1 AND environment = ?2 AND key = ?3",
+        params![project_id, environment, key],
+        variable_from_row,
     )
-    .map_err(|_| EnvkeepError::SecretNotFound(format!("{}:{}", project_id, key)))
+    .map_err(|_| EnvkeepError::VariableNotFound(environment.to_string(), key.to_string()))
 }
 
-/// Delete a variable.
+/// Delete a variable. The removed value is kept in the history, tagged with `command`.
 pub fn delete_variable(
     conn: &Connection,
     project_id: &str,
+    environment: &str,
     key: &str,
+    command: &str,
 ) -> Result<(), EnvkeepError> {
+    let Some(previous) = current_value(conn, project_id, environment, key)? else {
+        return Ok(());
+    };
+
     conn.execute(
-        "DELETE FROM variables WHERE project_id = ?1 AND key = ?2",
-        params![project_id, key],
+        "DELETE FROM variables WHERE project_id = ?1 AND environment = ?2 AND key = ?3",
+        params![project_id, environment, key],
     )?;
+
+    history::record_change(conn, project_id, environment, key, Some(&previous), None, command)?;
     Ok(())
 }
 
-/// Search for a key across all projects. Returns (project_name, encrypted_value).
+/// Search for a key across all projects and environments.
+/// Returns the variables with the name of their project.
 pub fn search_key(
     conn: &Connection,
     key: &str,
-) -> Result<Vec<(String, String)>, EnvkeepError> {
+) -> Result<Vec<(String, Variable)>, EnvkeepError> {
     let mut stmt = conn.prepare(
-        "SELECT p.name, v.encrypted_value
+        "SELECT v.id, v.project_id, v.environment, v.key, v.encrypted_value,
+                v.created_at, v.updated_at, p.name
          FROM variables v
          JOIN projects p ON v.project_id = p.id
          WHERE v.key = ?1
-         ORDER BY p.name",
+         ORDER BY p.name, v.environment",
     )?;
 
     let results = stmt
-        .query_map([key], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .query_map([key], |row| Ok((row.get(7)?, variable_from_row(row)?)))?
         .collect::<Result<Vec<_>, _>>()?;
 
     Ok(results)
-}
\ No newline at end of file
+}
+
+/// Get every variable in the vault with the name of its project, ordered by
+/// project, environment and key.
+pub fn list_all_variables(conn: &Connection) -> Result<Vec<(String, Variable)>, EnvkeepError> {
+    let mut stmt = conn.prepare(
+        "SELECT v.id, v.project_id, v.environment, v.key, v.encrypted_value,
+                v.created_at, v.updated_at, p.name
+         FROM variables v
+         JOIN projects p ON v.project_id = p.id
+         ORDER BY p.name, v.environment, v.key",
+    )?;
+
+    let results = stmt
+        .query_map([], |row| Ok((row.get(7)?, variable_from_row(row)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(results)
+}